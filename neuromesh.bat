@@ -8,7 +8,6 @@ if %errorlevel% neq 0 (
     echo Adding firewall rules...
     netsh advfirewall firewall add rule name="NeuroMesh TCP" dir=in action=allow protocol=TCP localport=7878 >nul 2>&1
     netsh advfirewall firewall add rule name="NeuroMesh UDP" dir=in action=allow protocol=UDP localport=5000 >nul 2>&1
-    netsh advfirewall firewall add rule name="Ollama" dir=in action=allow protocol=TCP localport=11434 >nul 2>&1
     echo Firewall configured!
 ) else (
     echo Firewall already configured.
@@ -32,9 +31,7 @@ if %errorlevel% == 0 (
 )
 
 echo.
-echo 5. Starting Ollama with external access...
-set OLLAMA_HOST=0.0.0.0
-set OLLAMA_ORIGINS=*
+echo 5. Starting Ollama...
 echo Initializing Neural Processing Engine...
 start /B ollama serve
 
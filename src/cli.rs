@@ -0,0 +1,276 @@
+use std::io::Write;
+use clap::{Args, Parser, Subcommand};
+use futures::StreamExt;
+use reqwest::{Client, RequestBuilder, Response};
+use crate::config::ConfigArgs;
+use crate::llm::{ChatChunk, ChatRequest, GenerationOptions, ModelInfo, OutputFormat};
+use crate::tcp::{AccessRequest, PeerConnectionStatus};
+
+// Without a subcommand the node is started, as it always was
+#[derive(Parser)]
+#[command(name = "neuromesh", version, about = "Peer-to-peer LLM sharing over the local network")]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub serve: ServeArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the node: peer discovery, mesh sessions, web interface and HTTP API
+    Serve(Box<ServeArgs>),
+    /// List known peers and the state of the connection to each
+    Peers(ClientArgs),
+    /// Send a prompt through the mesh and stream the answer
+    Chat {
+        /// The prompt; multiple words are joined with spaces
+        #[arg(required = true, num_args = 1..)]
+        prompt: Vec<String>,
+
+        /// Name recorded as the sender in the conversation
+        #[arg(long)]
+        sender: Option<String>,
+
+        /// Answer from uploaded documents and list the sources used
+        #[arg(long)]
+        documents: bool,
+
+        /// Sampling temperature, 0 to 2; 0 gives the same answer every time
+        #[arg(long)]
+        temperature: Option<f32>,
+
+        /// Seed for reproducible sampling
+        #[arg(long)]
+        seed: Option<i64>,
+
+        /// Most tokens to generate
+        #[arg(long)]
+        max_tokens: Option<u32>,
+
+        /// Make the model answer in JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Generate a fresh answer even if a cached one exists
+        #[arg(long)]
+        no_cache: bool,
+
+        #[command(flatten)]
+        client: ClientArgs,
+    },
+    /// List models on the local Ollama and on peers that granted access
+    Models(ClientArgs),
+    /// Review LLM access requests from peers
+    Access {
+        #[command(subcommand)]
+        action: AccessAction,
+
+        #[command(flatten)]
+        client: ClientArgs,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AccessAction {
+    /// Show requests waiting for a decision
+    List,
+    /// Grant a peer access to this node's LLM
+    Approve { node_id: String },
+    /// Refuse a peer's request
+    Deny { node_id: String },
+}
+
+#[derive(Args, Clone, Default)]
+pub struct ServeArgs {
+    /// Do not open the web interface in a browser
+    #[arg(long, env = "NEUROMESH_NO_BROWSER")]
+    pub no_browser: bool,
+
+    #[command(flatten)]
+    pub config: ConfigArgs,
+}
+
+#[derive(Args, Clone)]
+pub struct ClientArgs {
+    /// Base URL of the running node's HTTP API
+    #[arg(long, env = "NEUROMESH_API", default_value = "http://127.0.0.1:8080", global = true)]
+    pub api: String,
+
+    /// Operator token, for nodes on other machines
+    #[arg(long, env = "NEUROMESH_ADMIN_TOKEN", hide_env_values = true, global = true)]
+    pub admin_token: Option<String>,
+}
+
+impl ClientArgs {
+    fn url(&self, path: &str) -> String {
+        format!("{}/api{}", self.api.trim_end_matches('/'), path)
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.admin_token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
+
+// Turns a non-2xx response into the daemon's error message
+async fn check(response: Response) -> Result<Response, String> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let body: serde_json::Value = response.json().await.unwrap_or_default();
+    let error = body["error"].as_str().unwrap_or("request failed");
+    match body["details"].as_str() {
+        Some(details) => Err(format!("{} ({}): {}", error, status, details)),
+        None => Err(format!("{} ({})", error, status)),
+    }
+}
+
+async fn get<T: serde::de::DeserializeOwned>(client: &ClientArgs, path: &str) -> Result<T, String> {
+    let response = client.authorize(Client::new().get(client.url(path)))
+        .send()
+        .await
+        .map_err(|e| format!("Cannot reach NeuroMesh at {}: {}", client.api, e))?;
+    check(response).await?
+        .json()
+        .await
+        .map_err(|e| format!("Invalid response from {}: {}", client.api, e))
+}
+
+async fn peers(client: &ClientArgs) -> Result<(), String> {
+    let statuses: Vec<PeerConnectionStatus> = get(client, "/connections").await?;
+    if statuses.is_empty() {
+        println!("No peers known yet");
+        return Ok(());
+    }
+    println!("{:<24} {:<12} {:<9} {:<9} LAST ERROR", "ADDRESS", "STATE", "ATTEMPTS", "RTT");
+    for status in statuses {
+        println!(
+            "{:<24} {:<12} {:<9} {:<9} {}",
+            status.address,
+            format!("{:?}", status.state),
+            status.attempts,
+            status.rtt_ms.map(|ms| format!("{:.1}ms", ms)).unwrap_or_default(),
+            status.last_error.unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+async fn chat(client: &ClientArgs, prompt: String, sender: Option<String>, use_documents: bool, options: GenerationOptions, no_cache: bool) -> Result<(), String> {
+    let sender = sender.unwrap_or_else(|| {
+        hostname::get()
+            .map(|h| h.to_string_lossy().to_string())
+            .unwrap_or_else(|_| "cli".to_string())
+    });
+    let response = Client::new().post(client.url("/chat/stream"))
+        .json(&ChatRequest { message: prompt, sender, use_documents, options, no_cache })
+        .send()
+        .await
+        .map_err(|e| format!("Cannot reach NeuroMesh at {}: {}", client.api, e))?;
+    let mut stream = check(response).await?.bytes_stream();
+
+    let mut stdout = std::io::stdout();
+    let mut buffer = Vec::new();
+    while let Some(bytes) = stream.next().await {
+        let bytes = bytes.map_err(|e| format!("Stream interrupted: {}", e))?;
+        buffer.extend_from_slice(&bytes);
+        while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=pos).collect();
+            let chunk: ChatChunk = match serde_json::from_slice(&line) {
+                Ok(chunk) => chunk,
+                Err(_) => continue,
+            };
+            if let Some(error) = chunk.error {
+                println!();
+                return Err(error);
+            }
+            if let Some(failover) = chunk.failover {
+                eprintln!("\n[{} failed, continuing on {}]", failover.from, failover.to);
+            }
+            print!("{}", chunk.content);
+            let _ = stdout.flush();
+            if chunk.done {
+                println!();
+                if !chunk.citations.is_empty() {
+                    println!("\nSources:");
+                    for (i, citation) in chunk.citations.iter().enumerate() {
+                        println!("  [{}] {} (chunk {})", i + 1, citation.document_name, citation.chunk);
+                    }
+                }
+                return Ok(());
+            }
+        }
+    }
+    println!();
+    Err("Stream ended before the answer was complete".to_string())
+}
+
+async fn models(client: &ClientArgs) -> Result<(), String> {
+    let models: Vec<ModelInfo> = get(client, "/models").await?;
+    if models.is_empty() {
+        println!("No models available");
+        return Ok(());
+    }
+    println!("{:<32} HOST", "MODEL");
+    for model in models {
+        println!("{:<32} {}", model.name, model.host);
+    }
+    Ok(())
+}
+
+async fn access(client: &ClientArgs, action: AccessAction) -> Result<(), String> {
+    let (node_id, verdict) = match action {
+        AccessAction::List => {
+            let requests: Vec<AccessRequest> = get(client, "/access").await?;
+            if requests.is_empty() {
+                println!("No pending access requests");
+                return Ok(());
+            }
+            println!("{:<34} {:<16} {:<20} REASON", "NODE", "IP", "NAME");
+            for request in requests {
+                println!("{:<34} {:<16} {:<20} {}", request.node_id, request.ip, request.peer_name, request.reason);
+            }
+            return Ok(());
+        }
+        AccessAction::Approve { node_id } => (node_id, "approve"),
+        AccessAction::Deny { node_id } => (node_id, "deny"),
+    };
+
+    let response = client.authorize(Client::new().post(client.url(&format!("/access/{}/{}", node_id, verdict))))
+        .send()
+        .await
+        .map_err(|e| format!("Cannot reach NeuroMesh at {}: {}", client.api, e))?;
+    let request: AccessRequest = check(response).await?
+        .json()
+        .await
+        .map_err(|e| format!("Invalid response from {}: {}", client.api, e))?;
+    let verb = if verdict == "approve" { "Approved" } else { "Denied" };
+    println!("{} access for {} ({})", verb, request.peer_name, request.ip);
+    Ok(())
+}
+
+// Runs a client subcommand against a node that is already serving
+pub async fn run(command: Command) -> Result<(), String> {
+    match command {
+        Command::Serve(_) => unreachable!("serve runs the node from main"),
+        Command::Peers(client) => peers(&client).await,
+        Command::Chat { prompt, sender, documents, temperature, seed, max_tokens, json, no_cache, client } => {
+            let options = GenerationOptions {
+                temperature,
+                seed,
+                max_tokens,
+                format: json.then_some(OutputFormat::Json),
+                ..Default::default()
+            };
+            chat(&client, prompt.join(" "), sender, documents, options, no_cache).await
+        }
+        Command::Models(client) => models(&client).await,
+        Command::Access { action, client } => access(&client, action).await,
+    }
+}
@@ -0,0 +1,705 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use clap::{Args, ValueEnum};
+
+const DEFAULT_CONFIG_FILE: &str = "neuromesh.toml";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub http: HttpConfig,
+    pub tcp: TcpConfig,
+    pub udp: UdpConfig,
+    pub ollama: OllamaConfig,
+    pub openai: OpenAiConfig,
+    pub llm: LlmConfig,
+    pub storage: StorageConfig,
+    pub documents: DocumentsConfig,
+    pub sharing: SharingConfig,
+    pub peers: PeersConfig,
+    pub access: AccessConfig,
+    pub limits: LimitsConfig,
+    pub cache: CacheConfig,
+    pub shutdown: ShutdownConfig,
+    pub tasks: TasksConfig,
+    pub logging: LoggingConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub bind: String,
+    pub port: u16,
+    // Lets operator routes be used from other machines; never served back through /api/config
+    #[serde(skip_serializing)]
+    pub admin_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TcpConfig {
+    pub port: u16,
+    pub sync_interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UdpConfig {
+    pub enabled: bool,
+    pub broadcast_port: u16,
+    pub broadcast_interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OllamaConfig {
+    pub url: String,
+}
+
+// An OpenAI-compatible server such as llama.cpp's or vLLM's; `url` is the server root,
+// without the /v1 suffix
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenAiConfig {
+    pub url: String,
+    // Never served back through /api/config
+    #[serde(skip_serializing)]
+    pub api_key: Option<String>,
+}
+
+// Which kind of server runs our models; peers are told along with its address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    Ollama,
+    #[value(name = "openai")]
+    OpenAi,
+}
+
+impl BackendKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackendKind::Ollama => "ollama",
+            BackendKind::OpenAi => "openai",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<BackendKind> {
+        match value {
+            "ollama" => Some(BackendKind::Ollama),
+            "openai" => Some(BackendKind::OpenAi),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LlmConfig {
+    pub backend: BackendKind,
+    pub model: String,
+    pub remote_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub conversations_dir: PathBuf,
+    pub received_dir: PathBuf,
+    // Uploaded documents and their vector index
+    pub documents_dir: PathBuf,
+    // Files shared with peers, and downloads in progress
+    pub shared_dir: PathBuf,
+    // Disk space the conversations received from any one peer may take up
+    pub peer_quota_mb: u64,
+}
+
+// Retrieval over uploaded documents
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DocumentsConfig {
+    // Embeds document chunks and questions; may live on this node or any peer
+    pub embedding_model: String,
+    // Chunk length and the overlap between neighbouring chunks, in characters
+    pub chunk_chars: usize,
+    pub chunk_overlap: usize,
+    // Chunks added to the prompt when a chat request asks for documents
+    pub top_k: usize,
+    pub max_upload_mb: u64,
+}
+
+// Files shared across the mesh by content hash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SharingConfig {
+    // Largest file offered here or fetched from a peer
+    pub max_file_mb: u64,
+    // Disk space files fetched from any one peer may take up
+    pub peer_quota_mb: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeersConfig {
+    // Peers to dial without waiting for UDP discovery, as "host" or "host:port"
+    pub seeds: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessConfig {
+    // Grant LLM access to every peer that asks; otherwise requests wait for approval
+    pub auto_approve: bool,
+}
+
+// What each peer may ask of our LLM server. Zero means no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeerLimits {
+    pub requests_per_minute: u32,
+    pub max_concurrent: u32,
+    // Generated tokens, per UTC day
+    pub tokens_per_day: u64,
+    // Larger context windows and longer answers than these are cut down to them
+    pub max_num_ctx: u32,
+    pub max_tokens: u32,
+    // Longest a peer may keep a model loaded after its request; unlike the others, zero
+    // leaves it to the server and ignores what the peer asked for
+    pub max_keep_alive_secs: u64,
+}
+
+// Limits for every peer, unless `peers` has an entry for its IP address, which then
+// replaces them entirely
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub requests_per_minute: u32,
+    pub max_concurrent: u32,
+    pub tokens_per_day: u64,
+    pub max_num_ctx: u32,
+    pub max_tokens: u32,
+    pub max_keep_alive_secs: u64,
+    pub peers: HashMap<String, PeerLimits>,
+}
+
+impl LimitsConfig {
+    pub fn for_peer(&self, ip: &str) -> PeerLimits {
+        self.peers.get(ip).copied().unwrap_or(PeerLimits {
+            requests_per_minute: self.requests_per_minute,
+            max_concurrent: self.max_concurrent,
+            tokens_per_day: self.tokens_per_day,
+            max_num_ctx: self.max_num_ctx,
+            max_tokens: self.max_tokens,
+            max_keep_alive_secs: self.max_keep_alive_secs,
+        })
+    }
+}
+
+// Answers kept for identical chat requests. Only requests asking for temperature 0
+// are cached, since only those should get the same answer again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    pub ttl_secs: u64,
+    pub max_entries: usize,
+    // Answers together, in megabytes
+    pub max_mb: u64,
+    // Node IDs of peers whose requests may be answered from the cache, and whose
+    // answers are kept in it
+    pub share_with: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    // Upper bound for saying goodbye to peers, flushing stores and draining HTTP requests
+    pub timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TasksConfig {
+    // Failures in a row after which a background task is left stopped; 0 = no limit
+    pub max_restarts: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    // One JSON object per line, for log shippers
+    Json,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    // A level, optionally followed by per-module ones: "info,neuromesh::tcp=debug"
+    pub level: String,
+    pub format: LogFormat,
+    // Include prompts, answers and conversation contents in debug events
+    pub log_content: bool,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            bind: "0.0.0.0".to_string(),
+            port: 8080,
+            admin_token: None,
+        }
+    }
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        TcpConfig {
+            port: 7878,
+            sync_interval_secs: 30,
+        }
+    }
+}
+
+impl Default for UdpConfig {
+    fn default() -> Self {
+        UdpConfig {
+            enabled: true,
+            broadcast_port: 5000,
+            broadcast_interval_secs: 30,
+        }
+    }
+}
+
+impl Default for OllamaConfig {
+    fn default() -> Self {
+        OllamaConfig {
+            url: "http://127.0.0.1:11434".to_string(),
+        }
+    }
+}
+
+impl Default for OpenAiConfig {
+    fn default() -> Self {
+        OpenAiConfig {
+            url: "http://127.0.0.1:8000".to_string(),
+            api_key: None,
+        }
+    }
+}
+
+impl Default for LlmConfig {
+    fn default() -> Self {
+        LlmConfig {
+            backend: BackendKind::Ollama,
+            model: "phi3-fast".to_string(),
+            remote_timeout_secs: 60,
+        }
+    }
+}
+
+impl Default for AccessConfig {
+    fn default() -> Self {
+        AccessConfig { auto_approve: true }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            enabled: false,
+            ttl_secs: 3600,
+            max_entries: 256,
+            max_mb: 16,
+            share_with: Vec::new(),
+        }
+    }
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig { timeout_secs: 10 }
+    }
+}
+
+impl Default for TasksConfig {
+    fn default() -> Self {
+        TasksConfig { max_restarts: 10 }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: "info".to_string(),
+            format: LogFormat::Text,
+            log_content: false,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            conversations_dir: PathBuf::from("conversations"),
+            received_dir: PathBuf::from("received"),
+            documents_dir: PathBuf::from("documents"),
+            shared_dir: PathBuf::from("shared"),
+            peer_quota_mb: 64,
+        }
+    }
+}
+
+impl Default for DocumentsConfig {
+    fn default() -> Self {
+        DocumentsConfig {
+            embedding_model: "nomic-embed-text".to_string(),
+            chunk_chars: 1000,
+            chunk_overlap: 200,
+            top_k: 4,
+            max_upload_mb: 20,
+        }
+    }
+}
+
+impl Default for SharingConfig {
+    fn default() -> Self {
+        SharingConfig {
+            max_file_mb: 1024,
+            peer_quota_mb: 4096,
+        }
+    }
+}
+
+// Command-line flags that override the configuration file. Each flag can also be
+// given through the environment variable named next to it; a flag wins over the
+// variable, and either wins over the file.
+#[derive(Args, Debug, Clone, Default)]
+pub struct ConfigArgs {
+    /// Path to a TOML configuration file (defaults to ./neuromesh.toml if present)
+    #[arg(long, env = "NEUROMESH_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address the HTTP server binds to
+    #[arg(long, env = "NEUROMESH_HTTP_BIND")]
+    pub http_bind: Option<String>,
+
+    /// Port for the web interface and HTTP API
+    #[arg(long, env = "NEUROMESH_HTTP_PORT")]
+    pub http_port: Option<u16>,
+
+    /// Bearer token for operator routes used from other machines
+    #[arg(long, env = "NEUROMESH_ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<String>,
+
+    /// Port for peer-to-peer TCP sessions
+    #[arg(long, env = "NEUROMESH_TCP_PORT")]
+    pub tcp_port: Option<u16>,
+
+    /// Port for UDP discovery broadcasts
+    #[arg(long, env = "NEUROMESH_BROADCAST_PORT")]
+    pub broadcast_port: Option<u16>,
+
+    /// Disable UDP discovery and rely on --peer seeds only
+    #[arg(long, env = "NEUROMESH_NO_DISCOVERY")]
+    pub no_discovery: bool,
+
+    /// Base URL of the local Ollama server
+    #[arg(long, env = "NEUROMESH_OLLAMA_URL")]
+    pub ollama_url: Option<String>,
+
+    /// Kind of LLM server to use: ollama or openai (OpenAI-compatible, e.g. llama.cpp or vLLM)
+    #[arg(long, env = "NEUROMESH_BACKEND")]
+    pub backend: Option<BackendKind>,
+
+    /// Base URL of the local OpenAI-compatible server, without /v1
+    #[arg(long, env = "NEUROMESH_OPENAI_URL")]
+    pub openai_url: Option<String>,
+
+    /// API key sent to the OpenAI-compatible server
+    #[arg(long, env = "NEUROMESH_OPENAI_API_KEY", hide_env_values = true)]
+    pub openai_api_key: Option<String>,
+
+    /// Model used for chat requests
+    #[arg(long, env = "NEUROMESH_MODEL")]
+    pub model: Option<String>,
+
+    /// Seconds between conversation syncs with each peer
+    #[arg(long, env = "NEUROMESH_SYNC_INTERVAL")]
+    pub sync_interval: Option<u64>,
+
+    /// Seconds to wait for a remote peer's LLM before giving up
+    #[arg(long, env = "NEUROMESH_REMOTE_TIMEOUT")]
+    pub remote_timeout: Option<u64>,
+
+    /// Directory for this node's conversation and identity
+    #[arg(long, env = "NEUROMESH_CONVERSATIONS_DIR")]
+    pub conversations_dir: Option<PathBuf>,
+
+    /// Directory for conversations received from peers
+    #[arg(long, env = "NEUROMESH_RECEIVED_DIR")]
+    pub received_dir: Option<PathBuf>,
+
+    /// Directory for uploaded documents and their vector index
+    #[arg(long, env = "NEUROMESH_DOCUMENTS_DIR")]
+    pub documents_dir: Option<PathBuf>,
+
+    /// Directory for files shared with peers
+    #[arg(long, env = "NEUROMESH_SHARED_DIR")]
+    pub shared_dir: Option<PathBuf>,
+
+    /// Model used to embed documents and questions about them
+    #[arg(long, env = "NEUROMESH_EMBEDDING_MODEL")]
+    pub embedding_model: Option<String>,
+
+    /// Hold LLM access requests until approved with `neuromesh access approve`
+    #[arg(long, env = "NEUROMESH_MANUAL_APPROVAL")]
+    pub manual_approval: bool,
+
+    /// Seconds to wait for a clean shutdown before exiting anyway
+    #[arg(long, env = "NEUROMESH_SHUTDOWN_TIMEOUT")]
+    pub shutdown_timeout: Option<u64>,
+
+    /// Log level, optionally per module, e.g. "info,neuromesh::tcp=debug"
+    #[arg(long, env = "NEUROMESH_LOG")]
+    pub log_level: Option<String>,
+
+    /// Log output format: text or json
+    #[arg(long, env = "NEUROMESH_LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+
+    /// Include prompts, answers and conversation contents in debug logs
+    #[arg(long, env = "NEUROMESH_LOG_CONTENT")]
+    pub log_content: bool,
+
+    /// Peer to dial at startup, as host or host:port (repeatable)
+    #[arg(long = "peer", env = "NEUROMESH_PEERS", value_delimiter = ',')]
+    pub peers: Vec<String>,
+}
+
+impl Config {
+    pub fn load(args: &ConfigArgs) -> Result<Config, String> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?,
+            None => Config::default(),
+        };
+        config.apply_overrides(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Config, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
+        toml::from_str(&content)
+            .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))
+    }
+
+    fn apply_overrides(&mut self, args: &ConfigArgs) {
+        if let Some(bind) = &args.http_bind {
+            self.http.bind = bind.clone();
+        }
+        if let Some(port) = args.http_port {
+            self.http.port = port;
+        }
+        if let Some(token) = &args.admin_token {
+            self.http.admin_token = Some(token.clone());
+        }
+        if let Some(port) = args.tcp_port {
+            self.tcp.port = port;
+        }
+        if let Some(port) = args.broadcast_port {
+            self.udp.broadcast_port = port;
+        }
+        if args.no_discovery {
+            self.udp.enabled = false;
+        }
+        if let Some(url) = &args.ollama_url {
+            self.ollama.url = url.clone();
+        }
+        if let Some(backend) = args.backend {
+            self.llm.backend = backend;
+        }
+        if let Some(url) = &args.openai_url {
+            self.openai.url = url.clone();
+        }
+        if let Some(key) = &args.openai_api_key {
+            self.openai.api_key = Some(key.clone());
+        }
+        if let Some(model) = &args.model {
+            self.llm.model = model.clone();
+        }
+        if let Some(secs) = args.sync_interval {
+            self.tcp.sync_interval_secs = secs;
+        }
+        if let Some(secs) = args.remote_timeout {
+            self.llm.remote_timeout_secs = secs;
+        }
+        if let Some(dir) = &args.conversations_dir {
+            self.storage.conversations_dir = dir.clone();
+        }
+        if let Some(dir) = &args.received_dir {
+            self.storage.received_dir = dir.clone();
+        }
+        if let Some(dir) = &args.documents_dir {
+            self.storage.documents_dir = dir.clone();
+        }
+        if let Some(dir) = &args.shared_dir {
+            self.storage.shared_dir = dir.clone();
+        }
+        if let Some(model) = &args.embedding_model {
+            self.documents.embedding_model = model.clone();
+        }
+        if let Some(secs) = args.shutdown_timeout {
+            self.shutdown.timeout_secs = secs;
+        }
+        if args.manual_approval {
+            self.access.auto_approve = false;
+        }
+        if let Some(level) = &args.log_level {
+            self.logging.level = level.clone();
+        }
+        if let Some(format) = args.log_format {
+            self.logging.format = format;
+        }
+        if args.log_content {
+            self.logging.log_content = true;
+        }
+        self.peers.seeds.extend(args.peers.iter().filter(|peer| !peer.is_empty()).cloned());
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.http.port == 0 || self.tcp.port == 0 || self.udp.broadcast_port == 0 {
+            return Err("Ports must be non-zero".to_string());
+        }
+        if self.http.port == self.tcp.port {
+            return Err(format!("HTTP and TCP ports must differ (both are {})", self.http.port));
+        }
+        if self.tcp.sync_interval_secs == 0 || self.udp.broadcast_interval_secs == 0 {
+            return Err("Sync and broadcast intervals must be at least 1 second".to_string());
+        }
+        if self.llm.remote_timeout_secs == 0 {
+            return Err("Remote LLM timeout must be at least 1 second".to_string());
+        }
+        if self.shutdown.timeout_secs == 0 {
+            return Err("Shutdown timeout must be at least 1 second".to_string());
+        }
+        if self.llm.model.trim().is_empty() {
+            return Err("LLM model name must not be empty".to_string());
+        }
+        for (name, url) in [("Ollama", &self.ollama.url), ("OpenAI", &self.openai.url)] {
+            reqwest::Url::parse(url)
+                .ok()
+                .filter(|url| url.host_str().is_some() && url.port_or_known_default().is_some())
+                .ok_or_else(|| format!("Invalid {} URL: {}", name, url))?;
+        }
+        let storage = &self.storage;
+        if storage.conversations_dir.as_os_str().is_empty()
+            || storage.received_dir.as_os_str().is_empty()
+            || storage.documents_dir.as_os_str().is_empty()
+            || storage.shared_dir.as_os_str().is_empty()
+        {
+            return Err("Storage directories must not be empty".to_string());
+        }
+        let dirs = [&storage.conversations_dir, &storage.received_dir, &storage.documents_dir, &storage.shared_dir];
+        if dirs.iter().enumerate().any(|(i, dir)| dirs[i + 1..].contains(dir)) {
+            return Err("Conversations, received, documents and shared directories must differ".to_string());
+        }
+        let documents = &self.documents;
+        if documents.embedding_model.trim().is_empty() {
+            return Err("Embedding model name must not be empty".to_string());
+        }
+        if documents.chunk_chars == 0 || documents.chunk_overlap >= documents.chunk_chars {
+            return Err("Document chunks must be longer than their overlap".to_string());
+        }
+        if documents.top_k == 0 || documents.max_upload_mb == 0 {
+            return Err("documents.top_k and documents.max_upload_mb must be at least 1".to_string());
+        }
+        if storage.peer_quota_mb == 0 {
+            return Err("storage.peer_quota_mb must be at least 1".to_string());
+        }
+        if self.sharing.max_file_mb == 0 || self.sharing.peer_quota_mb == 0 {
+            return Err("sharing.max_file_mb and sharing.peer_quota_mb must be at least 1".to_string());
+        }
+        if self.cache.enabled && (self.cache.ttl_secs == 0 || self.cache.max_entries == 0 || self.cache.max_mb == 0) {
+            return Err("cache.ttl_secs, cache.max_entries and cache.max_mb must be at least 1".to_string());
+        }
+        tracing_subscriber::EnvFilter::try_new(&self.logging.level)
+            .map_err(|e| format!("Invalid log level {:?}: {}", self.logging.level, e))?;
+        for seed in &self.peers.seeds {
+            parse_peer_address(seed, self.tcp.port)
+                .ok_or_else(|| format!("Invalid peer address: {}", seed))?;
+        }
+        Ok(())
+    }
+
+    pub fn sync_interval(&self) -> Duration {
+        Duration::from_secs(self.tcp.sync_interval_secs)
+    }
+
+    pub fn broadcast_interval(&self) -> Duration {
+        Duration::from_secs(self.udp.broadcast_interval_secs)
+    }
+
+    pub fn remote_timeout(&self) -> Duration {
+        Duration::from_secs(self.llm.remote_timeout_secs)
+    }
+
+    pub fn max_upload_bytes(&self) -> usize {
+        (self.documents.max_upload_mb as usize).saturating_mul(1024 * 1024)
+    }
+
+    pub fn peer_storage_quota_bytes(&self) -> u64 {
+        self.storage.peer_quota_mb.saturating_mul(1024 * 1024)
+    }
+
+    pub fn max_shared_file_bytes(&self) -> u64 {
+        self.sharing.max_file_mb.saturating_mul(1024 * 1024)
+    }
+
+    pub fn peer_quota_bytes(&self) -> u64 {
+        self.sharing.peer_quota_mb.saturating_mul(1024 * 1024)
+    }
+
+    pub fn cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.cache.ttl_secs)
+    }
+
+    pub fn cache_max_bytes(&self) -> usize {
+        (self.cache.max_mb as usize).saturating_mul(1024 * 1024)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown.timeout_secs)
+    }
+
+    // Base URL of the LLM server selected by `llm.backend`
+    pub fn backend_url(&self) -> &str {
+        let url = match self.llm.backend {
+            BackendKind::Ollama => &self.ollama.url,
+            BackendKind::OpenAi => &self.openai.url,
+        };
+        url.trim_end_matches('/')
+    }
+}
+
+// Accepts "host" or "host:port"; a missing port means the given default
+pub fn parse_peer_address(address: &str, default_port: u16) -> Option<String> {
+    let address = address.trim();
+    if address.is_empty() {
+        return None;
+    }
+    match address.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port.parse::<u16>().ok().filter(|port| *port != 0)?;
+            if host.is_empty() {
+                return None;
+            }
+            Some(format!("{}:{}", host, port))
+        }
+        None => Some(format!("{}:{}", address, default_port)),
+    }
+}
@@ -1,10 +1,14 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::PathBuf;
 use tokio::sync::Mutex;
-use lazy_static::lazy_static;
 use chrono::{DateTime, Utc};
+use tracing::{debug, error, info};
 use crate::persistence;
-use hostname;
+use crate::sandbox::PeerStorage;
+
+// Name peers give the conversation they share, and the file it is kept in
+pub const PEER_CONVERSATION_FILE: &str = "local.json";
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatMessage {
@@ -13,6 +17,19 @@ pub struct ChatMessage {
     pub sender: String,
     pub message_type: MessageType,
     pub host_info: HostInfo,
+    // Document chunks the answer was given, numbered [1], [2], ... in the prompt
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub citations: Vec<Citation>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Citation {
+    pub document_id: String,
+    pub document_name: String,
+    // Position of the chunk within its document
+    pub chunk: usize,
+    pub excerpt: String,
+    pub score: f32,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -36,18 +53,31 @@ pub struct Conversation {
 }
 
 pub struct ConversationStore {
+    conversations_dir: PathBuf,
+    // Conversations peers sent us, one directory per peer
+    received: PeerStorage,
     local_conversation: Mutex<Option<Conversation>>,
     peer_conversations: Mutex<HashMap<String, Conversation>>,
 }
 
 impl ConversationStore {
-    pub fn new() -> Self {
+    pub fn new(conversations_dir: PathBuf, received: PeerStorage) -> Self {
         ConversationStore {
+            conversations_dir,
+            received,
             local_conversation: Mutex::new(None),
             peer_conversations: Mutex::new(HashMap::new()),
         }
     }
 
+    pub async fn init_dirs(&self) -> std::io::Result<()> {
+        persistence::init_conversations_dir(&self.conversations_dir, self.received.root()).await
+    }
+
+    pub async fn load_or_create_node_id(&self) -> std::io::Result<String> {
+        persistence::load_or_create_node_id(&self.conversations_dir).await
+    }
+
     pub async fn add_message(&self, conversation_id: String, message: ChatMessage) {
         if conversation_id == "local" {
             let mut local = self.local_conversation.lock().await;
@@ -79,21 +109,19 @@ impl ConversationStore {
 
             // Save local conversation
             if let Some(conversation) = local.as_ref() {
-                if let Err(e) = persistence::save_local_conversation(conversation).await {
-                    eprintln!("Error saving local conversation: {}", e);
+                if let Err(e) = persistence::save_local_conversation(&self.conversations_dir, conversation).await {
+                    error!("Error saving local conversation: {}", e);
                 }
             }
         }
     }
 
-    pub async fn add_peer_conversation(&self, peer_ip: String, conversation: Conversation) {
+    // Kept only if it could be saved, so memory never holds more than the peer's quota
+    pub async fn add_peer_conversation(&self, peer_ip: String, conversation: Conversation) -> std::io::Result<()> {
         let mut peer_conversations = self.peer_conversations.lock().await;
-        peer_conversations.insert(peer_ip.clone(), conversation.clone());
-        
-        // Save to disk
-        if let Err(e) = persistence::save_peer_conversation(&peer_ip, &conversation).await {
-            eprintln!("Error saving peer conversation: {}", e);
-        }
+        self.received.write_json(&peer_ip, PEER_CONVERSATION_FILE, &conversation).await?;
+        peer_conversations.insert(peer_ip, conversation);
+        Ok(())
     }
 
     pub async fn get_local_conversation(&self) -> Option<Conversation> {
@@ -102,27 +130,22 @@ impl ConversationStore {
     }
 
     pub async fn load_saved_conversations(&self) -> std::io::Result<()> {
-        println!("Loading saved conversations...");
-        
         // Load local conversation
-        if let Ok(Some(local)) = persistence::load_local_conversation().await {
-            println!("Loaded local conversation");
+        if let Ok(Some(local)) = persistence::load_local_conversation(&self.conversations_dir).await {
+            debug!(messages = local.messages.len(), "Loaded local conversation");
             let mut local_lock = self.local_conversation.lock().await;
             *local_lock = Some(local);
         }
 
         // Load peer conversations
-        match persistence::load_all_peer_conversations().await {
+        match persistence::load_all_peer_conversations(self.received.root()).await {
             Ok(peers) => {
-                println!("Successfully loaded {} peer conversations", peers.len());
+                info!("Loaded {} peer conversations", peers.len());
                 let mut peers_lock = self.peer_conversations.lock().await;
                 *peers_lock = peers;
-                for (peer, conv) in &*peers_lock {
-                    println!("Loaded conversation for peer {} with {} messages", peer, conv.messages.len());
-                }
             }
             Err(e) => {
-                eprintln!("Error loading peer conversations: {}", e);
+                error!("Error loading peer conversations: {}", e);
                 return Err(e);
             }
         }
@@ -130,12 +153,23 @@ impl ConversationStore {
         Ok(())
     }
 
+    // Writes everything held in memory back to disk. Taking the locks also waits for
+    // any save that is still in progress.
+    pub async fn flush(&self) -> std::io::Result<()> {
+        let local = self.local_conversation.lock().await;
+        if let Some(conversation) = local.as_ref() {
+            persistence::save_local_conversation(&self.conversations_dir, conversation).await?;
+        }
+
+        let peer_conversations = self.peer_conversations.lock().await;
+        for (peer_ip, conversation) in peer_conversations.iter() {
+            self.received.write_json(peer_ip, PEER_CONVERSATION_FILE, conversation).await?;
+        }
+        Ok(())
+    }
+
     pub async fn get_peer_conversations(&self) -> HashMap<String, Conversation> {
         let peers = self.peer_conversations.lock().await;
         peers.clone()
     }
 }
-
-lazy_static! {
-    pub static ref CONVERSATION_STORE: ConversationStore = ConversationStore::new();
-} 
\ No newline at end of file
@@ -0,0 +1,162 @@
+// Things that happen on a node, pushed to anyone watching /api/events as Server-Sent
+// Events. Publishing never waits: a subscriber that falls behind skips ahead and is
+// told how many events it missed.
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use actix_web::{get, web, HttpResponse, Error};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use crate::conversation::ChatMessage;
+use crate::node::Node;
+
+// Events a subscriber may be behind by before it starts missing some
+const EVENT_BUFFER: usize = 1024;
+// Comment lines sent while nothing happens, so proxies keep the stream open
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    // A peer announced itself over UDP discovery
+    PeerDiscovered { address: String },
+    PeerConnected { node_id: String, address: String },
+    // The last session to the peer ended; it will be redialed
+    PeerLost { node_id: String, address: String, error: Option<String> },
+    CapabilityChanged { node_id: String, has_llm: bool },
+    // `direction` is "received" when a peer asks us, "sent" when we ask a peer
+    AccessRequested { node_id: String, direction: &'static str, pending: bool },
+    AccessDecided { node_id: String, direction: &'static str, granted: bool },
+    MessageAdded { conversation: String, message: ChatMessage },
+    SyncCompleted { node_id: String, conversation: String, direction: &'static str, messages: usize },
+    InferenceStarted { id: u64, backend: &'static str, host: &'static str, operation: &'static str, model: String },
+    InferenceFinished { id: u64, outcome: &'static str, duration_ms: u64, tokens: Option<u64> },
+    // A streamed answer broke off on one host and carries on on another
+    InferenceFailover { request_id: String, model: String, from: String, to: String, resumed_after_chars: usize },
+}
+
+impl Event {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::PeerDiscovered { .. } => "peer_discovered",
+            Event::PeerConnected { .. } => "peer_connected",
+            Event::PeerLost { .. } => "peer_lost",
+            Event::CapabilityChanged { .. } => "capability_changed",
+            Event::AccessRequested { .. } => "access_requested",
+            Event::AccessDecided { .. } => "access_decided",
+            Event::MessageAdded { .. } => "message_added",
+            Event::SyncCompleted { .. } => "sync_completed",
+            Event::InferenceStarted { .. } => "inference_started",
+            Event::InferenceFinished { .. } => "inference_finished",
+            Event::InferenceFailover { .. } => "inference_failover",
+        }
+    }
+}
+
+// An event as sent to subscribers. `seq` grows by one per event, so gaps are visible.
+#[derive(Debug, Serialize)]
+pub struct EventRecord {
+    pub seq: u64,
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+pub struct EventBus {
+    sender: broadcast::Sender<Arc<EventRecord>>,
+    seq: AtomicU64,
+    inference_ids: AtomicU64,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUFFER);
+        EventBus { sender, seq: AtomicU64::new(0), inference_ids: AtomicU64::new(0) }
+    }
+
+    pub fn publish(&self, event: Event) {
+        let record = EventRecord {
+            seq: self.seq.fetch_add(1, Ordering::Relaxed) + 1,
+            at: Utc::now(),
+            event,
+        };
+        // Nobody listening is not an error
+        let _ = self.sender.send(Arc::new(record));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<EventRecord>> {
+        self.sender.subscribe()
+    }
+
+    // Ties an inference_finished event to its inference_started
+    pub fn next_inference_id(&self) -> u64 {
+        self.inference_ids.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    // Comma-separated event types to receive; all of them when absent
+    types: Option<String>,
+}
+
+struct Subscription {
+    events: broadcast::Receiver<Arc<EventRecord>>,
+    types: Option<HashSet<String>>,
+    keep_alive: tokio::time::Interval,
+    shutdown: CancellationToken,
+}
+
+fn sse_frame(kind: &str, data: &impl Serialize) -> web::Bytes {
+    let data = serde_json::to_string(data).unwrap_or_default();
+    web::Bytes::from(format!("event: {}\ndata: {}\n\n", kind, data))
+}
+
+impl Subscription {
+    // The next frame for the client, or None once the node is shutting down
+    async fn next_frame(&mut self) -> Option<web::Bytes> {
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled() => return None,
+                _ = self.keep_alive.tick() => return Some(web::Bytes::from_static(b": keep-alive\n\n")),
+                received = self.events.recv() => match received {
+                    Ok(record) => {
+                        let kind = record.event.kind();
+                        if self.types.as_ref().is_none_or(|types| types.contains(kind)) {
+                            return Some(sse_frame(kind, &*record));
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        return Some(sse_frame("lagged", &serde_json::json!({ "skipped": skipped })));
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                },
+            }
+        }
+    }
+}
+
+#[get("/events")]
+pub async fn stream_events(node: web::Data<Node>, query: web::Query<EventsQuery>) -> Result<HttpResponse, Error> {
+    let types = query.types.as_ref().map(|types| {
+        types.split(',').map(|kind| kind.trim().to_string()).filter(|kind| !kind.is_empty()).collect()
+    });
+    let subscription = Subscription {
+        events: node.events.subscribe(),
+        types,
+        keep_alive: tokio::time::interval(KEEP_ALIVE_INTERVAL),
+        shutdown: node.shutdown.clone(),
+    };
+    let body = futures::stream::unfold(subscription, |mut subscription| async move {
+        let frame = subscription.next_frame().await?;
+        Some((Ok::<_, Error>(frame), subscription))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(body))
+}
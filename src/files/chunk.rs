@@ -0,0 +1,29 @@
+// Splits text into overlapping windows of at most `size` characters, breaking at
+// whitespace in the second half of a window when there is any, so chunks rarely cut
+// a word in two. `overlap` must be smaller than `size`.
+pub fn chunk_text(text: &str, size: usize, overlap: usize) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+
+    while start < chars.len() {
+        let mut end = (start + size).min(chars.len());
+        if end < chars.len() {
+            let half = start + size / 2;
+            if let Some(offset) = chars[half..end].iter().rposition(|c| c.is_whitespace()) {
+                end = half + offset + 1;
+            }
+        }
+
+        let chunk: String = chars[start..end].iter().collect();
+        let chunk = chunk.trim();
+        if !chunk.is_empty() {
+            chunks.push(chunk.to_string());
+        }
+        if end == chars.len() {
+            break;
+        }
+        start = end.saturating_sub(overlap).max(start + 1);
+    }
+    chunks
+}
@@ -0,0 +1,110 @@
+// Documents uploaded for retrieval-augmented chat. Each upload is split into chunks,
+// embedded through the mesh and kept in a local vector index.
+use actix_multipart::Multipart;
+use actix_web::{delete, get, post, web, HttpResponse, Error};
+use chrono::Utc;
+use futures::TryStreamExt;
+use sha2::{Digest, Sha256};
+use tracing::info;
+use crate::llm::embed::embed_on_mesh;
+use crate::node::Node;
+use crate::server::Operator;
+use super::chunk;
+use super::index::{ChunkMatch, DocumentInfo};
+use super::upload_name;
+
+// Chunks and embeds one document. A document already indexed with the current
+// embedding model is returned as it is.
+async fn index_document(node: &Node, name: String, text: String) -> Result<DocumentInfo, String> {
+    let id = hex::encode(Sha256::digest(text.as_bytes()));
+    let model = &node.config.documents.embedding_model;
+    if let Some(existing) = node.documents.get(&id).await? {
+        if &existing.embedding_model == model {
+            return Ok(existing);
+        }
+    }
+
+    let documents = &node.config.documents;
+    let chunks = chunk::chunk_text(&text, documents.chunk_chars, documents.chunk_overlap);
+    if chunks.is_empty() {
+        return Err(format!("{} has no text to index", name));
+    }
+    let embeddings = embed_on_mesh(node, model, &chunks).await?;
+
+    let info = DocumentInfo {
+        id,
+        name,
+        size: text.len(),
+        chunks: chunks.len(),
+        embedding_model: model.clone(),
+        uploaded_at: Utc::now(),
+    };
+    info!(document = %info.id, "Indexed {} ({} chunks)", info.name, info.chunks);
+    node.documents.add(info, chunks.into_iter().zip(embeddings).collect()).await
+}
+
+// The chunks most relevant to `question`, best first
+pub async fn search_documents(node: &Node, question: &str) -> Result<Vec<ChunkMatch>, String> {
+    let model = &node.config.documents.embedding_model;
+    let mut embeddings = embed_on_mesh(node, model, &[question.to_string()]).await?;
+    let query = embeddings.pop().ok_or("No embedding returned for the question")?;
+    node.documents.search(model, query, node.config.documents.top_k).await
+}
+
+// Accepts one or more UTF-8 text files as multipart form fields
+#[post("/documents")]
+pub async fn upload_documents(node: web::Data<Node>, mut payload: Multipart, _: Operator) -> Result<HttpResponse, Error> {
+    let max_bytes = node.config.max_upload_bytes();
+    let mut uploaded = Vec::new();
+
+    while let Some(mut field) = payload.try_next().await? {
+        let name = upload_name(field.content_disposition().get_filename(), "document.txt");
+        let mut content = Vec::new();
+        while let Some(bytes) = field.try_next().await? {
+            if content.len() + bytes.len() > max_bytes {
+                return Ok(HttpResponse::PayloadTooLarge().json(serde_json::json!({
+                    "error": format!("{} is larger than {} MB", name, node.config.documents.max_upload_mb)
+                })));
+            }
+            content.extend_from_slice(&bytes);
+        }
+
+        let text = match String::from_utf8(content) {
+            Ok(text) => text,
+            Err(_) => {
+                return Ok(HttpResponse::UnsupportedMediaType().json(serde_json::json!({
+                    "error": format!("{} is not a UTF-8 text document", name)
+                })));
+            }
+        };
+        match index_document(&node, name, text).await {
+            Ok(info) => uploaded.push(info),
+            Err(e) => {
+                return Ok(HttpResponse::ServiceUnavailable().json(serde_json::json!({
+                    "error": "Failed to index document",
+                    "details": e
+                })));
+            }
+        }
+    }
+
+    if uploaded.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": "No files in upload" })));
+    }
+    Ok(HttpResponse::Ok().json(uploaded))
+}
+
+#[get("/documents")]
+pub async fn list_documents(node: web::Data<Node>) -> Result<HttpResponse, Error> {
+    let documents = node.documents.list().await.map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(documents))
+}
+
+#[delete("/documents/{id}")]
+pub async fn delete_document(node: web::Data<Node>, id: web::Path<String>, _: Operator) -> Result<HttpResponse, Error> {
+    match node.documents.delete(&id).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "No such document" }))),
+        Err(e) => Err(actix_web::error::ErrorInternalServerError(e)),
+    }
+}
@@ -0,0 +1,200 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+const INDEX_FILE: &str = "index.sqlite3";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DocumentInfo {
+    // SHA-256 of the content, so uploading the same file twice keeps one copy
+    pub id: String,
+    pub name: String,
+    pub size: usize,
+    pub chunks: usize,
+    pub embedding_model: String,
+    pub uploaded_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChunkMatch {
+    pub document_id: String,
+    pub document_name: String,
+    pub position: usize,
+    pub text: String,
+    pub score: f32,
+}
+
+// Uploaded documents, split into chunks with one embedding each, in a SQLite file.
+// Searching compares the query against every chunk, which is plenty for the few
+// thousand chunks a node is likely to hold.
+#[derive(Clone)]
+pub struct DocumentStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+fn encode_embedding(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+fn decode_embedding(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(4)
+        .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+        .collect()
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+impl DocumentStore {
+    pub async fn open(dir: &Path) -> std::io::Result<DocumentStore> {
+        tokio::fs::create_dir_all(dir).await?;
+        let db_path = dir.join(INDEX_FILE);
+        let conn = tokio::task::spawn_blocking(move || -> rusqlite::Result<Connection> {
+            let conn = Connection::open(&db_path)?;
+            conn.execute_batch(
+                "PRAGMA foreign_keys = ON;
+                 CREATE TABLE IF NOT EXISTS documents (
+                     id TEXT PRIMARY KEY,
+                     name TEXT NOT NULL,
+                     size INTEGER NOT NULL,
+                     embedding_model TEXT NOT NULL,
+                     uploaded_at TEXT NOT NULL
+                 );
+                 CREATE TABLE IF NOT EXISTS chunks (
+                     document_id TEXT NOT NULL REFERENCES documents(id) ON DELETE CASCADE,
+                     position INTEGER NOT NULL,
+                     text TEXT NOT NULL,
+                     embedding BLOB NOT NULL,
+                     PRIMARY KEY (document_id, position)
+                 );",
+            )?;
+            Ok(conn)
+        })
+        .await
+        .map_err(std::io::Error::other)?
+        .map_err(std::io::Error::other)?;
+
+        Ok(DocumentStore { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    // rusqlite blocks, so every query runs on the blocking pool
+    async fn with_conn<T, F>(&self, query: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().map_err(|_| "Document index lock poisoned".to_string())?;
+            query(&mut conn).map_err(|e| format!("Document index error: {}", e))
+        })
+        .await
+        .map_err(|e| e.to_string())?
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<DocumentInfo>, String> {
+        let id = id.to_string();
+        self.with_conn(move |conn| {
+            conn.query_row(
+                "SELECT d.id, d.name, d.size, d.embedding_model, d.uploaded_at,
+                        (SELECT COUNT(*) FROM chunks c WHERE c.document_id = d.id)
+                 FROM documents d WHERE d.id = ?1",
+                params![id],
+                row_to_info,
+            ).optional()
+        }).await
+    }
+
+    // Replaces any earlier copy of the same document
+    pub async fn add(&self, info: DocumentInfo, chunks: Vec<(String, Vec<f32>)>) -> Result<DocumentInfo, String> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute("DELETE FROM documents WHERE id = ?1", params![info.id])?;
+            tx.execute(
+                "INSERT INTO documents (id, name, size, embedding_model, uploaded_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![info.id, info.name, info.size as i64, info.embedding_model, info.uploaded_at.to_rfc3339()],
+            )?;
+            for (position, (text, embedding)) in chunks.iter().enumerate() {
+                tx.execute(
+                    "INSERT INTO chunks (document_id, position, text, embedding) VALUES (?1, ?2, ?3, ?4)",
+                    params![info.id, position as i64, text, encode_embedding(embedding)],
+                )?;
+            }
+            tx.commit()?;
+            Ok(info)
+        }).await
+    }
+
+    pub async fn list(&self) -> Result<Vec<DocumentInfo>, String> {
+        self.with_conn(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT d.id, d.name, d.size, d.embedding_model, d.uploaded_at,
+                        (SELECT COUNT(*) FROM chunks c WHERE c.document_id = d.id)
+                 FROM documents d ORDER BY d.uploaded_at",
+            )?;
+            let documents = statement.query_map([], row_to_info)?.collect();
+            documents
+        }).await
+    }
+
+    // Whether there was such a document
+    pub async fn delete(&self, id: &str) -> Result<bool, String> {
+        let id = id.to_string();
+        self.with_conn(move |conn| {
+            Ok(conn.execute("DELETE FROM documents WHERE id = ?1", params![id])? > 0)
+        }).await
+    }
+
+    // The `limit` chunks closest to `query` among those embedded with `model`;
+    // vectors from another model are not comparable
+    pub async fn search(&self, model: &str, query: Vec<f32>, limit: usize) -> Result<Vec<ChunkMatch>, String> {
+        let model = model.to_string();
+        self.with_conn(move |conn| {
+            let mut statement = conn.prepare(
+                "SELECT c.document_id, d.name, c.position, c.text, c.embedding
+                 FROM chunks c JOIN documents d ON d.id = c.document_id
+                 WHERE d.embedding_model = ?1",
+            )?;
+            let rows = statement.query_map(params![model], |row| {
+                let embedding: Vec<u8> = row.get(4)?;
+                Ok(ChunkMatch {
+                    document_id: row.get(0)?,
+                    document_name: row.get(1)?,
+                    position: row.get::<_, i64>(2)? as usize,
+                    text: row.get(3)?,
+                    score: cosine_similarity(&query, &decode_embedding(&embedding)),
+                })
+            })?;
+            let mut matches = rows.collect::<rusqlite::Result<Vec<ChunkMatch>>>()?;
+            matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+            matches.truncate(limit);
+            Ok(matches)
+        }).await
+    }
+}
+
+fn row_to_info(row: &rusqlite::Row) -> rusqlite::Result<DocumentInfo> {
+    let uploaded_at: String = row.get(4)?;
+    Ok(DocumentInfo {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        size: row.get::<_, i64>(2)? as usize,
+        embedding_model: row.get(3)?,
+        uploaded_at: DateTime::parse_from_rfc3339(&uploaded_at)
+            .map(|time| time.with_timezone(&Utc))
+            .unwrap_or_default(),
+        chunks: row.get::<_, i64>(5)? as usize,
+    })
+}
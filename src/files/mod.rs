@@ -1,4 +1,26 @@
-// Files module for file handling functionality
-pub fn init() {
-    println!("Files module initialized");
-} 
\ No newline at end of file
+// Files module: documents uploaded for retrieval-augmented chat, and whole files
+// shared with peers by content hash.
+mod chunk;
+mod documents;
+mod index;
+mod shared;
+mod sharing;
+mod transfer;
+
+pub use documents::{delete_document, list_documents, search_documents, upload_documents};
+pub use index::{ChunkMatch, DocumentStore};
+pub use shared::{FileManifest, SharedStore};
+pub use sharing::{delete_shared_file, fetch_shared_file, list_shared_files, offer_files, shared_file_content};
+pub use transfer::chunk_response;
+
+const MAX_NAME_CHARS: usize = 255;
+
+// Keeps only the last path component of an uploaded file's name
+fn upload_name(filename: Option<&str>, fallback: &str) -> String {
+    let name = filename
+        .and_then(|name| name.rsplit(['/', '\\']).next())
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or(fallback);
+    name.chars().take(MAX_NAME_CHARS).collect()
+}
@@ -0,0 +1,475 @@
+// Files shared with peers, addressed by the SHA-256 of their content. Complete files
+// live in `blobs/<hash>`, downloads in progress in `partial/<hash>.part`, and the list
+// of complete files, with the peer each was fetched from, in `index.json`.
+use std::collections::{BTreeSet, HashMap};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::{oneshot, Mutex};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+use crate::persistence::write_atomic;
+use crate::sandbox::confined;
+use super::MAX_NAME_CHARS;
+
+// Size of the pieces files are transferred and verified in
+pub const CHUNK_BYTES: u64 = 256 * 1024;
+// Largest chunk accepted in a peer's manifest, so one chunk always fits in a frame
+const MAX_CHUNK_BYTES: u64 = 4 * 1024 * 1024;
+// Offers remembered per peer; any beyond this are ignored
+const MAX_OFFERS_PER_PEER: usize = 1000;
+const INDEX_FILE: &str = "index.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileManifest {
+    // SHA-256 of the whole content, lowercase hex
+    pub hash: String,
+    pub name: String,
+    pub size: u64,
+    pub chunk_size: u64,
+    // SHA-256 of each chunk, in order
+    pub chunks: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedFile {
+    pub manifest: FileManifest,
+    // Node the file was fetched from; None when it was offered here
+    pub origin: Option<String>,
+    pub added_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadStatus {
+    pub hash: String,
+    pub name: String,
+    pub size: u64,
+    // Node the download is charged to
+    pub peer: String,
+    pub chunks_done: usize,
+    pub chunks_total: usize,
+    // Set once the download has stopped without completing
+    pub error: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum FetchError {
+    AlreadyShared(Box<SharedFile>),
+    InProgress(DownloadStatus),
+    NotOffered,
+    NoConnectedPeer,
+    TooLarge { size: u64, limit: u64 },
+    QuotaExceeded { peer: String, used: u64, quota: u64 },
+}
+
+pub type ChunkReply = Result<Vec<u8>, String>;
+
+struct Download {
+    status: DownloadStatus,
+    cancel: CancellationToken,
+}
+
+#[derive(Default)]
+struct State {
+    files: HashMap<String, SharedFile>,
+    // Manifests peers offered, by hash, with the node IDs offering each
+    offers: HashMap<String, (FileManifest, BTreeSet<String>)>,
+    downloads: HashMap<String, Download>,
+}
+
+pub struct SharedStore {
+    dir: PathBuf,
+    state: Mutex<State>,
+    // Chunk requests waiting for an answer, by (node ID, hash, chunk index)
+    pending: Mutex<HashMap<(String, String, u64), oneshot::Sender<ChunkReply>>>,
+}
+
+pub fn is_sha256_hex(value: &str) -> bool {
+    value.len() == 64 && value.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+impl FileManifest {
+    // Offset and length of chunk `index`
+    pub fn chunk_range(&self, index: u64) -> Option<(u64, usize)> {
+        if index >= self.chunks.len() as u64 {
+            return None;
+        }
+        let start = index * self.chunk_size;
+        Some((start, self.chunk_size.min(self.size - start) as usize))
+    }
+
+    // Checks a manifest from a peer before anything is based on it
+    pub fn validate(&self, max_size: u64) -> Result<(), String> {
+        if !is_sha256_hex(&self.hash) {
+            return Err("Invalid file hash".to_string());
+        }
+        let name = self.name.as_str();
+        if name.is_empty()
+            || name == "."
+            || name == ".."
+            || name.chars().count() > MAX_NAME_CHARS
+            || name.contains(['/', '\\'])
+            || name.chars().any(char::is_control)
+        {
+            return Err(format!("Invalid file name {:?}", self.name));
+        }
+        if self.size > max_size {
+            return Err(format!("{} is larger than the {} byte limit", self.name, max_size));
+        }
+        if self.chunk_size == 0 || self.chunk_size > MAX_CHUNK_BYTES {
+            return Err(format!("Invalid chunk size {}", self.chunk_size));
+        }
+        if self.chunks.len() as u64 != self.size.div_ceil(self.chunk_size) {
+            return Err("Chunk list does not match the file size".to_string());
+        }
+        if !self.chunks.iter().all(|chunk| is_sha256_hex(chunk)) {
+            return Err("Invalid chunk hash".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Hashes content as it streams in, as a whole and per chunk
+pub struct ManifestBuilder {
+    whole: Sha256,
+    chunk: Sha256,
+    in_chunk: u64,
+    chunks: Vec<String>,
+    size: u64,
+}
+
+impl ManifestBuilder {
+    pub fn new() -> Self {
+        ManifestBuilder {
+            whole: Sha256::new(),
+            chunk: Sha256::new(),
+            in_chunk: 0,
+            chunks: Vec::new(),
+            size: 0,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.whole.update(data);
+        self.size += data.len() as u64;
+        while !data.is_empty() {
+            let take = ((CHUNK_BYTES - self.in_chunk) as usize).min(data.len());
+            self.chunk.update(&data[..take]);
+            self.in_chunk += take as u64;
+            data = &data[take..];
+            if self.in_chunk == CHUNK_BYTES {
+                self.chunks.push(hex::encode(self.chunk.finalize_reset()));
+                self.in_chunk = 0;
+            }
+        }
+    }
+
+    pub fn finish(mut self, name: String) -> FileManifest {
+        if self.in_chunk > 0 {
+            self.chunks.push(hex::encode(self.chunk.finalize_reset()));
+        }
+        FileManifest {
+            hash: hex::encode(self.whole.finalize()),
+            name,
+            size: self.size,
+            chunk_size: CHUNK_BYTES,
+            chunks: self.chunks,
+        }
+    }
+}
+
+impl SharedStore {
+    pub async fn open(dir: &Path) -> std::io::Result<SharedStore> {
+        fs::create_dir_all(dir.join("blobs")).await?;
+        fs::create_dir_all(dir.join("partial")).await?;
+
+        let mut state = State::default();
+        let index_path = dir.join(INDEX_FILE);
+        if index_path.exists() {
+            let content = fs::read(&index_path).await?;
+            match serde_json::from_slice::<Vec<SharedFile>>(&content) {
+                Ok(files) => {
+                    for file in files {
+                        // A blob removed by hand is no longer shared
+                        let hash = &file.manifest.hash;
+                        if is_sha256_hex(hash) && dir.join("blobs").join(hash).exists() {
+                            state.files.insert(file.manifest.hash.clone(), file);
+                        }
+                    }
+                }
+                Err(e) => warn!("Ignoring unreadable shared file index: {}", e),
+            }
+        }
+
+        Ok(SharedStore {
+            dir: dir.to_path_buf(),
+            state: Mutex::new(state),
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // Paths are only ever built from validated hashes
+    pub fn blob_path(&self, hash: &str) -> Option<PathBuf> {
+        is_sha256_hex(hash).then(|| confined(&self.dir.join("blobs"), hash).ok()).flatten()
+    }
+
+    pub fn partial_path(&self, hash: &str) -> Option<PathBuf> {
+        is_sha256_hex(hash).then(|| confined(&self.dir.join("partial"), &format!("{}.part", hash)).ok()).flatten()
+    }
+
+    // A fresh file for an upload that has not been hashed yet
+    pub fn upload_path(&self) -> PathBuf {
+        self.dir.join("partial").join(format!("upload-{}.tmp", hex::encode(rand::random::<[u8; 8]>())))
+    }
+
+    async fn save_index(&self, state: &State) -> std::io::Result<()> {
+        let mut files: Vec<&SharedFile> = state.files.values().collect();
+        files.sort_by_key(|file| file.added_at);
+        let json = serde_json::to_vec_pretty(&files)?;
+        write_atomic(&self.dir.join(INDEX_FILE), &json).await
+    }
+
+    pub async fn get(&self, hash: &str) -> Option<SharedFile> {
+        self.state.lock().await.files.get(hash).cloned()
+    }
+
+    pub async fn list(&self) -> Vec<SharedFile> {
+        let mut files: Vec<SharedFile> = self.state.lock().await.files.values().cloned().collect();
+        files.sort_by_key(|file| file.added_at);
+        files
+    }
+
+    pub async fn manifests(&self) -> Vec<FileManifest> {
+        self.list().await.into_iter().map(|file| file.manifest).collect()
+    }
+
+    // Moves the file at `from` into the store under its hash; content already shared
+    // keeps its existing entry
+    pub async fn add(&self, manifest: FileManifest, from: &Path, origin: Option<String>) -> std::io::Result<SharedFile> {
+        let blob = self.blob_path(&manifest.hash)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid file hash"))?;
+        let mut state = self.state.lock().await;
+        if let Some(existing) = state.files.get(&manifest.hash) {
+            let existing = existing.clone();
+            fs::remove_file(from).await?;
+            return Ok(existing);
+        }
+
+        fs::rename(from, &blob).await?;
+        let file = SharedFile { manifest, origin, added_at: Utc::now() };
+        state.files.insert(file.manifest.hash.clone(), file.clone());
+        self.save_index(&state).await?;
+        Ok(file)
+    }
+
+    // Drops a shared file and any download of it. Returns whether there was either.
+    pub async fn remove(&self, hash: &str) -> std::io::Result<bool> {
+        let (Some(blob), Some(partial)) = (self.blob_path(hash), self.partial_path(hash)) else {
+            return Ok(false);
+        };
+        let mut state = self.state.lock().await;
+        let download = state.downloads.remove(hash);
+        if let Some(download) = &download {
+            download.cancel.cancel();
+        }
+        if partial.exists() {
+            fs::remove_file(&partial).await?;
+        }
+
+        let shared = state.files.remove(hash).is_some();
+        if shared {
+            fs::remove_file(&blob).await?;
+            self.save_index(&state).await?;
+        }
+        Ok(shared || download.is_some())
+    }
+
+    // Remembers what a peer offers, skipping manifests that do not check out
+    pub async fn record_offers(&self, node_id: &str, manifests: Vec<FileManifest>, max_size: u64) {
+        let mut state = self.state.lock().await;
+        let mut count = state.offers.values().filter(|(_, peers)| peers.contains(node_id)).count();
+        for manifest in manifests {
+            if count >= MAX_OFFERS_PER_PEER {
+                warn!(node = node_id, "Peer offers more than {} files; ignoring the rest", MAX_OFFERS_PER_PEER);
+                break;
+            }
+            if let Err(e) = manifest.validate(max_size) {
+                warn!(node = node_id, "Ignoring offer: {}", e);
+                continue;
+            }
+            let entry = state.offers
+                .entry(manifest.hash.clone())
+                .or_insert_with(|| (manifest.clone(), BTreeSet::new()));
+            // Same hash, different chunking: keep the first manifest, whose chunks we can check
+            if entry.0.chunk_size != manifest.chunk_size || entry.0.chunks != manifest.chunks {
+                continue;
+            }
+            if entry.1.insert(node_id.to_string()) {
+                count += 1;
+            }
+        }
+    }
+
+    pub async fn withdraw(&self, node_id: &str, hash: &str) {
+        let mut state = self.state.lock().await;
+        if let Some((_, peers)) = state.offers.get_mut(hash) {
+            peers.remove(node_id);
+            if peers.is_empty() {
+                state.offers.remove(hash);
+            }
+        }
+    }
+
+    pub async fn forget_peer(&self, node_id: &str) {
+        let mut state = self.state.lock().await;
+        state.offers.retain(|_, (_, peers)| {
+            peers.remove(node_id);
+            !peers.is_empty()
+        });
+    }
+
+    // Files peers offer that this node does not have, with the nodes offering each
+    pub async fn offers(&self) -> Vec<(FileManifest, Vec<String>)> {
+        let state = self.state.lock().await;
+        let mut offers: Vec<(FileManifest, Vec<String>)> = state.offers.values()
+            .filter(|(manifest, _)| !state.files.contains_key(&manifest.hash))
+            .map(|(manifest, peers)| (manifest.clone(), peers.iter().cloned().collect()))
+            .collect();
+        offers.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+        offers
+    }
+
+    pub async fn downloads(&self) -> Vec<DownloadStatus> {
+        let state = self.state.lock().await;
+        let mut downloads: Vec<DownloadStatus> = state.downloads.values().map(|d| d.status.clone()).collect();
+        downloads.sort_by(|a, b| a.name.cmp(&b.name));
+        downloads
+    }
+
+    // Bytes stored or being fetched on behalf of one peer
+    fn peer_usage(state: &State, node_id: &str) -> u64 {
+        let stored: u64 = state.files.values()
+            .filter(|file| file.origin.as_deref() == Some(node_id))
+            .map(|file| file.manifest.size)
+            .sum();
+        let fetching: u64 = state.downloads.values()
+            .filter(|download| download.status.peer == node_id && download.status.error.is_none())
+            .map(|download| download.status.size)
+            .sum();
+        stored + fetching
+    }
+
+    // Registers a download of `hash` from the connected peers offering it. The first
+    // peer with room left in its quota is charged; the others are fallbacks for
+    // chunks it cannot serve. Returns the manifest and the peers to ask, in order.
+    pub async fn begin_download(&self, hash: &str, connected: &[String], max_size: u64, quota: u64, cancel: CancellationToken) -> Result<(DownloadStatus, FileManifest, Vec<String>), FetchError> {
+        let mut state = self.state.lock().await;
+        if let Some(file) = state.files.get(hash) {
+            return Err(FetchError::AlreadyShared(Box::new(file.clone())));
+        }
+        if let Some(download) = state.downloads.get(hash) {
+            if download.status.error.is_none() {
+                return Err(FetchError::InProgress(download.status.clone()));
+            }
+        }
+        let Some((manifest, offering)) = state.offers.get(hash).cloned() else {
+            return Err(FetchError::NotOffered);
+        };
+        if manifest.size > max_size {
+            return Err(FetchError::TooLarge { size: manifest.size, limit: max_size });
+        }
+
+        let peers: Vec<String> = offering.into_iter().filter(|peer| connected.contains(peer)).collect();
+        if peers.is_empty() {
+            return Err(FetchError::NoConnectedPeer);
+        }
+        let charged = peers.iter()
+            .position(|peer| Self::peer_usage(&state, peer) + manifest.size <= quota)
+            .ok_or_else(|| FetchError::QuotaExceeded {
+                peer: peers[0].clone(),
+                used: Self::peer_usage(&state, &peers[0]),
+                quota,
+            })?;
+        let mut peers = peers;
+        peers.rotate_left(charged);
+
+        let status = DownloadStatus {
+            hash: manifest.hash.clone(),
+            name: manifest.name.clone(),
+            size: manifest.size,
+            peer: peers[0].clone(),
+            chunks_done: 0,
+            chunks_total: manifest.chunks.len(),
+            error: None,
+        };
+        state.downloads.insert(hash.to_string(), Download { status: status.clone(), cancel });
+        Ok((status, manifest, peers))
+    }
+
+    pub async fn set_progress(&self, hash: &str, chunks_done: usize) {
+        if let Some(download) = self.state.lock().await.downloads.get_mut(hash) {
+            download.status.chunks_done = chunks_done;
+        }
+    }
+
+    // The partial file stays, so fetching again resumes where this one stopped
+    pub async fn fail_download(&self, hash: &str, error: String) {
+        if let Some(download) = self.state.lock().await.downloads.get_mut(hash) {
+            download.status.error = Some(error);
+        }
+    }
+
+    pub async fn complete_download(&self, manifest: FileManifest, origin: String) -> std::io::Result<SharedFile> {
+        let partial = self.partial_path(&manifest.hash)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid file hash"))?;
+        let hash = manifest.hash.clone();
+        let result = self.add(manifest, &partial, Some(origin)).await;
+        self.state.lock().await.downloads.remove(&hash);
+        result
+    }
+
+    // One chunk of a complete shared file, for a peer that asked for it
+    pub async fn read_chunk(&self, hash: &str, index: u64) -> Result<Vec<u8>, String> {
+        let manifest = match self.state.lock().await.files.get(hash) {
+            Some(file) => file.manifest.clone(),
+            None => return Err("File is not shared here".to_string()),
+        };
+        let (offset, len) = manifest.chunk_range(index).ok_or("No such chunk")?;
+        let path = self.blob_path(hash).ok_or("Invalid file hash")?;
+
+        let mut file = fs::File::open(&path).await.map_err(|e| e.to_string())?;
+        file.seek(SeekFrom::Start(offset)).await.map_err(|e| e.to_string())?;
+        let mut data = vec![0u8; len];
+        file.read_exact(&mut data).await.map_err(|e| e.to_string())?;
+        Ok(data)
+    }
+
+    pub async fn expect_chunk(&self, node_id: &str, hash: &str, index: u64) -> oneshot::Receiver<ChunkReply> {
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().await.insert((node_id.to_string(), hash.to_string(), index), tx);
+        rx
+    }
+
+    pub async fn forget_chunk(&self, node_id: &str, hash: &str, index: u64) {
+        self.pending.lock().await.remove(&(node_id.to_string(), hash.to_string(), index));
+    }
+
+    // Hands a peer's answer to whoever asked; answers nobody is waiting for are dropped
+    pub async fn deliver(&self, node_id: &str, hash: String, index: u64, reply: ChunkReply) {
+        let waiting = self.pending.lock().await.remove(&(node_id.to_string(), hash, index));
+        match waiting {
+            Some(tx) => {
+                let _ = tx.send(reply);
+            }
+            None => debug!(node = node_id, "Ignoring unrequested chunk {}", index),
+        }
+    }
+}
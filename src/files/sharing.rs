@@ -0,0 +1,149 @@
+// REST endpoints for files shared across the mesh
+use actix_multipart::Multipart;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{delete, get, post, web, HttpResponse, Error};
+use futures::TryStreamExt;
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+use crate::node::Node;
+use crate::server::Operator;
+use crate::tcp::Message;
+use super::shared::{is_sha256_hex, FetchError, ManifestBuilder, SharedFile};
+use super::transfer::{broadcast, start_fetch};
+use super::upload_name;
+
+fn file_view(file: &SharedFile) -> Value {
+    json!({
+        "hash": file.manifest.hash,
+        "name": file.manifest.name,
+        "size": file.manifest.size,
+        "chunks": file.manifest.chunks.len(),
+        "origin": file.origin,
+        "added_at": file.added_at,
+    })
+}
+
+fn invalid_hash() -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({ "error": "Files are addressed by their SHA-256 in lowercase hex" }))
+}
+
+// Files shared here, files peers offer, and downloads in progress or stopped
+#[get("/files")]
+pub async fn list_shared_files(node: web::Data<Node>) -> Result<HttpResponse, Error> {
+    let files: Vec<Value> = node.shared.list().await.iter().map(file_view).collect();
+    let offers: Vec<Value> = node.shared.offers().await.into_iter()
+        .map(|(manifest, peers)| json!({
+            "hash": manifest.hash,
+            "name": manifest.name,
+            "size": manifest.size,
+            "chunks": manifest.chunks.len(),
+            "peers": peers,
+        }))
+        .collect();
+    let downloads = node.shared.downloads().await;
+    Ok(HttpResponse::Ok().json(json!({ "files": files, "offers": offers, "downloads": downloads })))
+}
+
+// Shares each uploaded file and offers it to every connected peer
+#[post("/files")]
+pub async fn offer_files(node: web::Data<Node>, mut payload: Multipart, _: Operator) -> Result<HttpResponse, Error> {
+    let max_bytes = node.config.max_shared_file_bytes();
+    let mut offered = Vec::new();
+
+    while let Some(mut field) = payload.try_next().await? {
+        let name = upload_name(field.content_disposition().get_filename(), "file");
+        let path = node.shared.upload_path();
+        let mut file = tokio::fs::File::create(&path).await?;
+        let mut builder = ManifestBuilder::new();
+
+        while let Some(bytes) = field.try_next().await? {
+            if builder.size() + bytes.len() as u64 > max_bytes {
+                drop(file);
+                let _ = tokio::fs::remove_file(&path).await;
+                return Ok(HttpResponse::PayloadTooLarge().json(json!({
+                    "error": format!("{} is larger than {} MB", name, node.config.sharing.max_file_mb)
+                })));
+            }
+            builder.update(&bytes);
+            file.write_all(&bytes).await?;
+        }
+        file.flush().await?;
+        drop(file);
+
+        let shared = node.shared.add(builder.finish(name), &path, None).await?;
+        info!(hash = %shared.manifest.hash, "Sharing {} ({} bytes)", shared.manifest.name, shared.manifest.size);
+        let manifest = shared.manifest.clone();
+        broadcast(&node, || Message::FileOffer { files: vec![manifest.clone()] }).await;
+        offered.push(file_view(&shared));
+    }
+
+    if offered.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(json!({ "error": "No files in upload" })));
+    }
+    Ok(HttpResponse::Ok().json(offered))
+}
+
+// The content of a file shared here
+#[get("/files/{hash}")]
+pub async fn shared_file_content(node: web::Data<Node>, hash: web::Path<String>) -> Result<HttpResponse, Error> {
+    if !is_sha256_hex(&hash) {
+        return Ok(invalid_hash());
+    }
+    let (Some(shared), Some(path)) = (node.shared.get(&hash).await, node.shared.blob_path(&hash)) else {
+        return Ok(HttpResponse::NotFound().json(json!({ "error": "No such shared file" })));
+    };
+
+    let file = tokio::fs::File::open(path).await?;
+    let mime_type = mime_guess::from_path(&shared.manifest.name).first_or_octet_stream();
+    Ok(HttpResponse::Ok()
+        .content_type(mime_type.to_string())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(shared.manifest.name)],
+        })
+        .streaming(tokio_util::io::ReaderStream::new(file)))
+}
+
+// Starts fetching a file a peer offers; 202 while it downloads in the background
+#[post("/files/{hash}/fetch")]
+pub async fn fetch_shared_file(node: web::Data<Node>, hash: web::Path<String>, _: Operator) -> Result<HttpResponse, Error> {
+    if !is_sha256_hex(&hash) {
+        return Ok(invalid_hash());
+    }
+    let response = match start_fetch(&node.into_inner(), &hash).await {
+        Ok(status) => HttpResponse::Accepted().json(status),
+        Err(FetchError::InProgress(status)) => HttpResponse::Accepted().json(status),
+        Err(FetchError::AlreadyShared(file)) => HttpResponse::Ok().json(file_view(&file)),
+        Err(FetchError::NotOffered) => {
+            HttpResponse::NotFound().json(json!({ "error": "No peer offers this file" }))
+        }
+        Err(FetchError::NoConnectedPeer) => {
+            HttpResponse::ServiceUnavailable().json(json!({ "error": "No peer offering this file is connected" }))
+        }
+        Err(FetchError::TooLarge { size, limit }) => HttpResponse::PayloadTooLarge().json(json!({
+            "error": format!("The file is {} bytes; the limit is {} bytes", size, limit)
+        })),
+        Err(FetchError::QuotaExceeded { peer, used, quota }) => HttpResponse::InsufficientStorage().json(json!({
+            "error": format!("Files from {} already use {} of {} bytes", peer, used, quota)
+        })),
+    };
+    Ok(response)
+}
+
+// Stops sharing a file, or abandons a download of it
+#[delete("/files/{hash}")]
+pub async fn delete_shared_file(node: web::Data<Node>, hash: web::Path<String>, _: Operator) -> Result<HttpResponse, Error> {
+    if !is_sha256_hex(&hash) {
+        return Ok(invalid_hash());
+    }
+    let shared = node.shared.get(&hash).await.is_some();
+    if !node.shared.remove(&hash).await? {
+        return Ok(HttpResponse::NotFound().json(json!({ "error": "No such shared file" })));
+    }
+    if shared {
+        let hash = hash.into_inner();
+        broadcast(&node, || Message::FileWithdraw { hash: hash.clone() }).await;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,174 @@
+// Moving shared files between nodes: answering peers' chunk requests, and fetching
+// a file from the peers that offer it one verified chunk at a time
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use crate::node::Node;
+use crate::tcp::Message;
+use super::shared::{DownloadStatus, FetchError, FileManifest};
+
+// How long a peer gets to answer one chunk request
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(30);
+const READ_BUFFER: usize = 64 * 1024;
+
+// Answer to a peer's ChunkRequest
+pub async fn chunk_response(node: &Node, hash: String, index: u64) -> Message {
+    match node.shared.read_chunk(&hash, index).await {
+        Ok(data) => Message::ChunkData { hash, index, data },
+        Err(reason) => Message::ChunkUnavailable { hash, index, reason },
+    }
+}
+
+// Sends a message to every connected peer
+pub async fn broadcast(node: &Node, message: impl Fn() -> Message) {
+    for peer in node.sessions.node_ids().await {
+        if let Err(e) = node.sessions.send_to(&peer, message()).await {
+            warn!(node = %peer, "Failed to notify peer: {}", e);
+        }
+    }
+}
+
+// Starts fetching a file peers offer; progress shows up in the store's downloads
+pub async fn start_fetch(node: &Arc<Node>, hash: &str) -> Result<DownloadStatus, FetchError> {
+    let connected = node.sessions.node_ids().await;
+    let cancel = node.shutdown.child_token();
+    let (status, manifest, peers) = node.shared.begin_download(
+        hash,
+        &connected,
+        node.config.max_shared_file_bytes(),
+        node.config.peer_quota_bytes(),
+        cancel.clone(),
+    ).await?;
+
+    info!(hash = %manifest.hash, node = %status.peer, "Fetching {} ({} bytes)", manifest.name, manifest.size);
+    tokio::spawn(download(node.clone(), manifest, peers, cancel));
+    Ok(status)
+}
+
+async fn download(node: Arc<Node>, manifest: FileManifest, peers: Vec<String>, cancel: CancellationToken) {
+    let result = tokio::select! {
+        result = receive_file(&node, &manifest, &peers) => result,
+        _ = cancel.cancelled() => Err("Cancelled".to_string()),
+    };
+
+    let hash = manifest.hash.clone();
+    let name = manifest.name.clone();
+    let result = match result {
+        Ok(()) => node.shared.complete_download(manifest, peers[0].clone()).await.map_err(|e| e.to_string()),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(file) => info!(%hash, "Fetched {} ({} bytes)", name, file.manifest.size),
+        Err(e) => {
+            warn!(%hash, "Fetching {} stopped: {}", name, e);
+            node.shared.fail_download(&hash, e).await;
+        }
+    }
+}
+
+async fn receive_file(node: &Node, manifest: &FileManifest, peers: &[String]) -> Result<(), String> {
+    let path = node.shared.partial_path(&manifest.hash).ok_or("Invalid file hash")?;
+    let done = verified_prefix(&path, manifest).await.map_err(|e| e.to_string())?;
+    if done > 0 {
+        info!(hash = %manifest.hash, "Resuming {} at chunk {} of {}", manifest.name, done, manifest.chunks.len());
+    }
+    node.shared.set_progress(&manifest.hash, done).await;
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .await
+        .map_err(|e| e.to_string())?;
+    for index in done..manifest.chunks.len() {
+        let data = fetch_chunk(node, manifest, index as u64, peers).await?;
+        file.write_all(&data).await.map_err(|e| e.to_string())?;
+        node.shared.set_progress(&manifest.hash, index + 1).await;
+    }
+    file.flush().await.map_err(|e| e.to_string())?;
+    drop(file);
+
+    // Every chunk matched the manifest, but the manifest itself may not match its hash
+    if hash_file(&path).await.map_err(|e| e.to_string())? != manifest.hash {
+        let _ = fs::remove_file(&path).await;
+        return Err("Content does not match the file hash".to_string());
+    }
+    Ok(())
+}
+
+// Asks each peer in turn for one chunk until one sends bytes that match the manifest
+async fn fetch_chunk(node: &Node, manifest: &FileManifest, index: u64, peers: &[String]) -> Result<Vec<u8>, String> {
+    let (_, len) = manifest.chunk_range(index).ok_or("No such chunk")?;
+    let expected = &manifest.chunks[index as usize];
+    let mut errors = Vec::new();
+
+    for peer in peers {
+        let reply = node.shared.expect_chunk(peer, &manifest.hash, index).await;
+        let request = Message::ChunkRequest { hash: manifest.hash.clone(), index };
+        if let Err(e) = node.sessions.send_to(peer, request).await {
+            node.shared.forget_chunk(peer, &manifest.hash, index).await;
+            errors.push(format!("{}: {}", peer, e));
+            continue;
+        }
+
+        match tokio::time::timeout(CHUNK_TIMEOUT, reply).await {
+            Ok(Ok(Ok(data))) => {
+                if data.len() == len && &hex::encode(Sha256::digest(&data)) == expected {
+                    return Ok(data);
+                }
+                warn!(hash = %manifest.hash, node = %peer, "Chunk {} of {} failed verification", index, manifest.name);
+                errors.push(format!("{}: chunk {} failed verification", peer, index));
+            }
+            Ok(Ok(Err(reason))) => errors.push(format!("{}: {}", peer, reason)),
+            Ok(Err(_)) => errors.push(format!("{}: request dropped", peer)),
+            Err(_) => {
+                node.shared.forget_chunk(peer, &manifest.hash, index).await;
+                errors.push(format!("{}: timed out", peer));
+            }
+        }
+    }
+    Err(errors.join("; "))
+}
+
+// Number of leading chunks already on disk that match the manifest. Anything after
+// them is cut off, so the download continues from a clean chunk boundary.
+async fn verified_prefix(path: &Path, manifest: &FileManifest) -> std::io::Result<usize> {
+    let mut file = match fs::OpenOptions::new().read(true).write(true).open(path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let mut done = 0;
+    let mut verified_bytes = 0;
+    for (index, expected) in manifest.chunks.iter().enumerate() {
+        let Some((_, len)) = manifest.chunk_range(index as u64) else { break };
+        let mut data = vec![0u8; len];
+        if file.read_exact(&mut data).await.is_err() || &hex::encode(Sha256::digest(&data)) != expected {
+            break;
+        }
+        done += 1;
+        verified_bytes += len as u64;
+    }
+    file.set_len(verified_bytes).await?;
+    Ok(done)
+}
+
+async fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; READ_BUFFER];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
@@ -0,0 +1,293 @@
+// A record of every LLM request this node took part in: the ones it ran for itself,
+// the ones it sent to peers and the ones it ran for peers. Entries are appended to a
+// JSON Lines file as requests finish and summarized by /api/usage, so the team can see
+// who is leaning on whose hardware. A background task does the writing; in memory the
+// ledger keeps only daily totals and the latest entries.
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+use crate::node::Node;
+
+const LEDGER_FILE: &str = "usage_ledger.jsonl";
+const DEFAULT_LEDGER_LIMIT: usize = 100;
+// Entries kept for /api/usage/ledger; older ones are only in the file
+const RECENT_ENTRIES: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    // When the request was sent
+    pub at: DateTime<Utc>,
+    // Node IDs of whoever asked and whoever ran the model
+    pub requester: String,
+    pub host: String,
+    pub model: String,
+    pub operation: String,
+    pub outcome: String,
+    pub prompt_tokens: Option<u64>,
+    pub completion_tokens: Option<u64>,
+    // As measured here, from sending the request to the end of the answer
+    pub duration_ms: u64,
+    // As reported by the server
+    pub server_total_ms: Option<u64>,
+    pub server_eval_ms: Option<u64>,
+}
+
+// Day, direction, peer and model
+type DayKey = (NaiveDate, &'static str, String, String);
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Totals {
+    requests: u64,
+    failed: u64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    duration_ms: u64,
+    server_eval_ms: u64,
+}
+
+#[derive(Default)]
+struct Summary {
+    days: BTreeMap<DayKey, Totals>,
+    // Newest last
+    recent: VecDeque<LedgerEntry>,
+}
+
+enum Write {
+    Entry(LedgerEntry),
+    // Answered once everything sent before it is on disk
+    Flush(oneshot::Sender<()>),
+}
+
+pub struct Ledger {
+    local_id: String,
+    writes: mpsc::UnboundedSender<Write>,
+    summary: Mutex<Summary>,
+}
+
+impl Ledger {
+    // Entries from earlier runs are summed up again; lines that do not parse are skipped
+    pub async fn open(dir: &Path, local_id: &str) -> Arc<Ledger> {
+        let path = dir.join(LEDGER_FILE);
+        let (writes, queue) = mpsc::unbounded_channel();
+        let ledger = Ledger { local_id: local_id.to_string(), writes, summary: Mutex::new(Summary::default()) };
+        if let Ok(file) = fs::File::open(&path).await {
+            let mut lines = BufReader::new(file).lines();
+            let mut skipped = 0;
+            while let Ok(Some(line)) = lines.next_line().await {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str(&line) {
+                    Ok(entry) => ledger.add(entry),
+                    Err(_) => skipped += 1,
+                }
+            }
+            if skipped > 0 {
+                warn!("Skipped {} unreadable lines in the usage ledger", skipped);
+            }
+        }
+        tokio::spawn(write_entries(path, queue));
+        Arc::new(ledger)
+    }
+
+    // Called as requests finish, possibly from a drop, so it never waits on the file
+    pub fn record(&self, entry: LedgerEntry) {
+        self.add(entry.clone());
+        if self.writes.send(Write::Entry(entry)).is_err() {
+            warn!("Failed to write to the usage ledger: the writer has stopped");
+        }
+    }
+
+    // Waits until every entry recorded so far is on disk
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.writes.send(Write::Flush(done)).is_ok() {
+            let _ = flushed.await;
+        }
+    }
+
+    fn add(&self, entry: LedgerEntry) {
+        let key = (entry.at.date_naive(), self.direction(&entry), self.peer(&entry).to_string(), entry.model.clone());
+        let mut summary = self.summary.lock().unwrap();
+        let totals = summary.days.entry(key).or_default();
+        totals.requests += 1;
+        if entry.outcome != "ok" {
+            totals.failed += 1;
+        }
+        totals.prompt_tokens += entry.prompt_tokens.unwrap_or(0);
+        totals.completion_tokens += entry.completion_tokens.unwrap_or(0);
+        totals.duration_ms += entry.duration_ms;
+        totals.server_eval_ms += entry.server_eval_ms.unwrap_or(0);
+        if summary.recent.len() == RECENT_ENTRIES {
+            summary.recent.pop_front();
+        }
+        summary.recent.push_back(entry);
+    }
+
+    // "local" for requests this node ran for itself, "consumed" for those a peer ran
+    // for us, "served" for those we ran for a peer
+    fn direction(&self, entry: &LedgerEntry) -> &'static str {
+        match (entry.requester == self.local_id, entry.host == self.local_id) {
+            (true, true) => "local",
+            (true, false) => "consumed",
+            _ => "served",
+        }
+    }
+
+    // The other party, or "local"
+    fn peer<'a>(&self, entry: &'a LedgerEntry) -> &'a str {
+        if entry.requester != self.local_id {
+            &entry.requester
+        } else if entry.host != self.local_id {
+            &entry.host
+        } else {
+            "local"
+        }
+    }
+}
+
+// Appends entries as they come, flushing whenever the queue runs dry so a crash loses
+// little
+async fn write_entries(path: PathBuf, mut queue: mpsc::UnboundedReceiver<Write>) {
+    let file = match OpenOptions::new().create(true).append(true).open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("Cannot open the usage ledger {}: {}", path.display(), e);
+            return;
+        }
+    };
+    let mut file = BufWriter::new(file);
+    while let Some(write) = queue.recv().await {
+        let written = match write {
+            Write::Entry(entry) => match serde_json::to_vec(&entry) {
+                Ok(mut line) => {
+                    line.push(b'\n');
+                    file.write_all(&line).await
+                }
+                Err(e) => Err(e.into()),
+            },
+            Write::Flush(done) => {
+                let flushed = file.flush().await;
+                let _ = done.send(());
+                flushed
+            }
+        };
+        let written = match written {
+            Ok(()) if queue.is_empty() => file.flush().await,
+            other => other,
+        };
+        if let Err(e) = written {
+            warn!("Failed to write to the usage ledger: {}", e);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UsageQuery {
+    // Comma-separated: peer, model, day, direction
+    group_by: Option<String>,
+    direction: Option<String>,
+    // Inclusive UTC days, YYYY-MM-DD
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct UsageRow {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    day: Option<NaiveDate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    direction: Option<&'static str>,
+    requests: u64,
+    failed: u64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    duration_ms: u64,
+    server_eval_ms: u64,
+}
+
+const GROUPS: [&str; 4] = ["peer", "model", "day", "direction"];
+
+// Peer, model, day and direction, each None unless grouped by
+type GroupKey = (Option<String>, Option<String>, Option<NaiveDate>, Option<&'static str>);
+
+fn bad_request(message: String) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({ "error": message }))
+}
+
+// Requests and tokens summed per group, in group order
+#[get("/usage")]
+pub async fn usage_report(node: web::Data<Node>, query: web::Query<UsageQuery>) -> HttpResponse {
+    let group_by: Vec<&str> = query.group_by.as_deref().unwrap_or("peer")
+        .split(',')
+        .map(str::trim)
+        .filter(|group| !group.is_empty())
+        .collect();
+    if let Some(unknown) = group_by.iter().find(|group| !GROUPS.contains(group)) {
+        return bad_request(format!("Cannot group by {:?}; use {}", unknown, GROUPS.join(", ")));
+    }
+    if let Some(direction) = query.direction.as_deref().filter(|d| !["local", "consumed", "served"].contains(d)) {
+        return bad_request(format!("Unknown direction {:?}; use local, consumed or served", direction));
+    }
+
+    let mut rows: BTreeMap<GroupKey, UsageRow> = BTreeMap::new();
+    for ((day, direction, peer, model), totals) in node.ledger.summary.lock().unwrap().days.iter() {
+        let (day, direction) = (*day, *direction);
+        if query.direction.as_deref().is_some_and(|wanted| wanted != direction)
+            || query.since.is_some_and(|since| day < since)
+            || query.until.is_some_and(|until| day > until)
+        {
+            continue;
+        }
+        let key = (
+            group_by.contains(&"peer").then(|| peer.clone()),
+            group_by.contains(&"model").then(|| model.clone()),
+            group_by.contains(&"day").then_some(day),
+            group_by.contains(&"direction").then_some(direction),
+        );
+        let row = rows.entry(key.clone()).or_insert_with(|| UsageRow {
+            peer: key.0,
+            model: key.1,
+            day: key.2,
+            direction: key.3,
+            ..Default::default()
+        });
+        row.requests += totals.requests;
+        row.failed += totals.failed;
+        row.prompt_tokens += totals.prompt_tokens;
+        row.completion_tokens += totals.completion_tokens;
+        row.duration_ms += totals.duration_ms;
+        row.server_eval_ms += totals.server_eval_ms;
+    }
+    HttpResponse::Ok().json(rows.into_values().collect::<Vec<_>>())
+}
+
+#[derive(Deserialize)]
+pub struct LedgerQuery {
+    limit: Option<usize>,
+}
+
+// The most recent entries, newest first, up to the last RECENT_ENTRIES
+#[get("/usage/ledger")]
+pub async fn recent_entries(node: web::Data<Node>, query: web::Query<LedgerQuery>) -> HttpResponse {
+    let limit = query.limit.unwrap_or(DEFAULT_LEDGER_LIMIT);
+    let entries: Vec<serde_json::Value> = node.ledger.summary.lock().unwrap().recent.iter().rev().take(limit)
+        .map(|entry| {
+            let mut value = serde_json::to_value(entry).unwrap_or_default();
+            value["direction"] = node.ledger.direction(entry).into();
+            value
+        })
+        .collect();
+    HttpResponse::Ok().json(entries)
+}
@@ -0,0 +1,23 @@
+pub mod cli;
+pub mod config;
+mod conversation;
+mod events;
+mod files;
+mod ip;
+mod ledger;
+mod llm;
+pub mod logging;
+mod mesh;
+mod metrics;
+mod node;
+mod peers;
+mod persistence;
+mod sandbox;
+mod server;
+mod supervisor;
+mod tcp;
+mod udp;
+
+pub use config::{Config, ConfigArgs};
+pub use node::Node;
+pub use llm::{ChatDelta, ChatDeltaStream, LlmBackend, LlmMessage, LlmRequest};
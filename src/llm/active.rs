@@ -0,0 +1,200 @@
+// LLM requests running on this node, for our own clients or for peers, by request ID
+// so they can be listed and cancelled. Cancelling drops the work in progress: the
+// connection to the LLM server closes, which stops generation, and a request that went
+// to a peer tells that peer to stop too.
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use crate::node::Node;
+use crate::server::{is_operator, operator_only};
+
+pub const CANCELLED: &str = "Request cancelled";
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_CLIENT_ID_LEN: usize = 64;
+
+pub fn request_id() -> String {
+    format!("req-{}", hex::encode(rand::random::<[u8; 8]>()))
+}
+
+fn client_request_id(http: &HttpRequest) -> Option<String> {
+    let id = http.headers().get(REQUEST_ID_HEADER)?.to_str().ok()?;
+    let usable = !id.is_empty()
+        && id.len() <= MAX_CLIENT_ID_LEN
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    usable.then(|| id.to_string())
+}
+
+// Names the request in its response, so the client can cancel it while it runs
+pub fn tagged(mut response: HttpResponse, id: &str) -> HttpResponse {
+    if let Ok(value) = HeaderValue::from_str(id) {
+        response.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+    response
+}
+
+// For a client still waiting when its request is cancelled
+pub fn cancelled_response(id: &str) -> HttpResponse {
+    HttpResponse::build(StatusCode::CONFLICT).json(serde_json::json!({ "error": CANCELLED, "request_id": id }))
+}
+
+struct Running {
+    // "local" for our own clients, otherwise the node ID of the peer that asked
+    requester: String,
+    // Address of the client that asked, which may cancel the request; None for a peer
+    client: Option<IpAddr>,
+    model: String,
+    started_at: DateTime<Utc>,
+    cancel: CancellationToken,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RequestSummary {
+    pub id: String,
+    pub requester: String,
+    pub model: String,
+    pub started_at: DateTime<Utc>,
+}
+
+pub struct ActiveRequests {
+    running: Mutex<HashMap<String, Running>>,
+}
+
+impl ActiveRequests {
+    pub fn new() -> Arc<Self> {
+        Arc::new(ActiveRequests { running: Mutex::new(HashMap::new()) })
+    }
+
+    // The request stays listed until the returned handle is dropped. None when a
+    // request with this ID is already running.
+    pub fn begin(self: &Arc<Self>, id: String, requester: &str, client: Option<IpAddr>, model: &str) -> Option<ActiveRequest> {
+        let cancel = CancellationToken::new();
+        match self.running.lock().unwrap().entry(id.clone()) {
+            Entry::Occupied(_) => return None,
+            Entry::Vacant(entry) => entry.insert(Running {
+                requester: requester.to_string(),
+                client,
+                model: model.to_string(),
+                started_at: Utc::now(),
+                cancel: cancel.clone(),
+            }),
+        };
+        Some(ActiveRequest { requests: self.clone(), id, cancel })
+    }
+
+    // A request from our own clients, under the ID the client chose in X-Request-Id so
+    // it can cancel a request it has no answer for yet. A client that chose none, or
+    // one that is unusable or taken, gets a fresh one from `fresh`.
+    pub fn begin_local(self: &Arc<Self>, http: &HttpRequest, model: &str, fresh: fn() -> String) -> ActiveRequest {
+        let client = http.peer_addr().map(|addr| addr.ip());
+        if let Some(request) = client_request_id(http).and_then(|id| self.begin(id, "local", client, model)) {
+            return request;
+        }
+        loop {
+            if let Some(request) = self.begin(fresh(), "local", client, model) {
+                return request;
+            }
+        }
+    }
+
+    // False when no such request is running
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.running.lock().unwrap().get(id) {
+            Some(running) => {
+                running.cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Cancels request `id` if `allowed` says so, given the address of the client that
+    // started it. None when no such request is running.
+    pub fn cancel_if(&self, id: &str, allowed: impl FnOnce(Option<IpAddr>) -> bool) -> Option<bool> {
+        let running = self.running.lock().unwrap();
+        let running = running.get(id)?;
+        let allowed = allowed(running.client);
+        if allowed {
+            running.cancel.cancel();
+        }
+        Some(allowed)
+    }
+
+    // Everything a peer asked of us, once its session has closed
+    pub fn cancel_from(&self, requester: &str) {
+        for running in self.running.lock().unwrap().values().filter(|running| running.requester == requester) {
+            running.cancel.cancel();
+        }
+    }
+
+    // Oldest first
+    pub fn list(&self) -> Vec<RequestSummary> {
+        let mut requests: Vec<RequestSummary> = self.running.lock().unwrap().iter()
+            .map(|(id, running)| RequestSummary {
+                id: id.clone(),
+                requester: running.requester.clone(),
+                model: running.model.clone(),
+                started_at: running.started_at,
+            })
+            .collect();
+        requests.sort_by(|a, b| a.started_at.cmp(&b.started_at).then_with(|| a.id.cmp(&b.id)));
+        requests
+    }
+}
+
+pub struct ActiveRequest {
+    requests: Arc<ActiveRequests>,
+    pub id: String,
+    cancel: CancellationToken,
+}
+
+impl ActiveRequest {
+    pub async fn cancelled(&self) {
+        self.cancel.cancelled().await
+    }
+
+    // Runs `work` unless the request is cancelled first, in which case `work` is dropped
+    pub async fn run<T>(&self, work: impl Future<Output = Result<T, String>>) -> Result<T, String> {
+        tokio::select! {
+            result = work => result,
+            _ = self.cancel.cancelled() => Err(CANCELLED.to_string()),
+        }
+    }
+}
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        self.requests.running.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[get("/requests")]
+pub async fn list_requests(node: web::Data<Node>) -> HttpResponse {
+    HttpResponse::Ok().json(node.requests.list())
+}
+
+// Open to the operator and to the client that started the request
+#[post("/requests/{id}/cancel")]
+pub async fn cancel_request(node: web::Data<Node>, path: web::Path<String>, http: HttpRequest) -> HttpResponse {
+    let id = path.into_inner();
+    let caller = http.peer_addr().map(|addr| addr.ip());
+    match node.requests.cancel_if(&id, |client| client.is_some_and(|client| Some(client) == caller) || is_operator(&node, &http)) {
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "No such request", "id": id })),
+        Some(false) => {
+            warn!(client = ?caller, request_id = %id, "Refused to cancel another client's LLM request");
+            operator_only()
+        }
+        Some(true) => {
+            info!(request_id = %id, "Cancelled LLM request");
+            HttpResponse::Ok().json(serde_json::json!({ "id": id, "cancelled": true }))
+        }
+    }
+}
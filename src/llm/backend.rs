@@ -0,0 +1,603 @@
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use crate::config::BackendKind;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(2);
+const MODELS_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_TEMPERATURE: f32 = 2.0;
+// As many as the OpenAI API takes
+const MAX_STOP_SEQUENCES: usize = 4;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LlmMessage {
+    pub role: String,
+    pub content: String,
+}
+
+// What the answer must be instead of free text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Json,
+}
+
+// How the answer is generated; None leaves the server's default. OpenAI-compatible
+// servers have no `num_ctx` or `keep_alive`, so those only reach Ollama.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerationOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    // Context window, in tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_ctx: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    // Tokens generated at most
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    // How long the server keeps the model loaded afterwards, in Ollama's terms: "10m",
+    // "1h", "0" to unload it at once, "-1" to keep it loaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<OutputFormat>,
+}
+
+// Seconds in a whole number, optionally negative, with an optional ms, s, m or h unit;
+// a bare number is seconds. Any negative duration comes out as -1, for ever.
+fn duration_secs(value: &str) -> Option<i64> {
+    let (digits, millis) = [("ms", 1), ("s", 1000), ("m", 60_000), ("h", 3_600_000)]
+        .into_iter()
+        .find_map(|(unit, millis)| value.strip_suffix(unit).map(|digits| (digits, millis)))
+        .unwrap_or((value, 1000));
+    let magnitude = digits.strip_prefix('-').unwrap_or(digits);
+    if magnitude.is_empty() || !magnitude.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let number: i64 = digits.parse().ok()?;
+    Some(if number < 0 { -1 } else { number.saturating_mul(millis) / 1000 })
+}
+
+// Ollama parses a string keep_alive as a Go duration, which needs a unit
+fn ollama_duration(value: &str) -> String {
+    if value.trim_start_matches('-').chars().all(|c| c.is_ascii_digit()) {
+        format!("{}s", value)
+    } else {
+        value.to_string()
+    }
+}
+
+impl GenerationOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(temperature) = self.temperature {
+            if !(0.0..=MAX_TEMPERATURE).contains(&temperature) {
+                return Err(format!("temperature must be between 0 and {}", MAX_TEMPERATURE));
+            }
+        }
+        if let Some(top_p) = self.top_p {
+            if !(0.0..=1.0).contains(&top_p) {
+                return Err("top_p must be between 0 and 1".to_string());
+            }
+        }
+        if self.num_ctx == Some(0) || self.max_tokens == Some(0) {
+            return Err("num_ctx and max_tokens must be at least 1".to_string());
+        }
+        if self.stop.len() > MAX_STOP_SEQUENCES {
+            return Err(format!("At most {} stop sequences are allowed", MAX_STOP_SEQUENCES));
+        }
+        if self.stop.iter().any(|stop| stop.is_empty()) {
+            return Err("Stop sequences must not be empty".to_string());
+        }
+        if let Some(keep_alive) = &self.keep_alive {
+            if duration_secs(keep_alive).is_none() {
+                return Err(format!("Invalid keep_alive {:?}; use a duration such as \"10m\", \"0\" or \"-1\"", keep_alive));
+            }
+        }
+        Ok(())
+    }
+
+    // How long `keep_alive` asks for the model to stay loaded, in seconds; -1 for ever
+    pub fn keep_alive_secs(&self) -> Option<i64> {
+        self.keep_alive.as_deref().and_then(duration_secs)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LlmRequest {
+    pub model: String,
+    pub messages: Vec<LlmMessage>,
+    pub options: GenerationOptions,
+    // Generate afresh even if a cached answer exists; the new answer replaces it
+    pub no_cache: bool,
+}
+
+// Token counts a server reports at the end of an answer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    // Time the server spent on the whole request and on generating, in milliseconds.
+    // Only Ollama reports them.
+    #[serde(default)]
+    pub total_duration_ms: Option<u64>,
+    #[serde(default)]
+    pub eval_duration_ms: Option<u64>,
+}
+
+// One piece of a streamed answer; the stream ends after the piece with `done` set
+#[derive(Debug, Clone)]
+pub struct ChatDelta {
+    pub content: String,
+    pub done: bool,
+    // Only on the last piece, and only from servers that report it
+    pub usage: Option<TokenUsage>,
+}
+
+pub type ChatDeltaStream = BoxStream<'static, Result<ChatDelta, String>>;
+
+// Tokens an answer generated, however it ended: what the server reported if it got
+// that far, otherwise one per piece streamed, as servers stream a token at a time
+#[derive(Debug, Default)]
+pub struct Tally {
+    pieces: u64,
+    usage: Option<TokenUsage>,
+}
+
+impl Tally {
+    pub fn add(&mut self, delta: &ChatDelta) {
+        if !delta.content.is_empty() {
+            self.pieces += 1;
+        }
+        if delta.usage.is_some() {
+            self.usage = delta.usage;
+        }
+    }
+
+    pub fn tokens(&self) -> u64 {
+        self.usage.map_or(self.pieces, |usage| usage.completion_tokens)
+    }
+
+    // What an answer that broke off before the server's report got through, if anything
+    pub fn partial(&self) -> Option<u64> {
+        (self.usage.is_none() && self.pieces > 0).then_some(self.pieces)
+    }
+}
+
+// A server that runs models for us: the local one from the configuration, or one a
+// peer granted us access to. Errors are plain strings ready for logs and API replies.
+#[async_trait]
+pub trait LlmBackend: Send + Sync {
+    fn kind(&self) -> BackendKind;
+
+    fn base_url(&self) -> &str;
+
+    async fn health(&self) -> bool;
+
+    async fn list_models(&self) -> Result<Vec<String>, String>;
+
+    async fn stream(&self, req: &LlmRequest) -> Result<ChatDeltaStream, String>;
+
+    // One vector per input, in input order
+    async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>, String>;
+
+    // Whole answer at once, collected from the stream
+    async fn chat(&self, req: &LlmRequest) -> Result<String, String> {
+        let mut stream = self.stream(req).await?;
+        let mut full_response = String::new();
+        let mut complete = false;
+        while let Some(delta) = stream.next().await {
+            let delta = delta?;
+            full_response.push_str(&delta.content);
+            if delta.done {
+                complete = true;
+                break;
+            }
+        }
+
+        if !complete {
+            return Err("Incomplete response from LLM".to_string());
+        }
+        if full_response.trim().is_empty() {
+            return Err("Empty response from LLM".to_string());
+        }
+        Ok(full_response)
+    }
+}
+
+pub fn connect(kind: BackendKind, base_url: &str, api_key: Option<String>) -> Arc<dyn LlmBackend> {
+    let client = Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .build()
+        .unwrap_or_default();
+    let base_url = base_url.trim_end_matches('/').to_string();
+    match kind {
+        BackendKind::Ollama => Arc::new(OllamaBackend { client, base_url }),
+        BackendKind::OpenAi => Arc::new(OpenAiBackend { client, base_url, api_key }),
+    }
+}
+
+// LLM server a peer granted us access to, used through our session with it. `host` is
+// the peer's address and `backend` the kind of server it announced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LlmEndpoint {
+    pub host: String,
+    pub backend: BackendKind,
+}
+
+async fn send_json<T: serde::de::DeserializeOwned>(request: RequestBuilder) -> Result<T, String> {
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("LLM server returned {}", response.status()));
+    }
+    response.json().await.map_err(|e| e.to_string())
+}
+
+async fn open_stream(request: RequestBuilder) -> Result<reqwest::Response, String> {
+    let response = request.send().await
+        .map_err(|e| format!("Failed to connect to LLM: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("LLM error: {}", response.status()));
+    }
+    Ok(response)
+}
+
+// Splits a streamed body into lines and turns each into at most one delta with
+// `parse`. Ends after the first `done` delta, or with an error if the body breaks off.
+fn delta_stream(response: reqwest::Response, parse: fn(&[u8]) -> Option<ChatDelta>) -> ChatDeltaStream {
+    struct State {
+        body: BoxStream<'static, reqwest::Result<actix_web::web::Bytes>>,
+        buffer: Vec<u8>,
+        finished: bool,
+    }
+
+    let state = State { body: response.bytes_stream().boxed(), buffer: Vec::new(), finished: false };
+    futures::stream::unfold(state, move |mut state| async move {
+        loop {
+            if state.finished {
+                return None;
+            }
+            if let Some(pos) = state.buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = state.buffer.drain(..=pos).collect();
+                if let Some(delta) = parse(&line) {
+                    state.finished = delta.done;
+                    return Some((Ok(delta), state));
+                }
+                continue;
+            }
+            match state.body.next().await {
+                Some(Ok(bytes)) => state.buffer.extend_from_slice(&bytes),
+                Some(Err(e)) => {
+                    state.finished = true;
+                    return Some((Err(format!("LLM stream failed: {}", e)), state));
+                }
+                None => {
+                    // A last line without a trailing newline still counts
+                    state.finished = true;
+                    let line = std::mem::take(&mut state.buffer);
+                    return parse(&line).map(|delta| (Ok(delta), state));
+                }
+            }
+        }
+    }).boxed()
+}
+
+struct OllamaBackend {
+    client: Client,
+    base_url: String,
+}
+
+#[derive(Serialize)]
+struct OllamaChatRequest<'a> {
+    model: &'a str,
+    messages: &'a [LlmMessage],
+    #[serde(skip_serializing_if = "OllamaOptions::is_empty")]
+    options: OllamaOptions<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<OutputFormat>,
+}
+
+// Sampling settings, which Ollama takes apart from the request itself
+#[derive(Serialize)]
+struct OllamaOptions<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_ctx: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    stop: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<u32>,
+}
+
+impl<'a> OllamaOptions<'a> {
+    fn new(options: &'a GenerationOptions) -> Self {
+        OllamaOptions {
+            temperature: options.temperature,
+            top_p: options.top_p,
+            num_ctx: options.num_ctx,
+            seed: options.seed,
+            stop: &options.stop,
+            num_predict: options.max_tokens,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.temperature.is_none() && self.top_p.is_none() && self.num_ctx.is_none()
+            && self.seed.is_none() && self.stop.is_empty() && self.num_predict.is_none()
+    }
+}
+
+#[derive(Deserialize)]
+struct OllamaChatResponse {
+    message: LlmMessage,
+    done: bool,
+    #[serde(default)]
+    prompt_eval_count: Option<u64>,
+    #[serde(default)]
+    eval_count: Option<u64>,
+    // Nanoseconds
+    #[serde(default)]
+    total_duration: Option<u64>,
+    #[serde(default)]
+    eval_duration: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct OllamaTags {
+    models: Vec<OllamaModel>,
+}
+
+#[derive(Deserialize)]
+struct OllamaModel {
+    name: String,
+}
+
+#[derive(Serialize)]
+struct OllamaEmbedRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct OllamaEmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+fn parse_ollama_line(line: &[u8]) -> Option<ChatDelta> {
+    let resp: OllamaChatResponse = serde_json::from_slice(line).ok()?;
+    let usage = resp.eval_count.map(|completion_tokens| TokenUsage {
+        prompt_tokens: resp.prompt_eval_count.unwrap_or(0),
+        completion_tokens,
+        total_duration_ms: resp.total_duration.map(|ns| ns / 1_000_000),
+        eval_duration_ms: resp.eval_duration.map(|ns| ns / 1_000_000),
+    });
+    Some(ChatDelta { content: resp.message.content, done: resp.done, usage })
+}
+
+#[async_trait]
+impl LlmBackend for OllamaBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Ollama
+    }
+
+    fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    // Ollama answers its root path once it is up
+    async fn health(&self) -> bool {
+        match self.client.get(&self.base_url).timeout(HEALTH_TIMEOUT).send().await {
+            Ok(response) => response.status().is_success(),
+            Err(_) => false,
+        }
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, String> {
+        let request = self.client.get(format!("{}/api/tags", self.base_url)).timeout(MODELS_TIMEOUT);
+        let tags: OllamaTags = send_json(request).await?;
+        Ok(tags.models.into_iter().map(|model| model.name).collect())
+    }
+
+    async fn stream(&self, req: &LlmRequest) -> Result<ChatDeltaStream, String> {
+        let body = OllamaChatRequest {
+            model: &req.model,
+            messages: &req.messages,
+            options: OllamaOptions::new(&req.options),
+            keep_alive: req.options.keep_alive.as_deref().map(ollama_duration),
+            format: req.options.format,
+        };
+        let response = open_stream(self.client.post(format!("{}/api/chat", self.base_url)).json(&body)).await?;
+        Ok(delta_stream(response, parse_ollama_line))
+    }
+
+    async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        let body = OllamaEmbedRequest { model, input: inputs };
+        let resp: OllamaEmbedResponse = send_json(self.client.post(format!("{}/api/embed", self.base_url)).json(&body)).await?;
+        if resp.embeddings.len() != inputs.len() {
+            return Err(format!("Expected {} embeddings, got {}", inputs.len(), resp.embeddings.len()));
+        }
+        Ok(resp.embeddings)
+    }
+}
+
+// llama.cpp's server, vLLM and anything else speaking the OpenAI REST API under /v1
+struct OpenAiBackend {
+    client: Client,
+    base_url: String,
+    api_key: Option<String>,
+}
+
+#[derive(Serialize)]
+struct OpenAiChatRequest<'a> {
+    model: &'a str,
+    messages: &'a [LlmMessage],
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    stop: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<serde_json::Value>,
+    stream_options: OpenAiStreamOptions,
+}
+
+#[derive(Serialize)]
+struct OpenAiStreamOptions {
+    // Token counts on a last chunk of their own, after the finish reason
+    include_usage: bool,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChunk {
+    #[serde(default)]
+    choices: Vec<OpenAiChunkChoice>,
+    // Some servers put it on the chunk with the finish reason
+    #[serde(default)]
+    usage: Option<TokenUsage>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChunkChoice {
+    #[serde(default)]
+    delta: OpenAiDelta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct OpenAiDelta {
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiModels {
+    data: Vec<OpenAiModel>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiModel {
+    id: String,
+}
+
+#[derive(Serialize)]
+struct OpenAiEmbedRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbedResponse {
+    data: Vec<OpenAiEmbedding>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbedding {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+// Server-sent events: "data: {chunk}" lines, closed by "data: [DONE]". The answer is
+// done once the usage arrives, which may come after the finish reason, or at [DONE]
+// from servers that never send it.
+fn parse_openai_line(line: &[u8]) -> Option<ChatDelta> {
+    let line = std::str::from_utf8(line).ok()?.trim();
+    let data = line.strip_prefix("data:")?.trim();
+    if data == "[DONE]" {
+        return Some(ChatDelta { content: String::new(), done: true, usage: None });
+    }
+    let chunk: OpenAiChunk = serde_json::from_str(data).ok()?;
+    let choice = chunk.choices.into_iter().next();
+    if choice.is_none() && chunk.usage.is_none() {
+        return None;
+    }
+    // Servers that report usage as they go only mean the last report
+    let finished = choice.as_ref().is_none_or(|choice| choice.finish_reason.is_some());
+    Some(ChatDelta {
+        content: choice.and_then(|choice| choice.delta.content).unwrap_or_default(),
+        done: finished && chunk.usage.is_some(),
+        usage: chunk.usage,
+    })
+}
+
+impl OpenAiBackend {
+    fn request(&self, method: reqwest::Method, path: &str) -> RequestBuilder {
+        let request = self.client.request(method, format!("{}/v1/{}", self.base_url, path));
+        match &self.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        }
+    }
+}
+
+#[async_trait]
+impl LlmBackend for OpenAiBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::OpenAi
+    }
+
+    fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    async fn health(&self) -> bool {
+        match self.request(reqwest::Method::GET, "models").timeout(HEALTH_TIMEOUT).send().await {
+            Ok(response) => response.status().is_success(),
+            Err(_) => false,
+        }
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, String> {
+        let models: OpenAiModels = send_json(self.request(reqwest::Method::GET, "models").timeout(MODELS_TIMEOUT)).await?;
+        Ok(models.data.into_iter().map(|model| model.id).collect())
+    }
+
+    async fn stream(&self, req: &LlmRequest) -> Result<ChatDeltaStream, String> {
+        let options = &req.options;
+        let body = OpenAiChatRequest {
+            model: &req.model,
+            messages: &req.messages,
+            stream: true,
+            temperature: options.temperature,
+            top_p: options.top_p,
+            seed: options.seed,
+            stop: &options.stop,
+            max_tokens: options.max_tokens,
+            response_format: options.format.map(|OutputFormat::Json| serde_json::json!({ "type": "json_object" })),
+            stream_options: OpenAiStreamOptions { include_usage: true },
+        };
+        let response = open_stream(self.request(reqwest::Method::POST, "chat/completions").json(&body)).await?;
+        Ok(delta_stream(response, parse_openai_line))
+    }
+
+    async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        let body = OpenAiEmbedRequest { model, input: inputs };
+        let mut resp: OpenAiEmbedResponse = send_json(self.request(reqwest::Method::POST, "embeddings").json(&body)).await?;
+        if resp.data.len() != inputs.len() {
+            return Err(format!("Expected {} embeddings, got {}", inputs.len(), resp.data.len()));
+        }
+        resp.data.sort_by_key(|embedding| embedding.index);
+        Ok(resp.data.into_iter().map(|embedding| embedding.embedding).collect())
+    }
+}
@@ -0,0 +1,163 @@
+// Answers to chat requests that should get the same answer every time: the same model,
+// messages and options, at temperature 0. Kept in memory for `cache.ttl_secs`; once
+// `max_entries` or `max_mb` is reached the least recently used answer goes first.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use actix_web::{delete, get, web, HttpResponse};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+use crate::config::Config;
+use crate::metrics::Metrics;
+use crate::node::Node;
+use crate::server::Operator;
+use super::{GenerationOptions, LlmRequest};
+
+struct Entry {
+    answer: String,
+    stored_at: Instant,
+    last_used: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<String, Entry>,
+    // Answers together
+    bytes: usize,
+    hits: u64,
+    misses: u64,
+}
+
+impl State {
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.bytes -= entry.answer.len();
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CacheStats {
+    pub enabled: bool,
+    pub entries: usize,
+    pub bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+pub struct ResponseCache {
+    enabled: bool,
+    ttl: Duration,
+    max_entries: usize,
+    max_bytes: usize,
+    share_with: Vec<String>,
+    metrics: Arc<Metrics>,
+    state: Mutex<State>,
+}
+
+impl ResponseCache {
+    pub fn new(config: &Config, metrics: Arc<Metrics>) -> Arc<Self> {
+        Arc::new(ResponseCache {
+            enabled: config.cache.enabled,
+            ttl: config.cache_ttl(),
+            max_entries: config.cache.max_entries,
+            max_bytes: config.cache_max_bytes(),
+            share_with: config.cache.share_with.clone(),
+            metrics,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    // None for requests that may be answered differently each time. How long the model
+    // stays loaded does not change the answer, so it is left out.
+    fn key(&self, req: &LlmRequest) -> Option<String> {
+        if !self.enabled || req.options.temperature != Some(0.0) {
+            return None;
+        }
+        let options = GenerationOptions { keep_alive: None, ..req.options.clone() };
+        let request = serde_json::to_vec(&(&req.model, &req.messages, &options)).ok()?;
+        Some(hex::encode(Sha256::digest(&request)))
+    }
+
+    pub fn get(&self, req: &LlmRequest) -> Option<String> {
+        if req.no_cache {
+            return None;
+        }
+        let key = self.key(req)?;
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let answer = match state.entries.get_mut(&key) {
+            Some(entry) if now.duration_since(entry.stored_at) < self.ttl => {
+                entry.last_used = now;
+                Some(entry.answer.clone())
+            }
+            Some(_) => {
+                state.remove(&key);
+                None
+            }
+            None => None,
+        };
+        match answer {
+            Some(_) => state.hits += 1,
+            None => state.misses += 1,
+        }
+        self.metrics.cache_lookup(answer.is_some());
+        answer
+    }
+
+    // Replaces any answer already kept for the same request
+    pub fn put(&self, req: &LlmRequest, answer: &str) {
+        let Some(key) = self.key(req) else { return };
+        if answer.len() > self.max_bytes {
+            debug!(bytes = answer.len(), "Answer too large to cache");
+            return;
+        }
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.remove(&key);
+        while state.entries.len() >= self.max_entries || state.bytes + answer.len() > self.max_bytes {
+            let Some(oldest) = state.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(key, _)| key.clone()) else { break };
+            state.remove(&oldest);
+        }
+        state.bytes += answer.len();
+        state.entries.insert(key, Entry { answer: answer.to_string(), stored_at: now, last_used: now });
+    }
+
+    // Whether requests from `peer` may be answered from the cache, and its answers kept
+    pub fn shared_with(&self, peer: &str) -> bool {
+        self.enabled && self.share_with.iter().any(|trusted| trusted == peer)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock().unwrap();
+        CacheStats {
+            enabled: self.enabled,
+            entries: state.entries.len(),
+            bytes: state.bytes,
+            hits: state.hits,
+            misses: state.misses,
+        }
+    }
+
+    // Number of answers dropped
+    pub fn clear(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let cleared = state.entries.len();
+        state.entries.clear();
+        state.bytes = 0;
+        cleared
+    }
+}
+
+#[get("/cache")]
+pub async fn cache_stats(node: web::Data<Node>) -> HttpResponse {
+    HttpResponse::Ok().json(node.cache.stats())
+}
+
+#[delete("/cache")]
+pub async fn clear_cache(node: web::Data<Node>, _: Operator) -> HttpResponse {
+    let cleared = node.cache.clear();
+    info!(entries = cleared, "Cleared the response cache");
+    HttpResponse::Ok().json(serde_json::json!({ "cleared": cleared }))
+}
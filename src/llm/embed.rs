@@ -0,0 +1,101 @@
+// Embeddings for a batch of texts, spread over every endpoint that hosts the model
+use std::sync::Arc;
+use std::time::Duration;
+use actix_web::{post, web, HttpResponse, Error};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use crate::node::Node;
+use super::{model_endpoints, LlmBackend};
+
+// Texts per request to one host; bigger batches are split and shared out
+const EMBED_BATCH_SIZE: usize = 32;
+
+#[derive(Serialize, Deserialize)]
+pub struct EmbedRequest {
+    pub model: String,
+    pub input: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EmbedResponse {
+    pub model: String,
+    // One per input, in input order
+    pub embeddings: Vec<Vec<f32>>,
+}
+
+// Tries the batch's assigned host first, then the others in order
+async fn embed_with_fallback(endpoints: &[(String, Arc<dyn LlmBackend>)], first: usize, model: &str, batch: &[String], timeout: Duration) -> Result<Vec<Vec<f32>>, String> {
+    let mut errors = Vec::new();
+    for offset in 0..endpoints.len() {
+        let (host, backend) = &endpoints[(first + offset) % endpoints.len()];
+        match tokio::time::timeout(timeout, backend.embed(model, batch)).await {
+            Ok(Ok(vectors)) => return Ok(vectors),
+            Ok(Err(e)) => errors.push(format!("{}: {}", host, e)),
+            Err(_) => errors.push(format!("{}: timed out", host)),
+        }
+    }
+    Err(errors.join("; "))
+}
+
+// Splits `inputs` into batches dealt round-robin to the endpoints. Each endpoint works
+// through its own batches one at a time while the endpoints run side by side; the
+// vectors are put back in input order.
+pub async fn embed_across(endpoints: &[(String, Arc<dyn LlmBackend>)], model: &str, inputs: &[String], timeout: Duration) -> Result<Vec<Vec<f32>>, String> {
+    if endpoints.is_empty() {
+        return Err(format!("No host has the embedding model {}", model));
+    }
+    let batches: Vec<&[String]> = inputs.chunks(EMBED_BATCH_SIZE).collect();
+
+    let workers = (0..endpoints.len()).map(|worker| {
+        let batches = &batches;
+        async move {
+            let mut results = Vec::new();
+            for (index, batch) in batches.iter().enumerate().skip(worker).step_by(endpoints.len()) {
+                results.push((index, embed_with_fallback(endpoints, worker, model, batch, timeout).await));
+            }
+            results
+        }
+    });
+
+    let mut ordered: Vec<Option<Vec<Vec<f32>>>> = vec![None; batches.len()];
+    for (index, result) in futures::future::join_all(workers).await.into_iter().flatten() {
+        let vectors = result?;
+        if vectors.len() != batches[index].len() {
+            return Err(format!("Expected {} embeddings, got {}", batches[index].len(), vectors.len()));
+        }
+        ordered[index] = Some(vectors);
+    }
+    Ok(ordered.into_iter().flatten().flatten().collect())
+}
+
+#[post("/embed")]
+pub async fn embed(node: web::Data<Node>, req: web::Json<EmbedRequest>) -> Result<HttpResponse, Error> {
+    let req = req.into_inner();
+    if req.input.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": "input must not be empty" })));
+    }
+
+    let endpoints = model_endpoints(&node, &req.model).await;
+    if endpoints.is_empty() {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("Model {} is not available on this node or any peer", req.model)
+        })));
+    }
+
+    let hosts = endpoints.len().min(req.input.len().div_ceil(EMBED_BATCH_SIZE));
+    info!(model = %req.model, "Embedding {} inputs on {} hosts", req.input.len(), hosts);
+    match embed_across(&endpoints, &req.model, &req.input, node.config.remote_timeout()).await {
+        Ok(embeddings) => Ok(HttpResponse::Ok().json(EmbedResponse { model: req.model, embeddings })),
+        Err(e) => Ok(HttpResponse::ServiceUnavailable().json(serde_json::json!({
+            "error": "No available embedding service",
+            "details": e
+        }))),
+    }
+}
+
+// Embeds `inputs` with `model` on whichever hosts have it, for callers that do not
+// care which ones
+pub async fn embed_on_mesh(node: &Node, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>, String> {
+    let endpoints = model_endpoints(node, model).await;
+    embed_across(&endpoints, model, inputs, node.config.remote_timeout()).await
+}
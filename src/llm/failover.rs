@@ -0,0 +1,87 @@
+// Mid-stream failover: when the host streaming an answer fails partway through, the
+// request moves to another host with the same model. The answer so far goes along as
+// the start of the assistant's reply, so the new host carries on from there rather
+// than starting over. Only Ollama continues a reply handed to it like that; OpenAI-style
+// servers take it as a finished turn, so they only take over answers not yet begun.
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use crate::config::BackendKind;
+use crate::events::Event;
+use crate::node::Node;
+use super::{model_endpoints, ChatDeltaStream, LlmMessage, LlmRequest};
+
+// Sent to streaming clients when their answer moves to another host
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailoverNotice {
+    pub from: String,
+    pub to: String,
+}
+
+// The request with `partial` as the start of the assistant's reply
+fn continuation(req: &LlmRequest, partial: &str) -> LlmRequest {
+    let mut req = req.clone();
+    if !partial.is_empty() {
+        req.messages.push(LlmMessage { role: "assistant".to_string(), content: partial.to_string() });
+    }
+    req
+}
+
+// Where one streamed answer is coming from, and where it has failed so far
+pub struct Failover {
+    request_id: String,
+    // "local" or the node ID of the peer streaming the answer
+    host: String,
+    failed: Vec<String>,
+}
+
+impl Failover {
+    pub fn new(request_id: &str, host: String) -> Self {
+        Failover { request_id: request_id.to_string(), host, failed: Vec::new() }
+    }
+
+    // Opens the rest of the answer on the next host with the model, once the current
+    // one has failed with `error` after sending `partial`. Errs when no host takes it.
+    pub async fn resume(&mut self, node: &Node, req: &LlmRequest, partial: &str, error: &str) -> Result<(ChatDeltaStream, FailoverNotice), String> {
+        warn!(host = %self.host, chars = partial.chars().count(), "Answer broke off: {}", error);
+        self.failed.push(self.host.clone());
+
+        let continuation = continuation(req, partial);
+        let mut errors = Vec::new();
+        let mut cannot_continue = false;
+        for (host, backend) in model_endpoints(node, &req.model).await {
+            if self.failed.contains(&host) {
+                continue;
+            }
+            if !partial.is_empty() && backend.kind() != BackendKind::Ollama {
+                debug!(%host, "Passed over for failover; its server cannot continue an answer");
+                cannot_continue = true;
+                continue;
+            }
+            match backend.stream(&continuation).await {
+                Ok(stream) => {
+                    info!(from = %self.host, to = %host, "Resumed the answer on another host");
+                    let notice = FailoverNotice { from: std::mem::replace(&mut self.host, host), to: self.host.clone() };
+                    node.events.publish(Event::InferenceFailover {
+                        request_id: self.request_id.clone(),
+                        model: req.model.clone(),
+                        from: notice.from.clone(),
+                        to: notice.to.clone(),
+                        resumed_after_chars: partial.chars().count(),
+                    });
+                    return Ok((stream, notice));
+                }
+                Err(e) => {
+                    errors.push(format!("{}: {}", host, e));
+                    self.failed.push(host);
+                }
+            }
+        }
+        if errors.is_empty() && cannot_continue {
+            return Err(format!("{}; no other host with {} can continue the answer", error, req.model));
+        }
+        if errors.is_empty() {
+            return Err(format!("{}; no other host has {}", error, req.model));
+        }
+        Err(format!("{}; no other host could continue ({})", error, errors.join("; ")))
+    }
+}
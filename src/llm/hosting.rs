@@ -0,0 +1,221 @@
+// The host side of remote inference: a peer's request, received over its session, is
+// checked against the peer's access and limits, then run on our own LLM server with
+// the answer streamed back over the same session. The peer can cancel it by its ID,
+// and it is cancelled when the peer's session closes. Peers the response cache is
+// shared with may be answered from it. A peer's generation options are held to its
+// limits before anything runs. Listing our models is answered straight away, for any
+// peer with access.
+use std::sync::Arc;
+use futures::StreamExt;
+use tokio::sync::mpsc;
+use tracing::{debug, info, Instrument};
+use crate::config::PeerLimits;
+use crate::node::Node;
+use crate::tcp::Message;
+use super::active::{ActiveRequest, CANCELLED};
+use super::remote::{InferenceCall, InferenceReply, InferenceRequest, InferenceResult};
+use super::{GenerationOptions, LlmBackend, LlmRequest, ResponseCache, Tally};
+
+struct Replies {
+    id: u64,
+    outbound: mpsc::Sender<Message>,
+}
+
+impl Replies {
+    // False once the session is gone, so there is nobody left to answer
+    async fn send(&self, result: InferenceResult) -> bool {
+        self.outbound.send(Message::InferenceReply(InferenceReply { id: self.id, result })).await.is_ok()
+    }
+}
+
+// Our ID for request `id` from `peer`; the peer's IDs are only unique among its own
+fn hosted_id(peer: &str, id: u64) -> String {
+    format!("{}:{}", peer, id)
+}
+
+// `peer` and `peer_ip` identify the session the request came in on. The request is
+// listed before this returns, so a cancel that follows it on the session finds it.
+pub fn serve_peer_request(node: Arc<Node>, peer: String, peer_ip: String, request: InferenceRequest, outbound: mpsc::Sender<Message>) {
+    let model = match &request.call {
+        InferenceCall::Chat { model, .. } | InferenceCall::Embed { model, .. } => model.clone(),
+        InferenceCall::Models => {
+            tokio::spawn(serve_models(node, peer_ip, Replies { id: request.id, outbound }).in_current_span());
+            return;
+        }
+    };
+    let replies = Replies { id: request.id, outbound };
+    let Some(active) = node.requests.begin(hosted_id(&peer, request.id), &peer, None, &model) else {
+        let error = format!("Request {} is already running", request.id);
+        tokio::spawn(async move { replies.send(InferenceResult::Failed { error }).await }.in_current_span());
+        return;
+    };
+    tokio::spawn(serve(node, peer, peer_ip, request, replies, active).in_current_span());
+}
+
+// A chat from a peer we share the cache with, answered from it
+fn cached_answer(node: &Node, peer: &str, call: &InferenceCall) -> Option<String> {
+    let InferenceCall::Chat { model, messages, options, no_cache } = call else { return None };
+    if !node.cache.shared_with(peer) {
+        return None;
+    }
+    node.cache.get(&LlmRequest { model: model.clone(), messages: messages.clone(), options: options.clone(), no_cache: *no_cache })
+}
+
+// Cuts a peer's context window, answer length and keep-alive down to its limits, and
+// bounds the answer length even when the peer left it to the server
+fn cap_options(options: &mut GenerationOptions, limits: &PeerLimits) {
+    if limits.max_num_ctx > 0 && options.num_ctx.is_some_and(|num_ctx| num_ctx > limits.max_num_ctx) {
+        debug!(requested = options.num_ctx, "Capped a peer's context window at {}", limits.max_num_ctx);
+        options.num_ctx = Some(limits.max_num_ctx);
+    }
+    if limits.max_tokens > 0 {
+        options.max_tokens = Some(options.max_tokens.map_or(limits.max_tokens, |max_tokens| max_tokens.min(limits.max_tokens)));
+    }
+    match options.keep_alive_secs() {
+        Some(_) if limits.max_keep_alive_secs == 0 => {
+            debug!(requested = ?options.keep_alive, "Ignored a peer's keep_alive");
+            options.keep_alive = None;
+        }
+        Some(secs) if secs < 0 || secs as u64 > limits.max_keep_alive_secs => {
+            debug!(requested = ?options.keep_alive, "Capped a peer's keep_alive at {}s", limits.max_keep_alive_secs);
+            options.keep_alive = Some(format!("{}s", limits.max_keep_alive_secs));
+        }
+        _ => {}
+    }
+}
+
+pub fn cancel_peer_request(node: &Node, peer: &str, id: u64) {
+    if node.requests.cancel(&hosted_id(peer, id)) {
+        debug!("Peer cancelled its LLM request {}", id);
+    }
+}
+
+async fn authorized(node: &Node, peer_ip: &str, replies: &Replies) -> bool {
+    if node.authorized_peers.lock().await.contains(peer_ip) {
+        return true;
+    }
+    replies.send(InferenceResult::Failed { error: "Not authorized to use this node's LLM".to_string() }).await;
+    false
+}
+
+// Runs nothing on the server, so it does not count against the peer's limits
+async fn serve_models(node: Arc<Node>, peer_ip: String, replies: Replies) {
+    if !authorized(&node, &peer_ip, &replies).await {
+        return;
+    }
+    let result = match node.server.list_models().await {
+        Ok(names) => InferenceResult::Models { names },
+        Err(error) => InferenceResult::Failed { error },
+    };
+    replies.send(result).await;
+}
+
+async fn serve(node: Arc<Node>, peer: String, peer_ip: String, mut request: InferenceRequest, replies: Replies, active: ActiveRequest) {
+    if !authorized(&node, &peer_ip, &replies).await {
+        return;
+    }
+
+    let limits = node.config.limits.for_peer(&peer_ip);
+    if let InferenceCall::Chat { options, .. } = &mut request.call {
+        if let Err(error) = options.validate() {
+            replies.send(InferenceResult::Failed { error }).await;
+            return;
+        }
+        cap_options(options, &limits);
+    }
+
+    // Costs our server nothing, so it does not count against the peer's limits
+    if let Some(answer) = cached_answer(&node, &peer, &request.call) {
+        debug!("Answered a peer from the response cache");
+        if replies.send(InferenceResult::Accepted).await {
+            replies.send(InferenceResult::Delta { content: answer, done: true, usage: None }).await;
+        }
+        return;
+    }
+
+    let admission = match node.quotas.admit(&peer_ip, limits) {
+        Ok(admission) => admission,
+        Err(exceeded) => {
+            info!(retry_after_secs = exceeded.retry_after.as_secs(), "Refused LLM request: {}", exceeded.reason);
+            replies.send(InferenceResult::QuotaExceeded {
+                reason: exceeded.reason,
+                retry_after_secs: exceeded.retry_after.as_secs(),
+            }).await;
+            return;
+        }
+    };
+
+    // Recorded in the ledger as served to `peer`, not as our own use
+    let backend = node.metered(node.server.clone(), "local", &peer, &node.node_id);
+    let tokens = if replies.send(InferenceResult::Accepted).await {
+        match request.call {
+            InferenceCall::Chat { model, messages, options, no_cache } => {
+                let cache = node.cache.shared_with(&peer).then_some(node.cache.as_ref());
+                relay_chat(backend.as_ref(), LlmRequest { model, messages, options, no_cache }, &replies, &active, cache).await
+            }
+            InferenceCall::Embed { model, inputs } => {
+                let result = match active.run(backend.embed(&model, &inputs)).await {
+                    Ok(vectors) => InferenceResult::Embeddings { vectors },
+                    Err(error) => InferenceResult::Failed { error },
+                };
+                replies.send(result).await;
+                0
+            }
+            // Answered by serve_models, never admitted
+            InferenceCall::Models => 0,
+        }
+    } else {
+        0
+    };
+    admission.finish(tokens).await;
+}
+
+// Streams the answer to the peer and returns the tokens it cost, keeping the answer in
+// `cache` if given. Generation stops as soon as the request is cancelled or the peer's
+// session goes away.
+async fn relay_chat(backend: &dyn LlmBackend, req: LlmRequest, replies: &Replies, active: &ActiveRequest, cache: Option<&ResponseCache>) -> u64 {
+    let mut upstream = match active.run(backend.stream(&req)).await {
+        Ok(upstream) => upstream,
+        Err(error) => {
+            replies.send(InferenceResult::Failed { error }).await;
+            return 0;
+        }
+    };
+
+    let mut answer = String::new();
+    let mut tally = Tally::default();
+    loop {
+        let delta = tokio::select! {
+            delta = upstream.next() => delta,
+            _ = active.cancelled() => {
+                debug!("LLM request for a peer cancelled");
+                replies.send(InferenceResult::Failed { error: CANCELLED.to_string() }).await;
+                return tally.tokens();
+            }
+        };
+        let Some(delta) = delta else { break };
+        match delta {
+            Ok(delta) => {
+                tally.add(&delta);
+                answer.push_str(&delta.content);
+                let (done, usage) = (delta.done, delta.usage);
+                if !replies.send(InferenceResult::Delta { content: delta.content, done, usage }).await {
+                    debug!("Peer went away during its LLM request");
+                    return tally.tokens();
+                }
+                if done {
+                    if let Some(cache) = cache {
+                        cache.put(&req, &answer);
+                    }
+                    return tally.tokens();
+                }
+            }
+            Err(error) => {
+                replies.send(InferenceResult::Failed { error }).await;
+                return tally.tokens();
+            }
+        }
+    }
+    replies.send(InferenceResult::Failed { error: "Incomplete response from LLM".to_string() }).await;
+    tally.tokens()
+}
@@ -1,173 +1,157 @@
 // LLM module for language model related functionality
-use actix_web::{post, web, HttpResponse, Error};
+pub mod active;
+mod backend;
+pub mod cache;
+pub mod embed;
+mod failover;
+mod hosting;
+pub mod openai;
+mod quota;
+mod remote;
+
+use actix_web::{get, post, web, Error, HttpRequest, HttpResponse};
+use futures::StreamExt;
+use tokio::sync::mpsc;
+use tracing::{debug, info, info_span, warn, Instrument, Span};
 use serde::{Deserialize, Serialize};
-use reqwest::Client;
 use chrono::Utc;
-use crate::conversation::{ChatMessage, CONVERSATION_STORE, HostInfo, MessageType};
-use crate::tcp::LLM_CONNECTIONS;
+use crate::config::BackendKind;
+use crate::conversation::{ChatMessage, Citation, HostInfo, MessageType};
+use crate::events::Event;
+use crate::files::{self, ChunkMatch};
+use crate::logging;
+use crate::node::Node;
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
-use hostname;
-
-// Remove the constant and make it a function that returns the correct URL
-async fn get_ollama_url() -> String {
-    let connections = LLM_CONNECTIONS.lock().await;
-    if let Some((host, port)) = connections.values().next() {
-        format!("http://{}:{}", host, port)
-    } else {
-        "http://127.0.0.1:11434".to_string()
-    }
-}
 
-const REMOTE_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+pub use backend::{connect, ChatDelta, ChatDeltaStream, GenerationOptions, LlmBackend, LlmEndpoint, LlmMessage, LlmRequest, OutputFormat, Tally, TokenUsage};
+pub use active::{ActiveRequest, ActiveRequests};
+pub use cache::ResponseCache;
+pub use failover::{Failover, FailoverNotice};
+pub use hosting::{cancel_peer_request, serve_peer_request};
+pub use quota::Quotas;
+pub use remote::{InferenceReply, InferenceRequest, RemoteCalls};
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct ChatRequest {
-    message: String,
-    sender: String,
+    pub message: String,
+    pub sender: String,
+    // Answer from the most relevant chunks of uploaded documents, with citations
+    #[serde(default)]
+    pub use_documents: bool,
+    #[serde(default)]
+    pub options: GenerationOptions,
+    // Skip a cached answer; the new one replaces it
+    #[serde(default)]
+    pub no_cache: bool,
 }
 
+// One line of the NDJSON body returned by /api/chat/stream
 #[derive(Serialize, Deserialize, Debug)]
-struct OllamaMessage {
-    role: String,
-    content: String,
+pub struct ChatChunk {
+    pub content: String,
+    pub done: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // Sent with the final chunk
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub citations: Vec<Citation>,
+    // Sent on its own when the answer moves to another host partway through
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failover: Option<FailoverNotice>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct OllamaRequest {
-    model: String,
-    messages: Vec<OllamaMessage>,
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ModelInfo {
+    pub name: String,
+    // "local" or the IP of the peer serving the model
+    pub host: String,
+    pub backend: BackendKind,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct OllamaResponse {
-    model: String,
-    created_at: String,
-    message: OllamaMessage,
-    done: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    done_reason: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    context: Option<Vec<i32>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    total_duration: Option<i64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    load_duration: Option<i64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    prompt_eval_count: Option<i32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    eval_count: Option<i32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    eval_duration: Option<i64>,
-}
-
-// Update the is_local_ollama_available function
-async fn is_local_ollama_available() -> bool {
-    if let Ok(client) = Client::builder()
-        .timeout(Duration::from_secs(2))
-        .build() 
-    {
-        let url = get_ollama_url().await;
-        match client.get(&url).send().await {
-            Ok(response) => response.status().is_success(),
-            Err(_) => false,
-        }
-    } else {
-        false
-    }
+async fn try_local_llm(node: &Node, req: &LlmRequest) -> Result<String, String> {
+    node.backend.chat(req).await
+        .map_err(|e| format!("Local LLM error: {}", e))
 }
 
-async fn try_local_llm(req: &OllamaRequest) -> Result<String, String> {
-    let client = Client::new();
-    let url = get_ollama_url().await;
-    let response = client
-        .post(format!("{}/api/chat", url))
-        .json(&req)
-        .send()
-        .await
-        .map_err(|e| format!("Failed to connect to local LLM: {}", e))?;
+// Known LLM connections, fastest measured round-trip first; unmeasured peers go last
+async fn remote_llm_candidates(node: &Node) -> Vec<(String, LlmEndpoint)> {
+    let connections: Vec<(String, LlmEndpoint)> = node.llm_connections.lock().await
+        .iter()
+        .map(|(peer, endpoint)| (peer.clone(), endpoint.clone()))
+        .collect();
 
-    if !response.status().is_success() {
-        return Err(format!("Local LLM error: {}", response.status()));
+    let mut ranked = Vec::with_capacity(connections.len());
+    for (peer, endpoint) in connections {
+        let rtt = node.peers.rtt(&endpoint.host).await;
+        ranked.push((rtt, (peer, endpoint)));
     }
+    ranked.sort_by_key(|(rtt, _)| rtt.unwrap_or(Duration::MAX));
+    ranked.into_iter().map(|(_, candidate)| candidate).collect()
+}
 
-    let body = response.text().await
-        .map_err(|e| format!("Failed to get local LLM response: {}", e))?;
-
-    process_ollama_response(&body)
+// Requests go over our session with the peer, None once it has closed
+async fn remote_backend(node: &Node, peer: &str, endpoint: &LlmEndpoint) -> Option<Arc<dyn LlmBackend>> {
+    let backend = remote::peer_backend(node, peer, endpoint).await?;
+    Some(node.metered(backend, "remote", &node.node_id, peer))
 }
 
-async fn try_remote_llm(req: &OllamaRequest) -> Result<String, String> {
-    let connections = LLM_CONNECTIONS.lock().await;
+async fn try_remote_llm(node: &Node, req: &LlmRequest) -> Result<String, String> {
+    let connections = remote_llm_candidates(node).await;
     
     if connections.is_empty() {
         return Err("No remote LLM connections available".to_string());
     }
 
     // Try each known LLM connection
-    for (peer, (host, port)) in connections.iter() {
-        let client = Client::builder()
-            .timeout(REMOTE_REQUEST_TIMEOUT)
-            .build()
-            .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
-
-        let remote_url = format!("http://{}:{}/api/chat", host, port);
-        
-        println!("Attempting to use remote LLM at {}", remote_url);
-        
-        match client.post(&remote_url)
-            .json(&req)
-            .timeout(REMOTE_REQUEST_TIMEOUT)
-            .send()
-            .await {
-                Ok(response) => {
-                    if response.status().is_success() {
-                        let body = response.text().await
-                            .map_err(|e| format!("Failed to get remote LLM response: {}", e))?;
-                        
-                        match process_ollama_response(&body) {
-                            Ok(result) => {
-                                println!("Successfully used remote LLM from peer {}", peer);
-                                return Ok(result)
-                            },
-                            Err(e) => println!("Failed to process response from {}: {}", peer, e),
-                        }
-                    } else {
-                        println!("Remote LLM {} returned error status: {}", peer, response.status());
-                    }
-                },
-                Err(e) => println!("Failed to connect to remote LLM {}: {}", peer, e),
+    for (peer, endpoint) in connections.iter() {
+        debug!(host = %peer, "Trying remote LLM at {} ({})", endpoint.host, endpoint.backend.as_str());
+        let Some(backend) = remote_backend(node, peer, endpoint).await else { continue };
+
+        match tokio::time::timeout(node.config.remote_timeout(), backend.chat(req)).await {
+            Ok(Ok(result)) => {
+                info!(host = %peer, "Answered by remote LLM");
+                return Ok(result);
             }
+            Ok(Err(e)) => warn!(host = %peer, "Remote LLM failed: {}", e),
+            Err(_) => warn!(host = %peer, "Remote LLM timed out"),
+        }
     }
     
     Err("No available LLM connections responded successfully".to_string())
 }
 
-fn process_ollama_response(body: &str) -> Result<String, String> {
-    let mut full_response = String::new();
-    let mut response_complete = false;
-
-    for line in body.lines() {
-        if let Ok(resp) = serde_json::from_str::<OllamaResponse>(line) {
-            full_response.push_str(&resp.message.content);
-            if resp.done {
-                response_complete = true;
-            }
-        }
-    }
-
-    if !response_complete {
-        return Err("Incomplete response from LLM".to_string());
+// When no local LLM answers and every peer that would has refused us for quota, how
+// long until the first of them takes requests again
+pub(crate) async fn quota_retry_after(node: &Node) -> Option<Duration> {
+    let candidates = remote_llm_candidates(node).await;
+    if candidates.is_empty() || node.backend.health().await {
+        return None;
     }
+    let waits: Option<Vec<Duration>> = candidates.iter()
+        .map(|(peer, _)| node.remote_calls.retry_after(peer))
+        .collect();
+    waits?.into_iter().min()
+}
 
-    if full_response.trim().is_empty() {
-        return Err("Empty response from LLM".to_string());
+async fn llm_unavailable(node: &Node, details: String) -> HttpResponse {
+    match quota_retry_after(node).await {
+        Some(retry_after) => HttpResponse::TooManyRequests()
+            .insert_header(("Retry-After", retry_after.as_secs().max(1).to_string()))
+            .json(serde_json::json!({
+                "error": "Quota exceeded",
+                "details": details,
+                "retry_after_secs": retry_after.as_secs().max(1),
+            })),
+        None => HttpResponse::ServiceUnavailable().json(serde_json::json!({
+            "error": "No available LLM service",
+            "details": details
+        })),
     }
-
-    Ok(full_response)
 }
 
-#[post("/chat")]
-pub async fn chat(req: web::Json<ChatRequest>) -> Result<HttpResponse, Error> {
+async fn local_host_info(node: &Node) -> HostInfo {
     let hostname = hostname::get()
         .map(|h| h.to_string_lossy().to_string())
         .unwrap_or_else(|_| "Unknown".to_string());
@@ -177,80 +161,394 @@ pub async fn chat(req: web::Json<ChatRequest>) -> Result<HttpResponse, Error> {
         .map(|addr| addr.ip().to_string())
         .unwrap_or_else(|_| "Unknown".to_string());
 
-    let host_info = HostInfo {
-        hostname: hostname.clone(),
-        ip_address: ip_address.clone(),
-        is_llm_host: is_local_ollama_available().await,
-    };
+    HostInfo {
+        hostname,
+        ip_address,
+        is_llm_host: node.backend.health().await,
+    }
+}
+
+const CITATION_EXCERPT_CHARS: usize = 200;
+
+// Chunks of uploaded documents relevant to the question, when the request asks for them
+async fn document_context(node: &Node, req: &ChatRequest) -> Result<Vec<ChunkMatch>, String> {
+    if !req.use_documents {
+        return Ok(Vec::new());
+    }
+    files::search_documents(node, &req.message).await
+}
 
-    // Create user question message
+fn citations(context: &[ChunkMatch]) -> Vec<Citation> {
+    context.iter().map(|chunk| Citation {
+        document_id: chunk.document_id.clone(),
+        document_name: chunk.document_name.clone(),
+        chunk: chunk.position,
+        excerpt: chunk.text.chars().take(CITATION_EXCERPT_CHARS).collect(),
+        score: chunk.score,
+    }).collect()
+}
+
+fn invalid_options(error: String) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "error": "Invalid generation options",
+        "details": error
+    }))
+}
+
+fn document_search_failed(error: String) -> HttpResponse {
+    HttpResponse::ServiceUnavailable().json(serde_json::json!({
+        "error": "Document search failed",
+        "details": error
+    }))
+}
+
+async fn add_local_message(node: &Node, message: ChatMessage) {
+    node.conversations.add_message("local".to_string(), message.clone()).await;
+    node.events.publish(Event::MessageAdded { conversation: "local".to_string(), message });
+}
+
+// Records the question in the local conversation and builds the LLM request for it,
+// with the document chunks in a system message ahead of it
+async fn start_chat(node: &Node, req: &ChatRequest, host_info: &HostInfo, context: &[ChunkMatch]) -> LlmRequest {
+    debug!(
+        sender = %req.sender,
+        prompt = %logging::content(&req.message, node.config.logging.log_content),
+        documents = context.len(),
+        "Chat request"
+    );
     let question_message = ChatMessage {
         content: req.message.clone(),
         timestamp: Utc::now(),
         sender: req.sender.clone(),
         message_type: MessageType::Question,
         host_info: host_info.clone(),
+        citations: Vec::new(),
     };
+    add_local_message(node, question_message).await;
 
-    // Save the question
-    CONVERSATION_STORE.add_message("local".to_string(), question_message).await;
+    let mut messages = Vec::new();
+    if !context.is_empty() {
+        let mut prompt = String::from(
+            "Answer using the numbered excerpts below when they are relevant and cite them like [1]. \
+             If they do not contain the answer, say so.\n"
+        );
+        for (i, chunk) in context.iter().enumerate() {
+            prompt.push_str(&format!("\n[{}] {}\n{}\n", i + 1, chunk.document_name, chunk.text));
+        }
+        messages.push(LlmMessage { role: "system".to_string(), content: prompt });
+    }
+    messages.push(LlmMessage {
+        role: "user".to_string(),
+        content: req.message.clone(),
+    });
 
-    let ollama_req = OllamaRequest {
-        model: "phi3-fast".to_string(),
-        messages: vec![
-            OllamaMessage {
-                role: "user".to_string(),
-                content: req.message.clone(),
-            }
-        ],
+    LlmRequest {
+        model: node.config.llm.model.clone(),
+        messages,
+        options: req.options.clone(),
+        no_cache: req.no_cache,
+    }
+}
+
+async fn save_response(node: &Node, content: String, host_info: HostInfo, citations: Vec<Citation>) -> ChatMessage {
+    debug!(answer = %logging::content(&content, node.config.logging.log_content), "Chat answer");
+    let response_message = ChatMessage {
+        content,
+        timestamp: Utc::now(),
+        sender: "LLM".to_string(),
+        message_type: MessageType::Response,
+        host_info,
+        citations,
     };
+    add_local_message(node, response_message.clone()).await;
+    response_message
+}
+
+// Chat requests all go to the local conversation
+fn chat_span(request: &ActiveRequest) -> Span {
+    info_span!("chat", conversation = "local", request_id = %request.id)
+}
 
-    // Check if we have local Ollama first
-    let has_local_llm = is_local_ollama_available().await;
+// Listed as running until answered or cancelled. Actix keeps running a handler whose
+// client has gone, so a client that may leave early names the request in X-Request-Id
+// and cancels it on the way out.
+#[post("/chat")]
+pub async fn chat(node: web::Data<Node>, http: HttpRequest, req: web::Json<ChatRequest>) -> Result<HttpResponse, Error> {
+    let request = node.requests.begin_local(&http, &node.config.llm.model, active::request_id);
+    let response = answer_chat(node.get_ref(), req.into_inner(), &request).instrument(chat_span(&request)).await?;
+    Ok(active::tagged(response, &request.id))
+}
+
+// Local LLM first when it answers, then remote peers; the error is the details of
+// every failure
+async fn generate_answer(node: &Node, llm_req: &LlmRequest) -> Result<String, String> {
+    // Check if we have a local LLM first
+    let has_local_llm = node.backend.health().await;
     
-    let response = if has_local_llm {
+    if has_local_llm {
         // Try local first if available
-        match try_local_llm(&ollama_req).await {
-            Ok(response) => response,
+        match try_local_llm(node, llm_req).await {
+            Ok(response) => Ok(response),
             Err(local_error) => {
                 // If local fails, try remote
-                match try_remote_llm(&ollama_req).await {
-                    Ok(response) => response,
-                    Err(remote_error) => {
-                        return Ok(HttpResponse::ServiceUnavailable()
-                            .json(serde_json::json!({
-                                "error": "No available LLM service",
-                                "details": format!("Local error: {}. Remote error: {}", local_error, remote_error)
-                            })));
-                    }
-                }
+                try_remote_llm(node, llm_req).await
+                    .map_err(|remote_error| format!("Local error: {}. Remote error: {}", local_error, remote_error))
             }
         }
     } else {
         // No local LLM, try remote directly
-        match try_remote_llm(&ollama_req).await {
-            Ok(response) => response,
-            Err(remote_error) => {
-                return Ok(HttpResponse::ServiceUnavailable()
-                    .json(serde_json::json!({
-                        "error": "No available LLM service",
-                        "details": format!("No local LLM available. Remote error: {}", remote_error)
-                    })));
+        try_remote_llm(node, llm_req).await
+            .map_err(|remote_error| format!("No local LLM available. Remote error: {}", remote_error))
+    }
+}
+
+async fn answer_chat(node: &Node, req: ChatRequest, request: &ActiveRequest) -> Result<HttpResponse, Error> {
+    if let Err(e) = req.options.validate() {
+        return Ok(invalid_options(e));
+    }
+    let context = match document_context(node, &req).await {
+        Ok(context) => context,
+        Err(e) => return Ok(document_search_failed(e)),
+    };
+    let host_info = local_host_info(node).await;
+    let llm_req = start_chat(node, &req, &host_info, &context).await;
+
+    let response = match node.cache.get(&llm_req) {
+        Some(cached) => {
+            info!("Answered from the response cache");
+            cached
+        }
+        None => {
+            let answer = tokio::select! {
+                answer = generate_answer(node, &llm_req) => answer,
+                _ = request.cancelled() => return Ok(active::cancelled_response(&request.id)),
+            };
+            match answer {
+                Ok(response) => {
+                    node.cache.put(&llm_req, &response);
+                    response
+                }
+                Err(details) => return Ok(llm_unavailable(node, details).await),
             }
         }
     };
 
-    // Create response message with host info
-    let response_message = ChatMessage {
-        content: response.clone(),
-        timestamp: Utc::now(),
-        sender: "LLM".to_string(),
-        message_type: MessageType::Response,
-        host_info,
+    let response_message = save_response(node, response, host_info, citations(&context)).await;
+    Ok(HttpResponse::Ok().json(response_message))
+}
+
+// Local LLM first (when it answers), then remote peers by measured RTT
+async fn chat_endpoints(node: &Node) -> Vec<(String, Arc<dyn LlmBackend>)> {
+    let mut endpoints = Vec::new();
+    if node.backend.health().await {
+        endpoints.push(("local".to_string(), node.backend.clone()));
+    }
+    for (peer, endpoint) in remote_llm_candidates(node).await {
+        if let Some(backend) = remote_backend(node, &peer, &endpoint).await {
+            endpoints.push((peer, backend));
+        }
+    }
+    endpoints
+}
+
+async fn open_chat_stream(node: &Node, req: &LlmRequest, request_id: &str) -> Result<(ChatDeltaStream, Failover), String> {
+    let endpoints = chat_endpoints(node).await;
+    if endpoints.is_empty() {
+        return Err("No local or remote LLM available".to_string());
+    }
+
+    let mut errors = Vec::new();
+    for (peer, backend) in endpoints {
+        match backend.stream(req).await {
+            Ok(stream) => {
+                info!(host = %peer, "Streaming chat response from {}", backend.base_url());
+                return Ok((stream, Failover::new(request_id, peer)));
+            }
+            Err(e) => errors.push(format!("{}: {}", peer, e)),
+        }
+    }
+    Err(errors.join("; "))
+}
+
+fn chunk_line(chunk: &ChatChunk) -> web::Bytes {
+    let mut line = serde_json::to_vec(chunk).unwrap_or_default();
+    line.push(b'\n');
+    web::Bytes::from(line)
+}
+
+// Forwards the backend's deltas as ChatChunk lines and saves the full answer once it
+// is complete. An answer that breaks off carries on on another host with the model.
+// Stops reading upstream as soon as the client goes away or the request is cancelled.
+async fn relay_chat_stream(node: Arc<Node>, req: LlmRequest, (mut upstream, mut failover): (ChatDeltaStream, Failover), tx: mpsc::Sender<Result<web::Bytes, std::io::Error>>, host_info: HostInfo, citations: Vec<Citation>, request: ActiveRequest) {
+    let mut full_response = String::new();
+
+    loop {
+        let delta = tokio::select! {
+            delta = upstream.next() => delta,
+            _ = tx.closed() => {
+                debug!("Chat stream client disconnected");
+                return;
+            }
+            _ = request.cancelled() => {
+                let chunk = ChatChunk { content: String::new(), done: true, error: Some(active::CANCELLED.to_string()), citations: Vec::new(), failover: None };
+                let _ = tx.send(Ok(chunk_line(&chunk))).await;
+                return;
+            }
+        };
+        let error = match delta {
+            Some(Ok(delta)) => {
+                full_response.push_str(&delta.content);
+                if !delta.content.is_empty() {
+                    let chunk = ChatChunk { content: delta.content, done: false, error: None, citations: Vec::new(), failover: None };
+                    if tx.send(Ok(chunk_line(&chunk))).await.is_err() {
+                        debug!("Chat stream client disconnected");
+                        return;
+                    }
+                }
+                if delta.done {
+                    break;
+                }
+                continue;
+            }
+            Some(Err(e)) => e,
+            None => "Incomplete response from LLM".to_string(),
+        };
+
+        match request.run(failover.resume(&node, &req, &full_response, &error)).await {
+            Ok((resumed, notice)) => {
+                upstream = resumed;
+                let chunk = ChatChunk { content: String::new(), done: false, error: None, citations: Vec::new(), failover: Some(notice) };
+                if tx.send(Ok(chunk_line(&chunk))).await.is_err() {
+                    debug!("Chat stream client disconnected");
+                    return;
+                }
+            }
+            Err(e) => {
+                let chunk = ChatChunk { content: String::new(), done: true, error: Some(e), citations: Vec::new(), failover: None };
+                let _ = tx.send(Ok(chunk_line(&chunk))).await;
+                return;
+            }
+        }
+    }
+
+    let error = full_response.trim().is_empty().then(|| "Empty response from LLM".to_string());
+    let citations = if error.is_none() {
+        node.cache.put(&req, &full_response);
+        save_response(&node, full_response, host_info, citations).await.citations
+    } else {
+        Vec::new()
     };
+    let _ = tx.send(Ok(chunk_line(&ChatChunk { content: String::new(), done: true, error, citations, failover: None }))).await;
+}
 
-    // Save the response
-    CONVERSATION_STORE.add_message("local".to_string(), response_message.clone()).await;
+// A cached answer as a stream of one piece
+async fn cached_stream(node: &Node, answer: String, host_info: HostInfo, citations: Vec<Citation>) -> HttpResponse {
+    info!("Answered from the response cache");
+    let content = chunk_line(&ChatChunk { content: answer.clone(), done: false, error: None, citations: Vec::new(), failover: None });
+    let citations = save_response(node, answer, host_info, citations).await.citations;
+    let done = chunk_line(&ChatChunk { content: String::new(), done: true, error: None, citations, failover: None });
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .body([content, done].concat())
+}
 
-    Ok(HttpResponse::Ok().json(response_message))
-}
\ No newline at end of file
+// Cancelled when the client goes away, which shows the next time there is something
+// to send it
+#[post("/chat/stream")]
+pub async fn chat_stream(node: web::Data<Node>, http: HttpRequest, req: web::Json<ChatRequest>) -> Result<HttpResponse, Error> {
+    let request = node.requests.begin_local(&http, &node.config.llm.model, active::request_id);
+    let id = request.id.clone();
+    let span = chat_span(&request);
+    let response = stream_chat(node.into_inner(), req.into_inner(), request).instrument(span).await?;
+    Ok(active::tagged(response, &id))
+}
+
+async fn stream_chat(node: Arc<Node>, req: ChatRequest, request: ActiveRequest) -> Result<HttpResponse, Error> {
+    if let Err(e) = req.options.validate() {
+        return Ok(invalid_options(e));
+    }
+    let context = match document_context(&node, &req).await {
+        Ok(context) => context,
+        Err(e) => return Ok(document_search_failed(e)),
+    };
+    let host_info = local_host_info(&node).await;
+    let llm_req = start_chat(&node, &req, &host_info, &context).await;
+    if let Some(cached) = node.cache.get(&llm_req) {
+        return Ok(cached_stream(&node, cached, host_info, citations(&context)).await);
+    }
+
+    let upstream = tokio::select! {
+        upstream = open_chat_stream(&node, &llm_req, &request.id) => upstream,
+        _ = request.cancelled() => return Ok(active::cancelled_response(&request.id)),
+    };
+    let upstream = match upstream {
+        Ok(response) => response,
+        Err(e) => return Ok(llm_unavailable(&node, e).await),
+    };
+
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(relay_chat_stream(node, llm_req, upstream, tx, host_info, citations(&context), request).in_current_span());
+    let body = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(body))
+}
+
+// Local LLM server first, then every peer that granted us access by node ID, by
+// measured RTT
+async fn model_sources(node: &Node) -> Vec<(String, Arc<dyn LlmBackend>)> {
+    let mut sources = vec![("local".to_string(), node.backend.clone())];
+    for (peer, endpoint) in remote_llm_candidates(node).await {
+        if let Some(backend) = remote_backend(node, &peer, &endpoint).await {
+            sources.push((peer, backend));
+        }
+    }
+    sources
+}
+
+// Ollama lists models with their tag, but clients usually leave ":latest" off
+fn model_matches(available: &str, requested: &str) -> bool {
+    available == requested || available.strip_suffix(":latest") == Some(requested)
+}
+
+// Models on the local LLM server and on every peer that granted us access
+async fn collect_models(node: &Node) -> Vec<ModelInfo> {
+    let addresses: HashMap<String, String> = node.llm_connections.lock().await
+        .iter()
+        .map(|(peer, endpoint)| (peer.clone(), endpoint.host.clone()))
+        .collect();
+    let mut models = Vec::new();
+    for (source, backend) in model_sources(node).await {
+        let host = addresses.get(&source).cloned().unwrap_or(source);
+        match backend.list_models().await {
+            Ok(names) => models.extend(names.into_iter().map(|name| ModelInfo {
+                name,
+                host: host.clone(),
+                backend: backend.kind(),
+            })),
+            Err(e) => warn!(%host, "Failed to list models: {}", e),
+        }
+    }
+    models
+}
+
+// Endpoints that host `model`, in the order they should be tried
+async fn model_endpoints(node: &Node, model: &str) -> Vec<(String, Arc<dyn LlmBackend>)> {
+    let mut endpoints = Vec::new();
+    for (host, backend) in model_sources(node).await {
+        match backend.list_models().await {
+            Ok(names) if names.iter().any(|name| model_matches(name, model)) => endpoints.push((host, backend)),
+            Ok(_) => {}
+            Err(e) => warn!(%host, "Failed to list models: {}", e),
+        }
+    }
+    endpoints
+}
+
+#[get("/models")]
+pub async fn list_models(node: web::Data<Node>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(collect_models(&node).await))
+}
@@ -0,0 +1,411 @@
+// OpenAI-compatible API (/v1/...) so existing tools can use the mesh as if it were
+// one OpenAI server. Each request goes to whichever endpoint hosts the model it names.
+use std::collections::HashSet;
+use std::sync::Arc;
+use actix_web::{get, post, web, Error, HttpRequest, HttpResponse};
+use chrono::Utc;
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tracing::{debug, info};
+use crate::node::Node;
+use super::active::{self, ActiveRequest, CANCELLED};
+use super::embed::embed_across;
+use super::{model_endpoints, ChatDeltaStream, Failover, GenerationOptions, LlmBackend, LlmMessage, LlmRequest, OutputFormat};
+
+#[derive(Deserialize)]
+pub struct ChatCompletionRequest {
+    model: String,
+    messages: Vec<CompletionMessage>,
+    #[serde(default)]
+    stream: bool,
+    #[serde(default)]
+    temperature: Option<f32>,
+    #[serde(default)]
+    top_p: Option<f32>,
+    #[serde(default)]
+    seed: Option<i64>,
+    #[serde(default)]
+    stop: Option<StopSequences>,
+    #[serde(default, alias = "max_completion_tokens")]
+    max_tokens: Option<u32>,
+    #[serde(default)]
+    response_format: Option<ResponseFormat>,
+    // Not part of the OpenAI API: Ollama's context window and keep-alive, and skipping
+    // a cached answer, the new one replacing it
+    #[serde(default)]
+    num_ctx: Option<u32>,
+    #[serde(default)]
+    keep_alive: Option<String>,
+    #[serde(default)]
+    no_cache: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StopSequences {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct ResponseFormat {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Deserialize)]
+struct CompletionMessage {
+    role: String,
+    // A string, or a list of parts of which only text is understood
+    content: Value,
+}
+
+#[derive(Deserialize)]
+pub struct EmbeddingRequest {
+    model: String,
+    input: EmbeddingInput,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EmbeddingInput {
+    One(String),
+    Many(Vec<String>),
+}
+
+fn error_response(status: actix_web::http::StatusCode, message: &str, kind: &str, code: Option<&str>) -> HttpResponse {
+    HttpResponse::build(status).json(json!({
+        "error": { "message": message, "type": kind, "code": code }
+    }))
+}
+
+fn model_not_found(model: &str) -> HttpResponse {
+    error_response(
+        actix_web::http::StatusCode::NOT_FOUND,
+        &format!("The model `{}` is not available on this node or any peer", model),
+        "invalid_request_error",
+        Some("model_not_found"),
+    )
+}
+
+fn cancelled() -> HttpResponse {
+    error_response(actix_web::http::StatusCode::CONFLICT, CANCELLED, "invalid_request_error", Some("request_cancelled"))
+}
+
+fn unavailable(details: &str) -> HttpResponse {
+    error_response(actix_web::http::StatusCode::SERVICE_UNAVAILABLE, details, "server_error", None)
+}
+
+// Rate limited rather than unavailable when every host that could answer has refused
+// us for quota
+async fn chat_unavailable(node: &Node, details: &str) -> HttpResponse {
+    let Some(retry_after) = super::quota_retry_after(node).await else {
+        return unavailable(details);
+    };
+    let mut response = error_response(actix_web::http::StatusCode::TOO_MANY_REQUESTS, details, "rate_limit_error", Some("rate_limit_exceeded"));
+    if let Ok(value) = actix_web::http::header::HeaderValue::from_str(&retry_after.as_secs().max(1).to_string()) {
+        response.headers_mut().insert(actix_web::http::header::RETRY_AFTER, value);
+    }
+    response
+}
+
+fn message_text(content: &Value) -> Option<String> {
+    match content {
+        Value::String(text) => Some(text.clone()),
+        Value::Array(parts) => Some(parts.iter()
+            .filter(|part| part["type"] == "text")
+            .filter_map(|part| part["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("")),
+        Value::Null => Some(String::new()),
+        _ => None,
+    }
+}
+
+// The request's generation options, checked
+fn generation_options(req: &mut ChatCompletionRequest) -> Result<GenerationOptions, String> {
+    let format = match req.response_format.as_ref().map(|format| format.kind.as_str()) {
+        None | Some("text") => None,
+        Some("json_object") => Some(OutputFormat::Json),
+        Some(other) => return Err(format!("Unsupported response_format type `{}`", other)),
+    };
+    let options = GenerationOptions {
+        temperature: req.temperature,
+        top_p: req.top_p,
+        num_ctx: req.num_ctx,
+        seed: req.seed,
+        stop: match req.stop.take() {
+            None => Vec::new(),
+            Some(StopSequences::One(stop)) => vec![stop],
+            Some(StopSequences::Many(stops)) => stops,
+        },
+        max_tokens: req.max_tokens,
+        keep_alive: req.keep_alive.take(),
+        format,
+    };
+    options.validate()?;
+    Ok(options)
+}
+
+fn completion_id() -> String {
+    format!("chatcmpl-{}", hex::encode(rand::random::<[u8; 12]>()))
+}
+
+fn sse_event(data: &Value) -> web::Bytes {
+    web::Bytes::from(format!("data: {}\n\n", data))
+}
+
+fn completion_chunk(id: &str, created: i64, model: &str, delta: Value, finish_reason: Option<&str>) -> Value {
+    json!({
+        "id": id,
+        "object": "chat.completion.chunk",
+        "created": created,
+        "model": model,
+        "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+    })
+}
+
+fn completion(id: &str, model: &str, content: String) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "id": id,
+        "object": "chat.completion",
+        "created": Utc::now().timestamp(),
+        "model": model,
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": content },
+            "finish_reason": "stop",
+        }],
+    }))
+}
+
+// A cached answer as the events of a stream of one piece
+fn cached_completion_stream(id: &str, model: &str, content: String) -> HttpResponse {
+    let created = Utc::now().timestamp();
+    let mut body = Vec::new();
+    body.extend_from_slice(&sse_event(&completion_chunk(id, created, model, json!({ "role": "assistant", "content": "" }), None)));
+    body.extend_from_slice(&sse_event(&completion_chunk(id, created, model, json!({ "content": content }), None)));
+    body.extend_from_slice(&sse_event(&completion_chunk(id, created, model, json!({}), Some("stop"))));
+    body.extend_from_slice(b"data: [DONE]\n\n");
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .body(body)
+}
+
+// First endpoint hosting the model that accepts the request, with its stream
+async fn open_stream(endpoints: Vec<(String, Arc<dyn LlmBackend>)>, req: &LlmRequest, request_id: &str) -> Result<(ChatDeltaStream, Failover), String> {
+    let mut errors = Vec::new();
+    for (peer, backend) in endpoints {
+        match backend.stream(req).await {
+            Ok(stream) => {
+                info!(model = %req.model, host = %peer, "Streaming completion");
+                return Ok((stream, Failover::new(request_id, peer)));
+            }
+            Err(e) => errors.push(format!("{}: {}", peer, e)),
+        }
+    }
+    Err(errors.join("; "))
+}
+
+// Re-emits the backend's deltas as OpenAI chunk events, carrying on on another host
+// if the answer breaks off; stops as soon as the client goes away or the request is
+// cancelled
+async fn relay_completion(node: Arc<Node>, req: LlmRequest, (mut upstream, mut failover): (ChatDeltaStream, Failover), tx: mpsc::Sender<Result<web::Bytes, std::io::Error>>, request: ActiveRequest) {
+    let id = request.id.as_str();
+    let model = req.model.as_str();
+    let created = Utc::now().timestamp();
+    let first = completion_chunk(id, created, model, json!({ "role": "assistant", "content": "" }), None);
+    if tx.send(Ok(sse_event(&first))).await.is_err() {
+        return;
+    }
+
+    let mut answer = String::new();
+    loop {
+        let delta = tokio::select! {
+            delta = upstream.next() => delta,
+            _ = tx.closed() => {
+                debug!("Completion stream client disconnected");
+                return;
+            }
+            _ = request.cancelled() => {
+                let error = json!({ "error": { "message": CANCELLED, "type": "invalid_request_error", "code": "request_cancelled" } });
+                let _ = tx.send(Ok(sse_event(&error))).await;
+                return;
+            }
+        };
+        let error = match delta {
+            Some(Ok(delta)) => {
+                answer.push_str(&delta.content);
+                if !delta.content.is_empty() {
+                    let chunk = completion_chunk(id, created, model, json!({ "content": delta.content }), None);
+                    if tx.send(Ok(sse_event(&chunk))).await.is_err() {
+                        debug!("Completion stream client disconnected");
+                        return;
+                    }
+                }
+                if delta.done {
+                    break;
+                }
+                continue;
+            }
+            Some(Err(e)) => e,
+            None => "Incomplete response from LLM".to_string(),
+        };
+
+        match request.run(failover.resume(&node, &req, &answer, &error)).await {
+            // A comment line, which OpenAI clients skip
+            Ok((resumed, notice)) => {
+                upstream = resumed;
+                let comment = format!(": failover from {} to {}\n\n", notice.from, notice.to);
+                if tx.send(Ok(web::Bytes::from(comment))).await.is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                let error = json!({ "error": { "message": e, "type": "server_error", "code": null } });
+                let _ = tx.send(Ok(sse_event(&error))).await;
+                return;
+            }
+        }
+    }
+
+    node.cache.put(&req, &answer);
+    let last = completion_chunk(id, created, model, json!({}), Some("stop"));
+    let _ = tx.send(Ok(sse_event(&last))).await;
+    let _ = tx.send(Ok(web::Bytes::from_static(b"data: [DONE]\n\n"))).await;
+}
+
+// The completion ID doubles as the request ID, for /api/requests/{id}/cancel, unless
+// the client chose one in X-Request-Id
+#[post("/chat/completions")]
+pub async fn chat_completions(node: web::Data<Node>, http: HttpRequest, req: web::Json<ChatCompletionRequest>) -> Result<HttpResponse, Error> {
+    let req = req.into_inner();
+    let request = node.requests.begin_local(&http, &req.model, completion_id);
+    let id = request.id.clone();
+    let response = complete_chat(node.into_inner(), req, request).await;
+    Ok(active::tagged(response, &id))
+}
+
+async fn complete_chat(node: Arc<Node>, mut req: ChatCompletionRequest, request: ActiveRequest) -> HttpResponse {
+    let options = match generation_options(&mut req) {
+        Ok(options) => options,
+        Err(e) => return error_response(actix_web::http::StatusCode::BAD_REQUEST, &e, "invalid_request_error", None),
+    };
+    let mut messages = Vec::with_capacity(req.messages.len());
+    for message in req.messages {
+        let Some(content) = message_text(&message.content) else {
+            return error_response(
+                actix_web::http::StatusCode::BAD_REQUEST,
+                "Message content must be a string or a list of text parts",
+                "invalid_request_error",
+                None,
+            );
+        };
+        messages.push(LlmMessage { role: message.role, content });
+    }
+    let llm_req = LlmRequest {
+        model: req.model.clone(),
+        messages,
+        options,
+        no_cache: req.no_cache,
+    };
+    if let Some(cached) = node.cache.get(&llm_req) {
+        info!(model = %req.model, "Served completion from the response cache");
+        if req.stream {
+            return cached_completion_stream(&request.id, &req.model, cached);
+        }
+        return completion(&request.id, &req.model, cached);
+    }
+
+    let endpoints = model_endpoints(&node, &req.model).await;
+    if endpoints.is_empty() {
+        return model_not_found(&req.model);
+    }
+
+    if req.stream {
+        let upstream = tokio::select! {
+            upstream = open_stream(endpoints, &llm_req, &request.id) => upstream,
+            _ = request.cancelled() => return cancelled(),
+        };
+        let upstream = match upstream {
+            Ok(upstream) => upstream,
+            Err(e) => return chat_unavailable(&node, &e).await,
+        };
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(relay_completion(node, llm_req, upstream, tx, request));
+        let body = futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|item| (item, rx))
+        });
+        return HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header(("Cache-Control", "no-cache"))
+            .streaming(body);
+    }
+
+    let mut errors = Vec::new();
+    for (peer, backend) in endpoints {
+        let answer = tokio::select! {
+            answer = backend.chat(&llm_req) => answer,
+            _ = request.cancelled() => return cancelled(),
+        };
+        match answer {
+            Ok(content) => {
+                info!(model = %req.model, host = %peer, "Served completion");
+                node.cache.put(&llm_req, &content);
+                return completion(&request.id, &req.model, content);
+            }
+            Err(e) => errors.push(format!("{}: {}", peer, e)),
+        }
+    }
+    chat_unavailable(&node, &errors.join("; ")).await
+}
+
+// Every model on this node and its peers, once each; `owned_by` names the first host
+#[get("/models")]
+pub async fn list_models(node: web::Data<Node>) -> Result<HttpResponse, Error> {
+    let mut seen = HashSet::new();
+    let mut data = Vec::new();
+    for model in super::collect_models(&node).await {
+        if seen.insert(model.name.clone()) {
+            data.push(json!({
+                "id": model.name,
+                "object": "model",
+                "created": 0,
+                "owned_by": model.host,
+            }));
+        }
+    }
+    Ok(HttpResponse::Ok().json(json!({ "object": "list", "data": data })))
+}
+
+#[post("/embeddings")]
+pub async fn embeddings(node: web::Data<Node>, req: web::Json<EmbeddingRequest>) -> Result<HttpResponse, Error> {
+    let req = req.into_inner();
+    let inputs = match req.input {
+        EmbeddingInput::One(text) => vec![text],
+        EmbeddingInput::Many(texts) => texts,
+    };
+
+    let endpoints = model_endpoints(&node, &req.model).await;
+    if endpoints.is_empty() {
+        return Ok(model_not_found(&req.model));
+    }
+
+    match embed_across(&endpoints, &req.model, &inputs, node.config.remote_timeout()).await {
+        Ok(vectors) => {
+            let data: Vec<Value> = vectors.into_iter().enumerate()
+                .map(|(index, embedding)| json!({ "object": "embedding", "index": index, "embedding": embedding }))
+                .collect();
+            Ok(HttpResponse::Ok().json(json!({
+                "object": "list",
+                "data": data,
+                "model": req.model,
+                "usage": { "prompt_tokens": 0, "total_tokens": 0 },
+            })))
+        }
+        Err(e) => Ok(unavailable(&e)),
+    }
+}
@@ -0,0 +1,159 @@
+// Per-peer limits on the LLM requests this node serves for others. Peers are counted
+// by IP address, the identity their access was granted to, since a node ID is whatever
+// the peer says it is. Each peer has a sliding one-minute window of request times, a
+// count of requests in flight and a generated-token total for the current UTC day. The window and the daily totals are saved after
+// every request, so restarting the node does not hand out a fresh allowance.
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tracing::warn;
+use crate::config::PeerLimits;
+use crate::persistence::write_atomic;
+
+const USAGE_FILE: &str = "peer_usage.json";
+// A peer at its concurrency limit is told to come back after this long
+const CONCURRENCY_RETRY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PeerUsage {
+    // Start times of the requests admitted in the last minute, oldest first
+    pub recent_requests: VecDeque<DateTime<Utc>>,
+    pub day: Option<NaiveDate>,
+    pub tokens_today: u64,
+    #[serde(skip_deserializing)]
+    pub in_flight: u32,
+}
+
+impl PeerUsage {
+    fn roll_over(&mut self, now: DateTime<Utc>) {
+        let window_start = now - chrono::Duration::minutes(1);
+        while self.recent_requests.front().is_some_and(|at| *at <= window_start) {
+            self.recent_requests.pop_front();
+        }
+        if self.day != Some(now.date_naive()) {
+            self.day = Some(now.date_naive());
+            self.tokens_today = 0;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct QuotaExceeded {
+    pub reason: String,
+    pub retry_after: Duration,
+}
+
+fn until(later: DateTime<Utc>, now: DateTime<Utc>) -> Duration {
+    (later - now).to_std().unwrap_or_default().max(Duration::from_secs(1))
+}
+
+pub struct Quotas {
+    path: PathBuf,
+    usage: Mutex<HashMap<String, PeerUsage>>,
+    // One save at a time, so two finishing requests do not share the temporary file
+    saving: tokio::sync::Mutex<()>,
+}
+
+impl Quotas {
+    // Counters saved by an earlier run are picked up again; a damaged file starts over
+    pub async fn open(dir: &Path) -> Arc<Quotas> {
+        let path = dir.join(USAGE_FILE);
+        let usage = match fs::read(&path).await {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable peer usage file: {}", e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        Arc::new(Quotas { path, usage: Mutex::new(usage), saving: tokio::sync::Mutex::new(()) })
+    }
+
+    // Counts a request from the peer at `ip` against its limits. The returned admission
+    // holds one concurrency slot until dropped.
+    pub fn admit(self: &Arc<Self>, ip: &str, limits: PeerLimits) -> Result<Admission, QuotaExceeded> {
+        let now = Utc::now();
+        let mut usage = self.usage.lock().unwrap();
+        let peer = usage.entry(ip.to_string()).or_default();
+        peer.roll_over(now);
+
+        if limits.tokens_per_day > 0 && peer.tokens_today >= limits.tokens_per_day {
+            let tomorrow = (now.date_naive() + chrono::Days::new(1)).and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+            return Err(QuotaExceeded {
+                reason: format!("Daily limit of {} tokens reached", limits.tokens_per_day),
+                retry_after: until(tomorrow, now),
+            });
+        }
+        if limits.requests_per_minute > 0 && peer.recent_requests.len() >= limits.requests_per_minute as usize {
+            let oldest = peer.recent_requests.front().copied().unwrap_or(now);
+            return Err(QuotaExceeded {
+                reason: format!("Limit of {} requests per minute reached", limits.requests_per_minute),
+                retry_after: until(oldest + chrono::Duration::minutes(1), now),
+            });
+        }
+        if limits.max_concurrent > 0 && peer.in_flight >= limits.max_concurrent {
+            return Err(QuotaExceeded {
+                reason: format!("Limit of {} concurrent requests reached", limits.max_concurrent),
+                retry_after: CONCURRENCY_RETRY,
+            });
+        }
+
+        peer.recent_requests.push_back(now);
+        peer.in_flight += 1;
+        Ok(Admission { quotas: self.clone(), ip: ip.to_string() })
+    }
+
+    pub fn usage(&self) -> HashMap<String, PeerUsage> {
+        let now = Utc::now();
+        let mut usage = self.usage.lock().unwrap();
+        for peer in usage.values_mut() {
+            peer.roll_over(now);
+        }
+        usage.clone()
+    }
+
+    async fn save(&self) {
+        let _saving = self.saving.lock().await;
+        let serialized = serde_json::to_vec(&*self.usage.lock().unwrap());
+        let data = match serialized {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Failed to serialize peer usage: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = write_atomic(&self.path, &data).await {
+            warn!("Failed to save peer usage: {}", e);
+        }
+    }
+}
+
+// One admitted request
+pub struct Admission {
+    quotas: Arc<Quotas>,
+    ip: String,
+}
+
+impl Admission {
+    // Adds the tokens the request used to the peer's daily total and saves the counters
+    pub async fn finish(self, tokens: u64) {
+        {
+            let mut usage = self.quotas.usage.lock().unwrap();
+            let peer = usage.entry(self.ip.clone()).or_default();
+            peer.roll_over(Utc::now());
+            peer.tokens_today = peer.tokens_today.saturating_add(tokens);
+        }
+        self.quotas.save().await;
+    }
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        if let Some(peer) = self.quotas.usage.lock().unwrap().get_mut(&self.ip) {
+            peer.in_flight = peer.in_flight.saturating_sub(1);
+        }
+    }
+}
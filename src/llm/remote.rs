@@ -0,0 +1,278 @@
+// LLM requests sent to a peer over its session rather than straight to its server, so
+// the peer can hold us to its limits. Each request carries an ID; the host answers with
+// Accepted (or why not), then the answer itself, and every reply names the request. A
+// request dropped before its answer is complete is cancelled on the host. Listing the
+// host's models goes the same way but is answered straight away, outside its limits.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::debug;
+use crate::config::BackendKind;
+use crate::node::Node;
+use crate::tcp::Message;
+use super::backend::TokenUsage;
+use super::{ChatDelta, ChatDeltaStream, GenerationOptions, LlmBackend, LlmEndpoint, LlmMessage, LlmRequest};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum InferenceCall {
+    Chat {
+        model: String,
+        messages: Vec<LlmMessage>,
+        #[serde(default)]
+        options: GenerationOptions,
+        #[serde(default)]
+        no_cache: bool,
+    },
+    Embed { model: String, inputs: Vec<String> },
+    Models,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InferenceRequest {
+    pub id: u64,
+    pub call: InferenceCall,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum InferenceResult {
+    // The host took the request on; the answer follows
+    Accepted,
+    Delta { content: String, done: bool, usage: Option<TokenUsage> },
+    Embeddings { vectors: Vec<Vec<f32>> },
+    Models { names: Vec<String> },
+    // Refused under the host's per-peer limits; asking again sooner is pointless
+    QuotaExceeded { reason: String, retry_after_secs: u64 },
+    Failed { error: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InferenceReply {
+    pub id: u64,
+    pub result: InferenceResult,
+}
+
+// Requests this node has sent to peers and is waiting on
+pub struct RemoteCalls {
+    next_id: AtomicU64,
+    // By host node ID and request ID. Unbounded, so a slow reader never holds up the
+    // session that delivers the replies.
+    pending: Mutex<HashMap<(String, u64), mpsc::UnboundedSender<InferenceResult>>>,
+    // Hosts that refused us for quota, with when to ask again
+    throttled: Mutex<HashMap<String, Instant>>,
+}
+
+impl RemoteCalls {
+    pub fn new() -> Arc<Self> {
+        Arc::new(RemoteCalls {
+            next_id: AtomicU64::new(1),
+            pending: Mutex::new(HashMap::new()),
+            throttled: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn open(self: &Arc<Self>, host: &str, outbound: mpsc::Sender<Message>) -> PendingCall {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, replies) = mpsc::unbounded_channel();
+        self.pending.lock().unwrap().insert((host.to_string(), id), sender);
+        PendingCall { calls: self.clone(), host: host.to_string(), id, replies, outbound, finished: false }
+    }
+
+    // Replies nobody is waiting for any more are dropped
+    pub fn deliver(&self, host: &str, reply: InferenceReply) {
+        if let Some(sender) = self.pending.lock().unwrap().get(&(host.to_string(), reply.id)) {
+            let _ = sender.send(reply.result);
+        }
+    }
+
+    // The session to `host` is gone; whoever waits on it sees the answer break off
+    pub fn host_lost(&self, host: &str) {
+        self.pending.lock().unwrap().retain(|(pending_host, _), _| pending_host != host);
+    }
+
+    fn throttle(&self, host: &str, retry_after: Duration) {
+        self.throttled.lock().unwrap().insert(host.to_string(), Instant::now() + retry_after);
+    }
+
+    // How long `host` asked us to wait, if it still applies
+    pub fn retry_after(&self, host: &str) -> Option<Duration> {
+        let mut throttled = self.throttled.lock().unwrap();
+        let until = *throttled.get(host)?;
+        let remaining = until.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            throttled.remove(host);
+            return None;
+        }
+        Some(remaining)
+    }
+}
+
+struct PendingCall {
+    calls: Arc<RemoteCalls>,
+    host: String,
+    id: u64,
+    replies: mpsc::UnboundedReceiver<InferenceResult>,
+    outbound: mpsc::Sender<Message>,
+    // The host has sent its last reply, or has gone
+    finished: bool,
+}
+
+impl PendingCall {
+    // None once the host has gone, or the session the call went out on has closed
+    async fn recv(&mut self) -> Option<InferenceResult> {
+        let result = tokio::select! {
+            result = self.replies.recv() => result,
+            _ = self.outbound.closed() => None,
+        };
+        self.finished = match &result {
+            Some(InferenceResult::Accepted) => false,
+            Some(InferenceResult::Delta { done, .. }) => *done,
+            _ => true,
+        };
+        result
+    }
+}
+
+impl Drop for PendingCall {
+    fn drop(&mut self) {
+        self.calls.pending.lock().unwrap().remove(&(self.host.clone(), self.id));
+        // Drop cannot wait for room in the queue; a full queue means the session is
+        // stuck anyway
+        if !self.finished && self.outbound.try_send(Message::InferenceCancel { id: self.id }).is_err() {
+            debug!(host = %self.host, "Could not tell the peer to cancel request {}", self.id);
+        }
+    }
+}
+
+pub fn quota_error(retry_after: Duration) -> String {
+    format!("Quota exceeded, retry after {}s", retry_after.as_secs().max(1))
+}
+
+// A peer's LLM reached through our session with it
+struct PeerBackend {
+    host: String,
+    kind: BackendKind,
+    // Names the peer in logs, where a local backend has its server's URL
+    label: String,
+    outbound: mpsc::Sender<Message>,
+    calls: Arc<RemoteCalls>,
+    timeout: Duration,
+}
+
+// None when we have no session with `host`
+pub async fn peer_backend(node: &Node, host: &str, endpoint: &LlmEndpoint) -> Option<Arc<dyn LlmBackend>> {
+    let outbound = node.sessions.outbound(host).await?;
+    Some(Arc::new(PeerBackend {
+        host: host.to_string(),
+        kind: endpoint.backend,
+        label: format!("peer {} ({})", host, endpoint.host),
+        outbound,
+        calls: node.remote_calls.clone(),
+        timeout: node.config.remote_timeout(),
+    }))
+}
+
+impl PeerBackend {
+    async fn send(&self, call: InferenceCall) -> Result<PendingCall, String> {
+        let mut pending = self.calls.open(&self.host, self.outbound.clone());
+        let request = Message::InferenceRequest(InferenceRequest { id: pending.id, call });
+        if self.outbound.send(request).await.is_err() {
+            pending.finished = true;
+            return Err("Session with the peer has closed".to_string());
+        }
+        Ok(pending)
+    }
+
+    // Sends the request and waits for the host to accept it
+    async fn call(&self, call: InferenceCall) -> Result<PendingCall, String> {
+        if let Some(retry_after) = self.calls.retry_after(&self.host) {
+            return Err(quota_error(retry_after));
+        }
+        let mut pending = self.send(call).await?;
+        match tokio::time::timeout(self.timeout, pending.recv()).await {
+            Ok(Some(InferenceResult::Accepted)) => Ok(pending),
+            Ok(Some(InferenceResult::QuotaExceeded { reason, retry_after_secs })) => {
+                let retry_after = Duration::from_secs(retry_after_secs);
+                self.calls.throttle(&self.host, retry_after);
+                Err(format!("{} ({})", quota_error(retry_after), reason))
+            }
+            Ok(Some(InferenceResult::Failed { error })) => Err(error),
+            Ok(Some(_)) => Err("Unexpected reply from the peer".to_string()),
+            Ok(None) => Err("The peer disconnected".to_string()),
+            Err(_) => Err("The peer did not answer".to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl LlmBackend for PeerBackend {
+    fn kind(&self) -> BackendKind {
+        self.kind
+    }
+
+    fn base_url(&self) -> &str {
+        &self.label
+    }
+
+    async fn health(&self) -> bool {
+        self.list_models().await.is_ok()
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, String> {
+        let mut pending = self.send(InferenceCall::Models).await?;
+        match tokio::time::timeout(self.timeout, pending.recv()).await {
+            Ok(Some(InferenceResult::Models { names })) => Ok(names),
+            Ok(Some(InferenceResult::Failed { error })) => Err(error),
+            Ok(Some(_)) => Err("Unexpected reply from the peer".to_string()),
+            Ok(None) => Err("The peer disconnected".to_string()),
+            Err(_) => Err("The peer did not answer".to_string()),
+        }
+    }
+
+    async fn stream(&self, req: &LlmRequest) -> Result<ChatDeltaStream, String> {
+        let call = InferenceCall::Chat {
+            model: req.model.clone(),
+            messages: req.messages.clone(),
+            options: req.options.clone(),
+            no_cache: req.no_cache,
+        };
+        let pending = self.call(call).await?;
+        let idle_timeout = self.timeout;
+
+        // Ends after the last piece or the first error; a lost session ends it early. A
+        // host that stays connected but goes quiet is given up on, so the answer can
+        // move elsewhere.
+        Ok(futures::stream::unfold(Some(pending), move |pending| async move {
+            let mut pending = pending?;
+            let Ok(reply) = tokio::time::timeout(idle_timeout, pending.recv()).await else {
+                return Some((Err(format!("The peer sent nothing for {}s", idle_timeout.as_secs())), None));
+            };
+            match reply? {
+                InferenceResult::Delta { content, done, usage } => {
+                    let delta = ChatDelta { content, done, usage };
+                    Some((Ok(delta), if done { None } else { Some(pending) }))
+                }
+                InferenceResult::Failed { error } => Some((Err(error), None)),
+                _ => Some((Err("Unexpected reply from the peer".to_string()), None)),
+            }
+        }).boxed())
+    }
+
+    async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        let call = InferenceCall::Embed { model: model.to_string(), inputs: inputs.to_vec() };
+        let mut pending = self.call(call).await?;
+        match tokio::time::timeout(self.timeout, pending.recv()).await {
+            Ok(Some(InferenceResult::Embeddings { vectors })) => Ok(vectors),
+            Ok(Some(InferenceResult::Failed { error })) => Err(error),
+            Ok(Some(_)) => Err("Unexpected reply from the peer".to_string()),
+            Ok(None) => Err("The peer disconnected".to_string()),
+            Err(_) => Err("The peer did not answer".to_string()),
+        }
+    }
+}
@@ -0,0 +1,40 @@
+// Log output for the node. Events are tagged with the module that logged them, so
+// `logging.level` can raise or lower one subsystem (e.g. "info,neuromesh::tcp=debug").
+// Prompts, answers and conversation contents are left out unless `logging.log_content`
+// is set; events say how long they were instead.
+use std::fmt;
+use tracing_subscriber::EnvFilter;
+use crate::config::{LogFormat, LoggingConfig};
+
+// Installs the process-wide subscriber. Only the first call has any effect, so
+// several nodes in one process (as in tests) log through whichever came first.
+pub fn init(config: &LoggingConfig) -> Result<(), String> {
+    let filter = EnvFilter::try_new(&config.level)
+        .map_err(|e| format!("Invalid log level {:?}: {}", config.level, e))?;
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let result = match config.format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().flatten_event(true).with_current_span(true).try_init(),
+    };
+    result.map_err(|e| format!("Logging already set up: {}", e))
+}
+
+// User content as it should appear in a log event
+pub struct Content<'a> {
+    text: &'a str,
+    shown: bool,
+}
+
+pub fn content(text: &str, shown: bool) -> Content<'_> {
+    Content { text, shown }
+}
+
+impl fmt::Display for Content<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.shown {
+            write!(f, "{:?}", self.text)
+        } else {
+            write!(f, "<{} chars redacted>", self.text.chars().count())
+        }
+    }
+}
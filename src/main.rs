@@ -1,130 +1,48 @@
-mod udp;
-mod ip;
-mod tcp;
-mod llm;
-mod conversation;
-mod persistence;
+use clap::Parser;
+use neuromesh::cli::{self, Cli, Command, ServeArgs};
+use neuromesh::logging;
+use neuromesh::{Config, Node};
 
-use std::collections::HashSet;
-use std::sync::Arc;
-use actix_web::{get, App, HttpResponse, HttpServer, Responder, web};
-use actix_cors::Cors;
-use rust_embed::Embed;
-use tokio::sync::Mutex;
-use udp::{periodic_broadcast, receive_broadcast};
-use tcp::{connect_to_peers, listen_for_connections};
-use conversation::CONVERSATION_STORE;
-
-#[derive(Embed)]
-#[folder = "./webpage/build/"]
-struct WebAssets;
-
-fn send_file_or_default(path: String) -> HttpResponse {
-    let path = if path.starts_with("assets/") {
-        path
-    } else {
-        path.trim_start_matches("/app/").to_string()
-    };
-    
-    let asset = WebAssets::get(path.as_str());
-    match asset {
-        Some(file) => {
-            let mime_type = mime_guess::from_path(&path).first_or_octet_stream();
-            HttpResponse::Ok()
-                .content_type(mime_type.to_string())
-                .body(file.data)
-        }
-        None => {
-            let index_asset = WebAssets::get("index.html");
-            match index_asset {
-                Some(index_file) => {
-                    let mime_type = mime_guess::from_path("index.html").first_or_octet_stream();
-                    HttpResponse::Ok()
-                        .content_type(mime_type.to_string())
-                        .body(index_file.data)
-                }
-                None => HttpResponse::NotFound().body("Not Found"),
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let cli = Cli::parse();
+    match cli.command {
+        None => serve(cli.serve).await,
+        Some(Command::Serve(args)) => serve(*args).await,
+        Some(command) => {
+            if let Err(e) = cli::run(command).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
             }
+            Ok(())
         }
     }
 }
 
-#[get("/app/")]
-async fn get_index() -> impl Responder {
-    send_file_or_default("index.html".to_string())
-}
-
-#[get("/app/{path:.*}")]
-async fn get_root_files(path: actix_web::web::Path<String>) -> impl Responder {
-    let path = path.into_inner();
-    send_file_or_default(path)
-}
-
-#[get("/peers")]
-async fn get_peers() -> Result<HttpResponse, actix_web::Error> {
-    println!("API: Received request for peer conversations");
-    let peer_conversations = CONVERSATION_STORE.get_peer_conversations().await;
-    println!("API: Found {} peer conversations", peer_conversations.len());
-    for (peer, conv) in &peer_conversations {
-        println!("API: Peer {} has {} messages", peer, conv.messages.len());
-    }
-    Ok(HttpResponse::Ok().json(peer_conversations))
-}
-
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    // Initialize conversations directory silently
-    if let Err(e) = persistence::init_conversations_dir().await {
-        eprintln!("Error initializing conversations directory: {}", e);
-        return Err(e);
-    }
-
-    // Load saved conversations
-    if let Err(e) = CONVERSATION_STORE.load_saved_conversations().await {
-        eprintln!("Error loading saved conversations: {}", e);
+async fn serve(args: ServeArgs) -> std::io::Result<()> {
+    let config = match Config::load(&args.config) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error loading configuration: {}", e);
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, e));
+        }
+    };
+    if let Err(e) = logging::init(&config.logging) {
+        eprintln!("Error setting up logging: {}", e);
     }
 
-    let received_ips = Arc::new(Mutex::new(HashSet::new()));
-    let received_ips_clone = received_ips.clone();
-
-    // Start UDP broadcast receiver
-    tokio::spawn(async move {
-        if let Err(e) = receive_broadcast(received_ips_clone).await {
-            eprintln!("Error in UDP receiver task: {}", e);
+    let node = match Node::new(config).await {
+        Ok(node) => node,
+        Err(e) => {
+            tracing::error!("Error starting node: {}", e);
+            return Err(e);
         }
-    });
-    
-    // Start TCP listener
-    tokio::spawn(listen_for_connections());
-
-    // Start UDP broadcaster
-    tokio::spawn(periodic_broadcast());
-
-    // Start peer connector
-    let received_ips_clone = received_ips.clone();
-    tokio::spawn(connect_to_peers(received_ips_clone));
+    };
 
     // Open web browser silently
-    let _ = open::that("http://localhost:8080/app/");
-    
-    // Start HTTP server without console output
-    HttpServer::new(|| {
-        App::new()
-        .wrap(
-            Cors::default()
-                .allow_any_origin()
-                .allow_any_method()
-                .allow_any_header()
-                .expose_headers(["content-type", "content-length"])
-                .max_age(3600)
-        )
-            .service(web::scope("/api")
-                .service(llm::chat))
-            .service(get_peers)
-            .service(get_index)
-            .service(get_root_files)
-    })
-    .bind(("0.0.0.0", 8080))?
-    .run()
-    .await
+    if !args.no_browser {
+        let _ = open::that(format!("http://localhost:{}/app/", node.config().http.port));
+    }
+
+    node.run().await
 }
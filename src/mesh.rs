@@ -0,0 +1,341 @@
+// What this node knows about the mesh beyond its own sessions. Every session sends the
+// peer a status announcement on connect and at each share interval: who we are, whether
+// we run an LLM, what it serves, how busy it is and which nodes we are connected to.
+// Those announcements let /api/nodes and /api/topology describe nodes one hop further
+// than our own sessions reach.
+use std::collections::{BTreeSet, HashMap, HashSet};
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::warn;
+use crate::node::Node;
+use crate::tcp::{Direction, ReconnectState};
+
+// Announcements listing more than this are cut short rather than trusted in full
+const MAX_NEIGHBORS: usize = 256;
+const MAX_MODELS: usize = 256;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Neighbor {
+    pub node_id: String,
+    // The neighbor's listening address ("ip:port") as seen by the announcing node
+    pub address: String,
+    pub has_llm: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeAnnouncement {
+    pub node_id: String,
+    pub hostname: String,
+    pub version: String,
+    // Whether the node's own LLM server is up, whoever it grants access to
+    pub has_llm: bool,
+    pub models: Vec<String>,
+    // LLM requests the node has in flight on its own server
+    pub load: u64,
+    pub neighbors: Vec<Neighbor>,
+}
+
+#[derive(Clone)]
+struct KnownNode {
+    address: String,
+    announcement: NodeAnnouncement,
+    last_seen: DateTime<Utc>,
+}
+
+// The latest announcement from each peer, by node ID. Entries outlive the session so a
+// reconnecting peer keeps its name; only announcements from connected peers count
+// towards the topology.
+pub struct MeshView {
+    nodes: Mutex<HashMap<String, KnownNode>>,
+}
+
+impl MeshView {
+    pub fn new() -> Self {
+        MeshView { nodes: Mutex::new(HashMap::new()) }
+    }
+
+    // `node_id` is the session's peer; a peer cannot announce on behalf of another node
+    pub async fn record(&self, node_id: &str, address: &str, mut announcement: NodeAnnouncement) {
+        if announcement.node_id != node_id {
+            warn!("Ignoring status announcement for node {}", announcement.node_id);
+            return;
+        }
+        announcement.neighbors.truncate(MAX_NEIGHBORS);
+        announcement.models.truncate(MAX_MODELS);
+        self.nodes.lock().await.insert(node_id.to_string(), KnownNode {
+            address: address.to_string(),
+            announcement,
+            last_seen: Utc::now(),
+        });
+    }
+
+    async fn snapshot(&self) -> HashMap<String, KnownNode> {
+        self.nodes.lock().await.clone()
+    }
+}
+
+fn local_hostname() -> String {
+    hostname::get()
+        .map(|h| h.to_string_lossy().to_string())
+        .unwrap_or_else(|_| "Unknown".to_string())
+}
+
+// Models served by this node's LLM server, empty when it is down
+async fn local_models(node: &Node) -> (bool, Vec<String>) {
+    if !node.backend.health().await {
+        return (false, Vec::new());
+    }
+    (true, node.backend.list_models().await.unwrap_or_default())
+}
+
+// This node's status as sent to peers
+pub async fn announcement(node: &Node) -> NodeAnnouncement {
+    let (has_llm, models) = local_models(node).await;
+    let llm_peers = node.llm_peers.lock().await.clone();
+    let neighbors = node.sessions.sessions().await.into_iter()
+        .map(|session| Neighbor {
+            has_llm: llm_peers.contains(&session.node_id),
+            node_id: session.node_id,
+            address: session.peer_address,
+        })
+        .collect();
+    NodeAnnouncement {
+        node_id: node.node_id.clone(),
+        hostname: local_hostname(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        has_llm,
+        models,
+        load: node.metrics.inference_in_flight("local"),
+        neighbors,
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct NodeView {
+    // Unknown for a peer we have an address for but have never reached
+    pub node_id: Option<String>,
+    pub hostname: Option<String>,
+    pub version: Option<String>,
+    // Listening addresses ("ip:port") the node was reached at or reported at
+    pub addresses: Vec<String>,
+    // "connected", "connecting" or "backoff" for our own peers, "indirect" for nodes
+    // only our peers are connected to
+    pub state: &'static str,
+    pub direction: Option<Direction>,
+    // 1 for our own peers, 2 for their neighbors
+    pub hops: u8,
+    pub rtt_ms: Option<f64>,
+    pub has_llm: bool,
+    // The node granted us access to its LLM
+    pub llm_access: bool,
+    pub models: Vec<String>,
+    pub load: Option<u64>,
+    // When the node last sent us a status announcement
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+impl NodeView {
+    fn new(node_id: Option<String>, address: String, state: &'static str, hops: u8) -> Self {
+        NodeView {
+            node_id,
+            hostname: None,
+            version: None,
+            addresses: vec![address],
+            state,
+            direction: None,
+            hops,
+            rtt_ms: None,
+            has_llm: false,
+            llm_access: false,
+            models: Vec::new(),
+            load: None,
+            last_seen: None,
+        }
+    }
+
+    fn add_address(&mut self, address: &str) {
+        if !self.addresses.iter().any(|known| known == address) {
+            self.addresses.push(address.to_string());
+        }
+    }
+
+    fn describe(&mut self, known: &KnownNode) {
+        let announcement = &known.announcement;
+        self.hostname = Some(announcement.hostname.clone());
+        self.version = Some(announcement.version.clone());
+        self.models = announcement.models.clone();
+        self.load = Some(announcement.load);
+        self.last_seen = Some(known.last_seen);
+    }
+}
+
+// Every node we are connected to, trying to reach, or hear about from a peer
+pub async fn nodes(node: &Node) -> Vec<NodeView> {
+    let sessions = node.sessions.sessions().await;
+    let statuses = node.reconnect.get_statuses().await;
+    let known = node.mesh.snapshot().await;
+    let llm_peers = node.llm_peers.lock().await.clone();
+    let granted: HashSet<String> = node.llm_connections.lock().await.keys().cloned().collect();
+
+    let mut views: Vec<NodeView> = Vec::new();
+    for session in &sessions {
+        let mut view = NodeView::new(Some(session.node_id.clone()), session.peer_address.clone(), "connected", 1);
+        view.direction = Some(session.direction);
+        view.rtt_ms = node.peers.rtt(&session.addr.ip().to_string()).await.map(|rtt| rtt.as_secs_f64() * 1000.0);
+        view.has_llm = llm_peers.contains(&session.node_id);
+        view.llm_access = granted.contains(&session.node_id);
+        if let Some(known) = known.get(&session.node_id) {
+            view.describe(known);
+        }
+        views.push(view);
+    }
+
+    // Addresses we dial but have no session on; a past announcement may name the node
+    for status in statuses.iter().filter(|status| status.state != ReconnectState::Connected) {
+        let identity = known.iter().find(|(_, known)| known.address == status.address);
+        let node_id = identity.map(|(node_id, _)| node_id.clone());
+        if let Some(view) = views.iter_mut().find(|view| node_id.is_some() && view.node_id == node_id) {
+            view.add_address(&status.address);
+            continue;
+        }
+        let state = match status.state {
+            ReconnectState::Connecting => "connecting",
+            _ => "backoff",
+        };
+        let mut view = NodeView::new(node_id, status.address.clone(), state, 1);
+        match identity {
+            Some((_, known)) => view.describe(known),
+            None => view.last_seen = status.last_connected,
+        }
+        views.push(view);
+    }
+
+    // Neighbors as reported by connected peers
+    for session in &sessions {
+        let Some(peer) = known.get(&session.node_id) else { continue };
+        for neighbor in &peer.announcement.neighbors {
+            if neighbor.node_id == node.node_id {
+                continue;
+            }
+            let node_id = Some(neighbor.node_id.clone());
+            match views.iter_mut().find(|view| view.node_id == node_id) {
+                Some(view) => {
+                    view.add_address(&neighbor.address);
+                    if view.hops > 1 {
+                        view.has_llm |= neighbor.has_llm;
+                    }
+                }
+                None => {
+                    let mut view = NodeView::new(node_id, neighbor.address.clone(), "indirect", 2);
+                    view.has_llm = neighbor.has_llm;
+                    views.push(view);
+                }
+            }
+        }
+    }
+
+    views.sort_by(|a, b| (a.hops, &a.node_id, &a.addresses).cmp(&(b.hops, &b.node_id, &b.addresses)));
+    views
+}
+
+#[derive(Debug, Serialize)]
+pub struct TopologyNode {
+    pub node_id: String,
+    pub hostname: Option<String>,
+    pub local: bool,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TopologyEdge {
+    pub source: String,
+    pub target: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Topology {
+    pub nodes: Vec<TopologyNode>,
+    pub edges: Vec<TopologyEdge>,
+}
+
+fn edge(a: &str, b: &str) -> TopologyEdge {
+    // Connections are undirected; ordering the ends lets duplicates collapse
+    let (source, target) = if a <= b { (a, b) } else { (b, a) };
+    TopologyEdge { source: source.to_string(), target: target.to_string() }
+}
+
+// Our own sessions plus the sessions our connected peers announced
+pub async fn topology(node: &Node) -> Topology {
+    let sessions = node.sessions.sessions().await;
+    let known = node.mesh.snapshot().await;
+
+    let mut edges = BTreeSet::new();
+    for session in &sessions {
+        edges.insert(edge(&node.node_id, &session.node_id));
+        if let Some(peer) = known.get(&session.node_id) {
+            for neighbor in &peer.announcement.neighbors {
+                edges.insert(edge(&session.node_id, &neighbor.node_id));
+            }
+        }
+    }
+
+    let mut ids = BTreeSet::from([node.node_id.clone()]);
+    for edge in &edges {
+        ids.insert(edge.source.clone());
+        ids.insert(edge.target.clone());
+    }
+    let nodes = ids.into_iter()
+        .map(|node_id| TopologyNode {
+            local: node_id == node.node_id,
+            hostname: if node_id == node.node_id {
+                Some(local_hostname())
+            } else {
+                known.get(&node_id).map(|known| known.announcement.hostname.clone())
+            },
+            node_id,
+        })
+        .collect();
+    Topology { nodes, edges: edges.into_iter().collect() }
+}
+
+#[get("/nodes")]
+pub async fn list_nodes(node: web::Data<Node>) -> HttpResponse {
+    HttpResponse::Ok().json(nodes(&node).await)
+}
+
+#[get("/topology")]
+pub async fn get_topology(node: web::Data<Node>) -> HttpResponse {
+    HttpResponse::Ok().json(topology(&node).await)
+}
+
+#[get("/status")]
+pub async fn get_status(node: web::Data<Node>) -> HttpResponse {
+    let (healthy, models) = local_models(&node).await;
+    let connected = node.sessions.sessions().await.len();
+    let known = node.reconnect.get_statuses().await.len();
+    HttpResponse::Ok().json(serde_json::json!({
+        "node_id": node.node_id,
+        "hostname": local_hostname(),
+        "version": env!("CARGO_PKG_VERSION"),
+        "started_at": node.started_at,
+        "uptime_secs": (Utc::now() - node.started_at).num_seconds(),
+        "http_port": node.config.http.port,
+        "tcp_port": node.config.tcp.port,
+        "backend": {
+            "kind": node.backend.kind(),
+            "url": node.backend.base_url(),
+            "healthy": healthy,
+        },
+        "models": models,
+        "load": node.metrics.inference_in_flight("local"),
+        "peers": {
+            "connected": connected,
+            "known": known,
+            "with_llm": node.llm_peers.lock().await.len(),
+            "llm_access": node.llm_connections.lock().await.len(),
+        },
+        "pending_access_requests": node.access_queue.list().await.len(),
+        "shutting_down": node.shutdown.is_cancelled(),
+    }))
+}
@@ -0,0 +1,307 @@
+// Prometheus metrics for one node, served at /metrics. Counters are bumped where
+// things happen; gauges that describe current state (peers, queues) are read fresh
+// on every scrape. Each node has its own registry, so nodes sharing a process do not
+// mix their numbers. LLM requests are also announced on the node's event stream and
+// written to its usage ledger.
+use std::sync::Arc;
+use std::time::Instant;
+use chrono::{DateTime, Utc};
+use async_trait::async_trait;
+use futures::StreamExt;
+use tracing::error;
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+use crate::config::BackendKind;
+use crate::events::{Event, EventBus};
+use crate::ledger::{Ledger, LedgerEntry};
+use crate::llm::{ChatDeltaStream, LlmBackend, LlmRequest, Tally, TokenUsage};
+use crate::node::Node;
+use crate::tcp::ReconnectState;
+
+// Inference takes anywhere from a few milliseconds (embeddings) to minutes
+const INFERENCE_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+pub struct Metrics {
+    registry: Registry,
+    peers: IntGaugeVec,
+    peer_messages: IntCounterVec,
+    peer_bytes: IntCounterVec,
+    syncs: IntCounterVec,
+    access_decisions: IntCounterVec,
+    inference_requests: IntCounterVec,
+    inference_seconds: HistogramVec,
+    inference_in_flight: IntGaugeVec,
+    tokens_generated: IntCounterVec,
+    cache_lookups: IntCounterVec,
+    access_queue: IntGauge,
+    outbound_queue: IntGauge,
+}
+
+fn counter(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let counter = IntCounterVec::new(Opts::new(name, help), labels).expect("invalid metric definition");
+    registry.register(Box::new(counter.clone())).expect("metric registered twice");
+    counter
+}
+
+fn gauge(registry: &Registry, name: &str, help: &str) -> IntGauge {
+    let gauge = IntGauge::new(name, help).expect("invalid metric definition");
+    registry.register(Box::new(gauge.clone())).expect("metric registered twice");
+    gauge
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        let registry = Registry::new();
+
+        let peers = IntGaugeVec::new(Opts::new("neuromesh_peers", "Known peers by connection state"), &["state"])
+            .expect("invalid metric definition");
+        registry.register(Box::new(peers.clone())).expect("metric registered twice");
+        let inference_seconds = HistogramVec::new(
+            HistogramOpts::new("neuromesh_inference_duration_seconds", "Time from sending an LLM request to the end of its answer")
+                .buckets(INFERENCE_BUCKETS.to_vec()),
+            &["backend", "host", "operation"],
+        ).expect("invalid metric definition");
+        registry.register(Box::new(inference_seconds.clone())).expect("metric registered twice");
+        let inference_in_flight = IntGaugeVec::new(Opts::new("neuromesh_inference_in_flight", "LLM requests sent and not yet answered"), &["host"])
+            .expect("invalid metric definition");
+        registry.register(Box::new(inference_in_flight.clone())).expect("metric registered twice");
+
+        Metrics {
+            peers,
+            peer_messages: counter(&registry, "neuromesh_peer_messages_total", "Messages exchanged with peers", &["direction", "type"]),
+            peer_bytes: counter(&registry, "neuromesh_peer_bytes_total", "Bytes exchanged with peers, framing included", &["direction", "type"]),
+            syncs: counter(&registry, "neuromesh_conversation_syncs_total", "Conversations sent to and accepted from peers", &["direction"]),
+            access_decisions: counter(&registry, "neuromesh_access_decisions_total", "LLM access responses sent to and received from peers", &["direction", "outcome"]),
+            inference_requests: counter(&registry, "neuromesh_inference_requests_total", "LLM requests by outcome", &["backend", "host", "operation", "outcome"]),
+            inference_seconds,
+            inference_in_flight,
+            tokens_generated: counter(&registry, "neuromesh_tokens_generated_total", "Tokens generated, as reported by the LLM server", &["backend", "host"]),
+            cache_lookups: counter(&registry, "neuromesh_response_cache_lookups_total", "Cacheable chat requests, answered from the cache or not", &["result"]),
+            access_queue: gauge(&registry, "neuromesh_access_queue_depth", "LLM access requests waiting for approval"),
+            outbound_queue: gauge(&registry, "neuromesh_peer_outbound_queue_depth", "Messages queued for peers but not yet written"),
+            registry,
+        }
+    }
+
+    pub fn message_sent(&self, kind: &str, bytes: usize) {
+        self.peer_messages.with_label_values(&["sent", kind]).inc();
+        self.peer_bytes.with_label_values(&["sent", kind]).inc_by(bytes as u64);
+    }
+
+    pub fn message_received(&self, kind: &str, bytes: usize) {
+        self.peer_messages.with_label_values(&["received", kind]).inc();
+        self.peer_bytes.with_label_values(&["received", kind]).inc_by(bytes as u64);
+    }
+
+    // `direction` is "sent" or "received"
+    pub fn conversation_synced(&self, direction: &str) {
+        self.syncs.with_label_values(&[direction]).inc();
+    }
+
+    pub fn access_decision(&self, direction: &str, granted: bool) {
+        let outcome = if granted { "granted" } else { "denied" };
+        self.access_decisions.with_label_values(&[direction, outcome]).inc();
+    }
+
+    pub fn cache_lookup(&self, hit: bool) {
+        self.cache_lookups.with_label_values(&[if hit { "hit" } else { "miss" }]).inc();
+    }
+
+    // LLM requests in flight through the given host ("local" or "remote")
+    pub fn inference_in_flight(&self, host: &str) -> u64 {
+        self.inference_in_flight.with_label_values(&[host]).get().max(0) as u64
+    }
+
+    fn inference_done(&self, labels: &InferenceLabels, outcome: &str, started: Instant) {
+        let (backend, host, operation) = (labels.backend.as_str(), labels.host, labels.operation);
+        self.inference_in_flight.with_label_values(&[host]).dec();
+        self.inference_requests.with_label_values(&[backend, host, operation, outcome]).inc();
+        self.inference_seconds.with_label_values(&[backend, host, operation]).observe(started.elapsed().as_secs_f64());
+    }
+
+    // The text exposition format, with the state gauges brought up to date first
+    pub async fn render(&self, node: &Node) -> String {
+        let statuses = node.reconnect.get_statuses().await;
+        for (state, label) in [
+            (ReconnectState::Connecting, "connecting"),
+            (ReconnectState::Connected, "connected"),
+            (ReconnectState::Backoff, "backoff"),
+        ] {
+            let count = statuses.iter().filter(|status| status.state == state).count();
+            self.peers.with_label_values(&[label]).set(count as i64);
+        }
+        self.access_queue.set(node.access_queue.list().await.len() as i64);
+        self.outbound_queue.set(node.sessions.queued_messages().await as i64);
+
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            error!("Failed to encode metrics: {}", e);
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+struct InferenceLabels {
+    backend: BackendKind,
+    host: &'static str,
+    operation: &'static str,
+}
+
+// One LLM request from start to finish, counted, announced and written to the ledger
+// when it ends
+struct Inference {
+    metrics: Arc<Metrics>,
+    events: Arc<EventBus>,
+    ledger: Arc<Ledger>,
+    parties: Parties,
+    labels: InferenceLabels,
+    model: String,
+    id: u64,
+    sent_at: DateTime<Utc>,
+    started: Instant,
+    // What a chat streamed, for an answer that ends early
+    tally: Tally,
+    recorded: bool,
+}
+
+impl Inference {
+    fn start(backend: &MeteredBackend, operation: &'static str, model: &str) -> Inference {
+        let labels = InferenceLabels { backend: backend.inner.kind(), host: backend.host, operation };
+        let id = backend.events.next_inference_id();
+        backend.metrics.inference_in_flight.with_label_values(&[labels.host]).inc();
+        backend.events.publish(Event::InferenceStarted {
+            id,
+            backend: labels.backend.as_str(),
+            host: labels.host,
+            operation,
+            model: model.to_string(),
+        });
+        Inference {
+            metrics: backend.metrics.clone(),
+            events: backend.events.clone(),
+            ledger: backend.ledger.clone(),
+            parties: backend.parties.clone(),
+            labels,
+            model: model.to_string(),
+            id,
+            sent_at: Utc::now(),
+            started: Instant::now(),
+            tally: Tally::default(),
+            recorded: false,
+        }
+    }
+
+    fn record(&mut self, outcome: &'static str, usage: Option<TokenUsage>) {
+        if self.recorded {
+            return;
+        }
+        self.recorded = true;
+        let duration_ms = self.started.elapsed().as_millis() as u64;
+        self.metrics.inference_done(&self.labels, outcome, self.started);
+        // An answer cut short counts what it streamed, as a host charges a peer for it
+        let completion_tokens = usage.map(|usage| usage.completion_tokens).or(self.tally.partial());
+        self.events.publish(Event::InferenceFinished {
+            id: self.id,
+            outcome,
+            duration_ms,
+            tokens: completion_tokens,
+        });
+        self.ledger.record(LedgerEntry {
+            at: self.sent_at,
+            requester: self.parties.requester.clone(),
+            host: self.parties.host.clone(),
+            model: self.model.clone(),
+            operation: self.labels.operation.to_string(),
+            outcome: outcome.to_string(),
+            prompt_tokens: usage.map(|usage| usage.prompt_tokens),
+            completion_tokens,
+            duration_ms,
+            server_total_ms: usage.and_then(|usage| usage.total_duration_ms),
+            server_eval_ms: usage.and_then(|usage| usage.eval_duration_ms),
+        });
+    }
+}
+
+// A chat stream dropped before its last piece, because the client left or the server
+// stopped sending, counts as incomplete
+impl Drop for Inference {
+    fn drop(&mut self) {
+        self.record("incomplete", None);
+    }
+}
+
+// Node IDs of whoever a request runs for and whoever runs it
+#[derive(Clone)]
+pub struct Parties {
+    pub requester: String,
+    pub host: String,
+}
+
+// Counts, times, announces and records the requests sent through `inner`. `host` is
+// "local" or "remote".
+struct MeteredBackend {
+    inner: Arc<dyn LlmBackend>,
+    host: &'static str,
+    parties: Parties,
+    metrics: Arc<Metrics>,
+    events: Arc<EventBus>,
+    ledger: Arc<Ledger>,
+}
+
+pub fn metered(inner: Arc<dyn LlmBackend>, host: &'static str, parties: Parties, metrics: Arc<Metrics>, events: Arc<EventBus>, ledger: Arc<Ledger>) -> Arc<dyn LlmBackend> {
+    Arc::new(MeteredBackend { inner, host, parties, metrics, events, ledger })
+}
+
+#[async_trait]
+impl LlmBackend for MeteredBackend {
+    fn kind(&self) -> BackendKind {
+        self.inner.kind()
+    }
+
+    fn base_url(&self) -> &str {
+        self.inner.base_url()
+    }
+
+    async fn health(&self) -> bool {
+        self.inner.health().await
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, String> {
+        self.inner.list_models().await
+    }
+
+    async fn stream(&self, req: &LlmRequest) -> Result<ChatDeltaStream, String> {
+        let mut inference = Inference::start(self, "chat", &req.model);
+        let upstream = match self.inner.stream(req).await {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                inference.record("error", None);
+                return Err(e);
+            }
+        };
+
+        let tokens = self.metrics.tokens_generated.with_label_values(&[self.inner.kind().as_str(), self.host]);
+        Ok(upstream.map(move |delta| {
+            if let Ok(delta) = &delta {
+                inference.tally.add(delta);
+            }
+            match &delta {
+                Ok(delta) if delta.done => {
+                    if let Some(usage) = delta.usage {
+                        tokens.inc_by(usage.completion_tokens);
+                    }
+                    inference.record("ok", delta.usage);
+                }
+                Ok(_) => {}
+                Err(_) => inference.record("error", None),
+            }
+            delta
+        }).boxed())
+    }
+
+    async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        let mut inference = Inference::start(self, "embed", model);
+        let result = self.inner.embed(model, inputs).await;
+        inference.record(if result.is_ok() { "ok" } else { "error" }, None);
+        result
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use crate::config::Config;
+use crate::conversation::ConversationStore;
+use crate::events::{Event, EventBus};
+use crate::files::{DocumentStore, SharedStore};
+use crate::ledger::Ledger;
+use crate::llm::{self, ActiveRequests, LlmBackend, LlmEndpoint, Quotas, RemoteCalls, ResponseCache};
+use crate::mesh::MeshView;
+use crate::metrics::{self, Metrics, Parties};
+use crate::peers::PeerRegistry;
+use crate::sandbox::PeerStorage;
+use crate::tcp::{self, AccessQueue, ConnectionManager, ReconnectScheduler};
+use crate::udp::{self, Discovery};
+use crate::server;
+use crate::supervisor::Supervisor;
+
+// How often shutdown checks whether every session has said goodbye
+const SESSION_DRAIN_POLL: Duration = Duration::from_millis(50);
+
+// Everything one running NeuroMesh node owns. Nothing is process-wide, so several
+// nodes can live in one process (tests, embedding) as long as their ports and
+// directories differ.
+pub struct Node {
+    pub(crate) config: Config,
+    pub(crate) node_id: String,
+    pub(crate) conversations: ConversationStore,
+    pub(crate) documents: DocumentStore,
+    pub(crate) shared: SharedStore,
+    // Round-trip times measured by heartbeats, keyed by peer IP
+    pub(crate) peers: PeerRegistry,
+    pub(crate) sessions: ConnectionManager,
+    // What peers last told us about themselves and their own sessions
+    pub(crate) mesh: MeshView,
+    pub(crate) reconnect: ReconnectScheduler,
+    pub(crate) access_queue: AccessQueue,
+    pub(crate) discovery: Discovery,
+    // The LLM server configured for this node, whether or not it is running, metered as
+    // our own use
+    pub(crate) backend: Arc<dyn LlmBackend>,
+    // The same server unmetered, for requests run on behalf of peers
+    pub(crate) server: Arc<dyn LlmBackend>,
+    pub(crate) metrics: Arc<Metrics>,
+    pub(crate) events: Arc<EventBus>,
+    // Every LLM request this node asked for or ran
+    pub(crate) ledger: Arc<Ledger>,
+    // Peers that announced an LLM, by node ID
+    pub(crate) llm_peers: Mutex<HashSet<String>>,
+    // Peers we granted access to our LLM, by IP
+    pub(crate) authorized_peers: Mutex<HashSet<String>>,
+    // Peers that granted us access, by node ID, with the address of their LLM. Keyed by
+    // ID rather than IP so several nodes behind one address each count as a host.
+    pub(crate) llm_connections: Mutex<HashMap<String, LlmEndpoint>>,
+    // LLM requests running here, for our clients or for peers
+    pub(crate) requests: Arc<ActiveRequests>,
+    // LLM requests we sent to peers and are waiting on
+    pub(crate) remote_calls: Arc<RemoteCalls>,
+    // How much each peer has asked of our LLM, against `limits`
+    pub(crate) quotas: Arc<Quotas>,
+    // Answers to requests that should always get the same one
+    pub(crate) cache: Arc<ResponseCache>,
+    // Cancelled once to stop the node; every task and session watches it
+    pub(crate) shutdown: CancellationToken,
+    pub(crate) supervisor: Supervisor,
+    pub(crate) started_at: DateTime<Utc>,
+}
+
+impl Node {
+    // Prepares storage, loads this node's identity and saved conversations. Nothing
+    // touches the network until `run`.
+    pub async fn new(config: Config) -> std::io::Result<Arc<Node>> {
+        config.validate()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+        let conversations = ConversationStore::new(
+            config.storage.conversations_dir.clone(),
+            PeerStorage::new(config.storage.received_dir.clone(), config.peer_storage_quota_bytes()),
+        );
+        conversations.init_dirs().await?;
+        let node_id = conversations.load_or_create_node_id().await?;
+        info!(node = %node_id, "Node starting");
+
+        if let Err(e) = conversations.load_saved_conversations().await {
+            error!("Error loading saved conversations: {}", e);
+        }
+        let documents = DocumentStore::open(&config.storage.documents_dir).await?;
+        let shared = SharedStore::open(&config.storage.shared_dir).await?;
+        let quotas = Quotas::open(&config.storage.conversations_dir).await;
+        let ledger = Ledger::open(&config.storage.conversations_dir, &node_id).await;
+
+        let metrics = Arc::new(Metrics::new());
+        let events = Arc::new(EventBus::new());
+        let server = llm::connect(config.llm.backend, config.backend_url(), config.openai.api_key.clone());
+        let ourselves = Parties { requester: node_id.clone(), host: node_id.clone() };
+        let backend = metrics::metered(server.clone(), "local", ourselves, metrics.clone(), events.clone(), ledger.clone());
+        let cache = ResponseCache::new(&config, metrics.clone());
+        let shutdown = CancellationToken::new();
+        let supervisor = Supervisor::new(shutdown.clone(), config.tasks.max_restarts);
+        Ok(Arc::new(Node {
+            backend,
+            server,
+            metrics,
+            events,
+            ledger,
+            config,
+            sessions: ConnectionManager::new(node_id.clone()),
+            node_id,
+            conversations,
+            documents,
+            shared,
+            peers: PeerRegistry::new(),
+            mesh: MeshView::new(),
+            reconnect: ReconnectScheduler::new(),
+            access_queue: AccessQueue::new(),
+            discovery: Discovery::new(),
+            llm_peers: Mutex::new(HashSet::new()),
+            authorized_peers: Mutex::new(HashSet::new()),
+            llm_connections: Mutex::new(HashMap::new()),
+            requests: ActiveRequests::new(),
+            remote_calls: RemoteCalls::new(),
+            quotas,
+            cache,
+            supervisor,
+            shutdown,
+            started_at: Utc::now(),
+        }))
+    }
+
+    // Counts an LLM access response sent or received and announces it
+    pub(crate) fn access_decided(&self, node_id: &str, direction: &'static str, granted: bool) {
+        self.metrics.access_decision(direction, granted);
+        self.events.publish(Event::AccessDecided { node_id: node_id.to_string(), direction, granted });
+    }
+
+    pub(crate) fn conversation_synced(&self, node_id: &str, conversation: &str, messages: usize, direction: &'static str) {
+        self.metrics.conversation_synced(direction);
+        self.events.publish(Event::SyncCompleted {
+            node_id: node_id.to_string(),
+            conversation: conversation.to_string(),
+            direction,
+            messages,
+        });
+    }
+
+    // `inner` metered and recorded as a request `requester` makes of `host`
+    pub(crate) fn metered(&self, inner: Arc<dyn LlmBackend>, label: &'static str, requester: &str, host: &str) -> Arc<dyn LlmBackend> {
+        let parties = Parties { requester: requester.to_string(), host: host.to_string() };
+        metrics::metered(inner, label, parties, self.metrics.clone(), self.events.clone(), self.ledger.clone())
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // Asks a running node to shut down; `run` returns once it has
+    pub fn stop(&self) {
+        self.shutdown.cancel();
+    }
+
+    // Starts the mesh networking tasks and serves the web interface and HTTP API
+    // until Ctrl-C or `stop`, then shuts down gracefully
+    pub async fn run(self: Arc<Self>) -> std::io::Result<()> {
+        let server = server::start(self.clone())?;
+        let server_handle = server.handle();
+        let mut server = tokio::spawn(server);
+
+        let node = self.clone();
+        self.supervisor.spawn("tcp-listener", move || tcp::listen_for_connections(node.clone())).await;
+
+        if self.config.udp.enabled {
+            let node = self.clone();
+            self.supervisor.spawn("udp-receiver", move || udp::receive_broadcast(node.clone())).await;
+            let node = self.clone();
+            self.supervisor.spawn("udp-broadcaster", move || {
+                let node = node.clone();
+                async move {
+                    udp::periodic_broadcast(node).await;
+                    Ok(())
+                }
+            }).await;
+        } else {
+            info!("UDP discovery disabled; connecting to configured peers only");
+        }
+
+        let node = self.clone();
+        self.supervisor.spawn("peer-connector", move || {
+            let node = node.clone();
+            async move {
+                tcp::connect_to_peers(node).await;
+                Ok(())
+            }
+        }).await;
+
+        let server_result = tokio::select! {
+            result = &mut server => Some(result),
+            _ = tokio::signal::ctrl_c() => {
+                info!("Received Ctrl-C, shutting down");
+                None
+            }
+            _ = self.shutdown.cancelled() => None,
+        };
+
+        match server_result {
+            Some(result) => {
+                self.shut_down().await;
+                result.map_err(std::io::Error::other)?
+            }
+            None => {
+                // In-flight HTTP requests get the same deadline as peer sessions (actix
+                // enforces it), and both drain at the same time
+                tokio::join!(self.shut_down(), server_handle.stop(true));
+                server.await.map_err(std::io::Error::other)?
+            }
+        }
+    }
+
+    async fn shut_down(&self) {
+        self.shutdown.cancel();
+
+        // Sessions notice the cancellation themselves and send Goodbye before closing
+        let deadline = self.config.shutdown_timeout();
+        let drained = tokio::time::timeout(deadline, async {
+            self.supervisor.join().await;
+            while !self.sessions.is_empty().await {
+                tokio::time::sleep(SESSION_DRAIN_POLL).await;
+            }
+        }).await;
+        if drained.is_err() {
+            warn!("Peer sessions did not close within {}s, continuing shutdown", deadline.as_secs());
+        }
+
+        // Always runs, so conversations are on disk however the sessions ended
+        if let Err(e) = self.conversations.flush().await {
+            error!("Error saving conversations during shutdown: {}", e);
+        }
+        self.ledger.flush().await;
+        info!(node = %self.node_id, "Node stopped");
+    }
+}
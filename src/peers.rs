@@ -0,0 +1,63 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use chrono::{DateTime, Utc};
+
+// Weight given to a new sample when updating the smoothed RTT (same as TCP's SRTT)
+const RTT_SMOOTHING: f64 = 0.125;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct PeerInfo {
+    pub ip: String,
+    pub rtt_ms: Option<f64>,
+    pub smoothed_rtt_ms: Option<f64>,
+    pub last_pong: Option<DateTime<Utc>>,
+}
+
+impl PeerInfo {
+    fn new(ip: String) -> Self {
+        PeerInfo {
+            ip,
+            rtt_ms: None,
+            smoothed_rtt_ms: None,
+            last_pong: None,
+        }
+    }
+}
+
+pub struct PeerRegistry {
+    peers: Mutex<HashMap<String, PeerInfo>>,
+}
+
+impl PeerRegistry {
+    pub fn new() -> Self {
+        PeerRegistry {
+            peers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn record_rtt(&self, ip: &str, rtt: Duration) {
+        let mut peers = self.peers.lock().await;
+        let peer = peers
+            .entry(ip.to_string())
+            .or_insert_with(|| PeerInfo::new(ip.to_string()));
+
+        let sample = rtt.as_secs_f64() * 1000.0;
+        peer.rtt_ms = Some(sample);
+        peer.smoothed_rtt_ms = Some(match peer.smoothed_rtt_ms {
+            Some(srtt) => srtt + RTT_SMOOTHING * (sample - srtt),
+            None => sample,
+        });
+        peer.last_pong = Some(Utc::now());
+    }
+
+    // Smoothed round-trip time to the peer, if we have measured it yet
+    pub async fn rtt(&self, ip: &str) -> Option<Duration> {
+        let peers = self.peers.lock().await;
+        peers
+            .get(ip)
+            .and_then(|peer| peer.smoothed_rtt_ms)
+            .map(|ms| Duration::from_secs_f64(ms / 1000.0))
+    }
+}
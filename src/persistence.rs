@@ -1,16 +1,20 @@
 use std::path::Path;
 use tokio::fs;
-use serde_json;
 use crate::conversation::Conversation;
 use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
 
-pub const CONVERSATIONS_DIR: &str = "conversations";
-pub const RECEIVED_DIR: &str = "received";
+// Writes to a temporary file and renames it over the target, so an interrupted write
+// never leaves a truncated file behind
+pub(crate) async fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let file_name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let temp_path = path.with_file_name(format!("{}.tmp", file_name));
+    fs::write(&temp_path, contents).await?;
+    fs::rename(&temp_path, path).await
+}
 
-pub async fn init_conversations_dir() -> std::io::Result<()> {
-    let conversations_path = Path::new(CONVERSATIONS_DIR);
-    let received_path = Path::new(RECEIVED_DIR);
-    
+pub async fn init_conversations_dir(conversations_path: &Path, received_path: &Path) -> std::io::Result<()> {
     if !conversations_path.exists() {
         fs::create_dir_all(conversations_path).await?;
     }
@@ -20,27 +24,43 @@ pub async fn init_conversations_dir() -> std::io::Result<()> {
     Ok(())
 }
 
-pub async fn save_local_conversation(conversation: &Conversation) -> std::io::Result<()> {
-    let file_path = Path::new(CONVERSATIONS_DIR).join("local.json");
-    let json = serde_json::to_string_pretty(conversation)?;
-    fs::write(file_path, json).await?;
-    Ok(())
+// Stable identifier for this node, generated on first start and reused afterwards
+pub async fn load_or_create_node_id(conversations_dir: &Path) -> std::io::Result<String> {
+    let file_path = conversations_dir.join("node_id");
+    if file_path.exists() {
+        let node_id = fs::read_to_string(&file_path).await?.trim().to_string();
+        if !node_id.is_empty() {
+            return Ok(node_id);
+        }
+    }
+
+    let hostname = hostname::get()
+        .map(|h| h.to_string_lossy().to_string())
+        .unwrap_or_else(|_| "Unknown".to_string());
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+
+    let mut hasher = Sha256::new();
+    hasher.update(hostname.as_bytes());
+    hasher.update(std::process::id().to_le_bytes());
+    hasher.update(nanos.to_le_bytes());
+    let node_id = hex::encode(&hasher.finalize()[..16]);
+
+    write_atomic(&file_path, node_id.as_bytes()).await?;
+    Ok(node_id)
 }
 
-pub async fn save_peer_conversation(peer_ip: &str, conversation: &Conversation) -> std::io::Result<()> {
-    let peer_dir = Path::new(RECEIVED_DIR).join(peer_ip);
-    if !peer_dir.exists() {
-        fs::create_dir_all(&peer_dir).await?;
-    }
-    
-    let file_path = peer_dir.join("local.json");
+pub async fn save_local_conversation(conversations_dir: &Path, conversation: &Conversation) -> std::io::Result<()> {
+    let file_path = conversations_dir.join("local.json");
     let json = serde_json::to_string_pretty(conversation)?;
-    fs::write(file_path, json).await?;
+    write_atomic(&file_path, json.as_bytes()).await?;
     Ok(())
 }
 
-pub async fn load_local_conversation() -> std::io::Result<Option<Conversation>> {
-    let file_path = Path::new(CONVERSATIONS_DIR).join("local.json");
+pub async fn load_local_conversation(conversations_dir: &Path) -> std::io::Result<Option<Conversation>> {
+    let file_path = conversations_dir.join("local.json");
     if !file_path.exists() {
         return Ok(None);
     }
@@ -50,14 +70,13 @@ pub async fn load_local_conversation() -> std::io::Result<Option<Conversation>>
     Ok(Some(conversation))
 }
 
-pub async fn load_all_peer_conversations() -> std::io::Result<HashMap<String, Conversation>> {
+pub async fn load_all_peer_conversations(received_path: &Path) -> std::io::Result<HashMap<String, Conversation>> {
     let mut peer_conversations = HashMap::new();
-    let received_path = Path::new(RECEIVED_DIR);
     
-    println!("Loading peer conversations from: {}", received_path.display());
+    debug!(path = %received_path.display(), "Loading peer conversations");
     
     if !received_path.exists() {
-        println!("Creating received directory as it does not exist");
+        debug!("Creating received directory as it does not exist");
         fs::create_dir_all(received_path).await?;
         return Ok(peer_conversations);
     }
@@ -68,42 +87,33 @@ pub async fn load_all_peer_conversations() -> std::io::Result<HashMap<String, Co
         let file_type = entry.file_type().await?;
         let peer_ip = entry.file_name().to_string_lossy().to_string();
         
-        println!("Found entry: {} (is_dir: {})", peer_ip, file_type.is_dir());
-        
         if file_type.is_dir() {
             let local_json_path = entry.path().join("local.json");
-            println!("Checking for local.json at: {}", local_json_path.display());
             
             if local_json_path.exists() {
-                println!("Found local.json for peer: {}", peer_ip);
                 match fs::read_to_string(&local_json_path).await {
                     Ok(content) => {
+                        // The error says where parsing stopped; the content itself is a
+                        // private conversation and stays out of the log
                         match serde_json::from_str::<Conversation>(&content) {
                             Ok(conversation) => {
-                                println!("Successfully loaded conversation for peer: {}", peer_ip);
-                                println!("Conversation contains {} messages", conversation.messages.len());
+                                debug!(peer = %peer_ip, messages = conversation.messages.len(), "Loaded peer conversation");
                                 peer_conversations.insert(peer_ip, conversation);
                             }
                             Err(e) => {
-                                eprintln!("Failed to parse conversation for peer {}: {}", peer_ip, e);
-                                eprintln!("Content: {}", content);
+                                warn!(peer = %peer_ip, path = %local_json_path.display(), "Failed to parse peer conversation: {}", e);
                             }
                         }
                     }
                     Err(e) => {
-                        eprintln!("Failed to read local.json for peer {}: {}", peer_ip, e);
+                        warn!(peer = %peer_ip, path = %local_json_path.display(), "Failed to read peer conversation: {}", e);
                     }
                 }
             } else {
-                println!("No local.json found at: {}", local_json_path.display());
+                debug!(peer = %peer_ip, "No conversation saved for peer");
             }
         }
     }
     
-    println!("Loaded {} peer conversations", peer_conversations.len());
-    for (peer, conv) in &peer_conversations {
-        println!("Peer {} has {} messages", peer, conv.messages.len());
-    }
-    
     Ok(peer_conversations)
 } 
\ No newline at end of file
@@ -0,0 +1,102 @@
+// Storage for data that arrives from peers. Every path is one checked name below a
+// fixed root, so nothing a peer sends can point a write anywhere else, and each peer's
+// directory is held to a disk quota. Only typed values are written, so a payload has
+// been parsed (and rejected if it did not parse) before it reaches disk.
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use tokio::fs;
+use tokio::sync::Mutex;
+use crate::persistence::write_atomic;
+
+const MAX_NAME_LEN: usize = 255;
+
+fn invalid_name(name: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Unsafe file name {:?}", name))
+}
+
+// Letters, digits, '.', '_' and '-', not starting with a dot. That rules out
+// separators, "..", drive prefixes and names Windows would treat specially.
+pub fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && !name.starts_with('.')
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+// `root` joined with `name`, if `name` is safe
+pub fn confined(root: &Path, name: &str) -> std::io::Result<PathBuf> {
+    if !is_safe_name(name) {
+        return Err(invalid_name(name));
+    }
+    Ok(root.join(name))
+}
+
+// Directory name for a peer key such as its IP. IPv6 colons and zone IDs are not
+// valid in Windows file names, so anything outside the safe set becomes '_'.
+pub fn peer_dir_name(peer: &str) -> std::io::Result<String> {
+    let name: String = peer
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    if is_safe_name(&name) {
+        Ok(name)
+    } else {
+        Err(invalid_name(peer))
+    }
+}
+
+// One directory per peer below `root`, each limited to `quota` bytes
+pub struct PeerStorage {
+    root: PathBuf,
+    quota: u64,
+    // Quota checks and the writes they allow happen one at a time
+    writing: Mutex<()>,
+}
+
+impl PeerStorage {
+    pub fn new(root: PathBuf, quota: u64) -> Self {
+        PeerStorage { root, quota, writing: Mutex::new(()) }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn peer_dir(&self, peer: &str) -> std::io::Result<PathBuf> {
+        confined(&self.root, &peer_dir_name(peer)?)
+    }
+
+    // Bytes in the peer's directory, not counting `except`
+    async fn usage(dir: &Path, except: &Path) -> std::io::Result<u64> {
+        let mut total = 0;
+        let mut entries = match fs::read_dir(dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.path() != except {
+                total += entry.metadata().await?.len();
+            }
+        }
+        Ok(total)
+    }
+
+    // Replaces the peer's file `name` with `value` as pretty JSON, unless that would
+    // take the peer over its quota
+    pub async fn write_json<T: Serialize>(&self, peer: &str, name: &str, value: &T) -> std::io::Result<()> {
+        let dir = self.peer_dir(peer)?;
+        let path = confined(&dir, name)?;
+        let contents = serde_json::to_vec_pretty(value)?;
+
+        let _writing = self.writing.lock().await;
+        let used = Self::usage(&dir, &path).await?;
+        if used + contents.len() as u64 > self.quota {
+            return Err(std::io::Error::other(
+                format!("Peer {} would exceed its {} byte storage quota", peer, self.quota),
+            ));
+        }
+        fs::create_dir_all(&dir).await?;
+        write_atomic(&path, &contents).await
+    }
+}
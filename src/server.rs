@@ -0,0 +1,276 @@
+use std::future::{ready, Ready};
+use std::net::IpAddr;
+use std::sync::Arc;
+use actix_web::{get, post, App, FromRequest, HttpRequest, HttpResponse, HttpServer, Responder, web};
+use actix_web::dev::{Payload, RequestHead, Server};
+use actix_web::error::InternalError;
+use actix_web::http::header::{self, HeaderValue};
+use actix_cors::Cors;
+use rust_embed::Embed;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+use crate::events;
+use crate::files;
+use crate::ledger;
+use crate::llm;
+use crate::mesh;
+use crate::node::Node;
+use crate::supervisor::TaskState;
+use crate::tcp;
+
+// Chat histories and embedding batches easily outgrow actix's default JSON limit
+const JSON_LIMIT: usize = 16 * 1024 * 1024;
+
+#[derive(Embed)]
+#[folder = "./webpage/build/"]
+struct WebAssets;
+
+fn send_file_or_default(path: String) -> HttpResponse {
+    let path = if path.starts_with("assets/") {
+        path
+    } else {
+        path.trim_start_matches("/app/").to_string()
+    };
+    
+    let asset = WebAssets::get(path.as_str());
+    match asset {
+        Some(file) => {
+            let mime_type = mime_guess::from_path(&path).first_or_octet_stream();
+            HttpResponse::Ok()
+                .content_type(mime_type.to_string())
+                .body(file.data)
+        }
+        None => {
+            let index_asset = WebAssets::get("index.html");
+            match index_asset {
+                Some(index_file) => {
+                    let mime_type = mime_guess::from_path("index.html").first_or_octet_stream();
+                    HttpResponse::Ok()
+                        .content_type(mime_type.to_string())
+                        .body(index_file.data)
+                }
+                None => HttpResponse::NotFound().body("Not Found"),
+            }
+        }
+    }
+}
+
+// Required by routes only the node's operator may use: callers on this machine, through
+// the node's own pages or without a browser, or anyone with `http.admin_token` as a
+// bearer token
+pub(crate) struct Operator;
+
+impl FromRequest for Operator {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let allowed = req.app_data::<web::Data<Node>>().is_some_and(|node| is_operator(node, req));
+        if !allowed {
+            warn!(client = ?req.peer_addr(), path = req.path(), "Refused a request to an operator route");
+            return ready(Err(InternalError::from_response("operator only", operator_only()).into()));
+        }
+        ready(Ok(Operator))
+    }
+}
+
+pub(crate) fn operator_only() -> HttpResponse {
+    HttpResponse::Forbidden().json(serde_json::json!({ "error": "Only the node's operator may do this" }))
+}
+
+pub(crate) fn is_operator(node: &Node, req: &HttpRequest) -> bool {
+    let headers = req.headers();
+    if let Some(token) = &node.config.http.admin_token {
+        let bearer = headers.get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        // Digests compare in the same time however much of the token was guessed
+        if bearer.is_some_and(|given| Sha256::digest(given) == Sha256::digest(token)) {
+            return true;
+        }
+    }
+    // The Host check turns away other sites' names rebound to this machine
+    req.peer_addr().is_some_and(|addr| addr.ip().is_loopback())
+        && headers.get(header::HOST).and_then(|host| host.to_str().ok()).is_some_and(is_loopback_host)
+        && headers.get(header::ORIGIN).is_none_or(|origin| same_origin(origin, req.head()))
+}
+
+fn is_loopback_host(host: &str) -> bool {
+    let name = host.rsplit_once(':')
+        .filter(|(_, port)| port.parse::<u16>().is_ok())
+        .map_or(host, |(name, _)| name);
+    let name = name.trim_start_matches('[').trim_end_matches(']');
+    name == "localhost" || name.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+// Whether a browser request comes from a page the node served itself
+fn same_origin(origin: &HeaderValue, head: &RequestHead) -> bool {
+    let host = head.headers().get(header::HOST).and_then(|host| host.to_str().ok());
+    host.is_some_and(|host| origin.to_str().is_ok_and(|origin| origin == format!("http://{}", host)))
+}
+
+// Other sites may list models and run inference through the node; everything else,
+// conversations and usage included, is left to the node's own pages
+fn cross_origin_allowed(origin: &HeaderValue, head: &RequestHead) -> bool {
+    let path = head.uri.path();
+    matches!(path, "/api/models" | "/api/chat" | "/api/chat/stream" | "/api/embed")
+        || path.starts_with("/v1/")
+        || same_origin(origin, head)
+}
+
+#[get("/app/")]
+async fn get_index() -> impl Responder {
+    send_file_or_default("index.html".to_string())
+}
+
+#[get("/app/{path:.*}")]
+async fn get_root_files(path: actix_web::web::Path<String>) -> impl Responder {
+    let path = path.into_inner();
+    send_file_or_default(path)
+}
+
+#[get("/peers")]
+async fn get_peers(node: web::Data<Node>) -> Result<HttpResponse, actix_web::Error> {
+    let peer_conversations = node.conversations.get_peer_conversations().await;
+    Ok(HttpResponse::Ok().json(peer_conversations))
+}
+
+#[get("/connections")]
+async fn get_connections(node: web::Data<Node>) -> Result<HttpResponse, actix_web::Error> {
+    let mut statuses = node.reconnect.get_statuses().await;
+    for status in statuses.iter_mut().filter(|status| status.state == tcp::ReconnectState::Connected) {
+        let host = status.address.rsplit_once(':').map_or(status.address.as_str(), |(host, _)| host);
+        status.rtt_ms = node.peers.rtt(host).await.map(|rtt| rtt.as_secs_f64() * 1000.0);
+    }
+    Ok(HttpResponse::Ok().json(statuses))
+}
+
+// Prometheus scrape endpoint
+#[get("/metrics")]
+async fn get_metrics(node: web::Data<Node>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(node.metrics.render(&node).await)
+}
+
+#[get("/config")]
+async fn get_config(node: web::Data<Node>, _: Operator) -> Result<HttpResponse, actix_web::Error> {
+    Ok(HttpResponse::Ok().json(&node.config))
+}
+
+#[get("/access")]
+async fn get_access_requests(node: web::Data<Node>, _: Operator) -> Result<HttpResponse, actix_web::Error> {
+    Ok(HttpResponse::Ok().json(node.access_queue.list().await))
+}
+
+#[post("/access/{node_id}/{verdict}")]
+async fn resolve_access(node: web::Data<Node>, path: web::Path<(String, String)>, _: Operator) -> Result<HttpResponse, actix_web::Error> {
+    let (node_id, verdict) = path.into_inner();
+    let approve = match verdict.as_str() {
+        "approve" => true,
+        "deny" => false,
+        _ => return Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "Unknown access action" }))),
+    };
+    match tcp::resolve_access_request(&node, &node_id, approve).await {
+        Ok(request) => Ok(HttpResponse::Ok().json(request)),
+        Err(e) => Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": e }))),
+    }
+}
+
+// How much each peer has asked of our LLM, against its limits
+#[get("/limits")]
+async fn get_limits(node: web::Data<Node>) -> HttpResponse {
+    let mut usage: Vec<_> = node.quotas.usage().into_iter().collect();
+    usage.sort_by(|a, b| a.0.cmp(&b.0));
+    let peers: Vec<serde_json::Value> = usage.into_iter().map(|(ip, usage)| serde_json::json!({
+        "limits": node.config.limits.for_peer(&ip),
+        "ip": ip,
+        "requests_last_minute": usage.recent_requests.len(),
+        "in_flight": usage.in_flight,
+        "tokens_today": usage.tokens_today,
+    })).collect();
+    HttpResponse::Ok().json(peers)
+}
+
+#[get("/health")]
+async fn get_health(node: web::Data<Node>) -> Result<HttpResponse, actix_web::Error> {
+    let tasks = node.supervisor.statuses().await;
+    let status = if node.shutdown.is_cancelled() {
+        "stopping"
+    } else if tasks.iter().all(|task| task.state == TaskState::Running) {
+        "ok"
+    } else {
+        "degraded"
+    };
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": status,
+        "node_id": node.node_id,
+        "tasks": tasks,
+    })))
+}
+
+// Binds the HTTP server; the node decides when to stop it, so signals are left to it
+pub fn start(node: Arc<Node>) -> std::io::Result<Server> {
+    let bind = (node.config.http.bind.clone(), node.config.http.port);
+    let shutdown_timeout = node.config.shutdown.timeout_secs;
+    let data = web::Data::from(node);
+
+    // Start HTTP server without console output
+    let server = HttpServer::new(move || {
+        App::new()
+        .app_data(data.clone())
+        .wrap(
+            Cors::default()
+                .allowed_origin_fn(cross_origin_allowed)
+                .allow_any_method()
+                .allow_any_header()
+                .expose_headers(["content-type", "content-length"])
+                .max_age(3600)
+        )
+            .service(web::scope("/api")
+                .app_data(web::JsonConfig::default().limit(JSON_LIMIT))
+                .service(llm::chat)
+                .service(llm::chat_stream)
+                .service(llm::list_models)
+                .service(llm::embed::embed)
+                .service(llm::active::list_requests)
+                .service(llm::active::cancel_request)
+                .service(llm::cache::cache_stats)
+                .service(llm::cache::clear_cache)
+                .service(files::upload_documents)
+                .service(files::list_documents)
+                .service(files::delete_document)
+                .service(files::list_shared_files)
+                .service(files::offer_files)
+                .service(files::shared_file_content)
+                .service(files::fetch_shared_file)
+                .service(files::delete_shared_file)
+                .service(get_access_requests)
+                .service(resolve_access)
+                .service(get_connections)
+                .service(get_config)
+                .service(get_health)
+                .service(get_limits)
+                .service(mesh::list_nodes)
+                .service(mesh::get_topology)
+                .service(mesh::get_status)
+                .service(ledger::usage_report)
+                .service(ledger::recent_entries)
+                .service(events::stream_events))
+            // OpenAI-compatible API
+            .service(web::scope("/v1")
+                .app_data(web::JsonConfig::default().limit(JSON_LIMIT))
+                .service(llm::openai::chat_completions)
+                .service(llm::openai::list_models)
+                .service(llm::openai::embeddings))
+            .service(get_peers)
+            .service(get_metrics)
+            .service(get_index)
+            .service(get_root_files)
+    })
+    .bind(bind)?
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout)
+    .run();
+    Ok(server)
+}
@@ -0,0 +1,227 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use chrono::{DateTime, Utc};
+use tracing::{error, info, warn};
+
+const INITIAL_RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum TaskState {
+    Running,
+    Restarting,
+    Stopped,
+    // Failed more times in a row than the node allows
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskStatus {
+    pub name: String,
+    pub state: TaskState,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    pub running_since: Option<DateTime<Utc>>,
+}
+
+type Statuses = Arc<Mutex<BTreeMap<&'static str, TaskStatus>>>;
+
+// Doubles per consecutive failure up to the maximum
+fn restart_delay(failures: u32) -> Duration {
+    let exponent = failures.saturating_sub(1).min(16);
+    INITIAL_RESTART_DELAY
+        .saturating_mul(1 << exponent)
+        .min(MAX_RESTART_DELAY)
+}
+
+async fn set_status(statuses: &Statuses, name: &'static str, update: impl FnOnce(&mut TaskStatus)) {
+    let mut statuses = statuses.lock().await;
+    if let Some(status) = statuses.get_mut(name) {
+        update(status);
+    }
+}
+
+// Runs the node's long-lived background tasks. A task that returns an error or
+// panics is started again after a backoff, until it has failed `max_restarts` times
+// in a row; a task that returns Ok is done. All of them are stopped when the
+// shutdown token is cancelled.
+pub struct Supervisor {
+    shutdown: CancellationToken,
+    max_restarts: u32,
+    statuses: Statuses,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Supervisor {
+    // A `max_restarts` of 0 restarts failed tasks for as long as the node runs
+    pub fn new(shutdown: CancellationToken, max_restarts: u32) -> Self {
+        Supervisor {
+            shutdown,
+            max_restarts,
+            statuses: Arc::new(Mutex::new(BTreeMap::new())),
+            handles: Mutex::new(Vec::new()),
+        }
+    }
+
+    // `task` is called again for every restart
+    pub async fn spawn<F, Fut>(&self, name: &'static str, task: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = std::io::Result<()>> + Send + 'static,
+    {
+        self.statuses.lock().await.insert(name, TaskStatus {
+            name: name.to_string(),
+            state: TaskState::Running,
+            restarts: 0,
+            last_error: None,
+            running_since: None,
+        });
+
+        let handle = tokio::spawn(supervise(name, task, self.max_restarts, self.statuses.clone(), self.shutdown.clone()));
+        self.handles.lock().await.push(handle);
+    }
+
+    pub async fn statuses(&self) -> Vec<TaskStatus> {
+        self.statuses.lock().await.values().cloned().collect()
+    }
+
+    // Waits for every supervised task to stop; only returns once shutdown has begun
+    pub async fn join(&self) {
+        let handles: Vec<JoinHandle<()>> = self.handles.lock().await.drain(..).collect();
+        for handle in handles {
+            let _ = handle.await;
+        }
+    }
+}
+
+async fn supervise<F, Fut>(name: &'static str, task: F, max_restarts: u32, statuses: Statuses, shutdown: CancellationToken)
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = std::io::Result<()>> + Send + 'static,
+{
+    let mut failures = 0;
+    loop {
+        set_status(&statuses, name, |status| {
+            status.state = TaskState::Running;
+            status.running_since = Some(Utc::now());
+        }).await;
+
+        let started = Instant::now();
+        // Spawned separately so a panic is reported here instead of killing the loop
+        let mut attempt = tokio::spawn(task());
+        let outcome = tokio::select! {
+            _ = shutdown.cancelled() => {
+                attempt.abort();
+                break;
+            }
+            outcome = &mut attempt => outcome,
+        };
+
+        let error = match outcome {
+            Ok(Ok(())) => {
+                info!(task = name, "Task finished");
+                break;
+            }
+            Ok(Err(e)) => e.to_string(),
+            Err(e) => e.to_string(),
+        };
+
+        // A task that ran for a good while before failing starts over from the shortest delay
+        if started.elapsed() >= MAX_RESTART_DELAY {
+            failures = 0;
+        }
+        failures += 1;
+        if max_restarts > 0 && failures > max_restarts {
+            error!(task = name, "Task failed: {} (giving up after {} restarts in a row)", error, max_restarts);
+            set_status(&statuses, name, |status| {
+                status.state = TaskState::Failed;
+                status.last_error = Some(error);
+                status.running_since = None;
+            }).await;
+            return;
+        }
+        let delay = restart_delay(failures);
+        warn!(task = name, "Task failed: {} (restarting in {}s)", error, delay.as_secs());
+
+        set_status(&statuses, name, |status| {
+            status.state = TaskState::Restarting;
+            status.restarts += 1;
+            status.last_error = Some(error);
+            status.running_since = None;
+        }).await;
+
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = tokio::time::sleep(delay) => {}
+        }
+    }
+
+    set_status(&statuses, name, |status| {
+        status.state = TaskState::Stopped;
+        status.running_since = None;
+    }).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    async fn only_task(supervisor: &Supervisor) -> TaskStatus {
+        supervisor.statuses().await.remove(0)
+    }
+
+    #[tokio::test]
+    async fn a_crashed_task_is_restarted() {
+        let supervisor = Supervisor::new(CancellationToken::new(), 0);
+        let runs = Arc::new(AtomicU32::new(0));
+        let counted = runs.clone();
+        supervisor.spawn("flaky", move || {
+            let run = counted.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if run == 0 {
+                    panic!("first run crashes");
+                }
+                std::future::pending::<std::io::Result<()>>().await
+            }
+        }).await;
+
+        tokio::time::sleep(INITIAL_RESTART_DELAY + Duration::from_millis(500)).await;
+        let status = only_task(&supervisor).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(status.state, TaskState::Running);
+        assert_eq!(status.restarts, 1);
+        assert!(status.last_error.is_some_and(|error| error.contains("panicked")));
+        assert!(status.running_since.is_some());
+
+        supervisor.shutdown.cancel();
+        supervisor.join().await;
+        assert_eq!(only_task(&supervisor).await.state, TaskState::Stopped);
+    }
+
+    #[tokio::test]
+    async fn restarts_stop_after_the_limit() {
+        let supervisor = Supervisor::new(CancellationToken::new(), 2);
+        let runs = Arc::new(AtomicU32::new(0));
+        let counted = runs.clone();
+        supervisor.spawn("broken", move || {
+            counted.fetch_add(1, Ordering::SeqCst);
+            async { Err(std::io::Error::other("port in use")) }
+        }).await;
+
+        // Returns by itself, without a shutdown, once the task is given up on
+        tokio::time::timeout(Duration::from_secs(10), supervisor.join()).await.expect("the task was restarted past the limit");
+        let status = only_task(&supervisor).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        assert_eq!(status.state, TaskState::Failed);
+        assert_eq!(status.restarts, 2);
+        assert_eq!(status.last_error.as_deref(), Some("port in use"));
+        assert!(status.running_since.is_none());
+    }
+}
@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessRequest {
+    pub node_id: String,
+    pub ip: String,
+    pub peer_name: String,
+    pub reason: String,
+    pub requested_at: DateTime<Utc>,
+}
+
+// LLM access requests waiting for an operator when auto-approval is turned off,
+// keyed by the requesting node's ID. A repeated request replaces the earlier one.
+pub struct AccessQueue {
+    pending: Mutex<HashMap<String, AccessRequest>>,
+}
+
+impl AccessQueue {
+    pub fn new() -> Self {
+        AccessQueue {
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn add(&self, request: AccessRequest) {
+        let mut pending = self.pending.lock().await;
+        pending.insert(request.node_id.clone(), request);
+    }
+
+    pub async fn take(&self, node_id: &str) -> Option<AccessRequest> {
+        let mut pending = self.pending.lock().await;
+        pending.remove(node_id)
+    }
+
+    pub async fn list(&self) -> Vec<AccessRequest> {
+        let pending = self.pending.lock().await;
+        let mut requests: Vec<AccessRequest> = pending.values().cloned().collect();
+        requests.sort_by_key(|request| request.requested_at);
+        requests
+    }
+}
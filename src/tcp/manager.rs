@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde::Serialize;
+use tokio::sync::{mpsc, Mutex, Notify};
+use tracing::info;
+use super::message::Message;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+struct SessionEntry {
+    id: u64,
+    direction: Direction,
+    addr: SocketAddr,
+    peer_address: String,
+    close: Arc<Notify>,
+    outbound: mpsc::Sender<Message>,
+}
+
+// A live session as seen from outside the manager
+pub struct SessionInfo {
+    pub node_id: String,
+    pub direction: Direction,
+    pub addr: SocketAddr,
+    pub peer_address: String,
+}
+
+// Returned to a session that won admission; used to learn when it has been superseded
+pub struct SessionHandle {
+    pub node_id: String,
+    id: u64,
+    close: Arc<Notify>,
+}
+
+impl SessionHandle {
+    // Resolves once the manager decides another connection to the same peer should win
+    pub async fn superseded(&self) {
+        self.close.notified().await;
+    }
+}
+
+// Keeps exactly one live session per remote node, whichever side dialed it.
+//
+// When both nodes dial each other at the same time, each ends up with an inbound
+// and an outbound connection to the same peer. Both sides apply the same rule to
+// pick a survivor: keep the connection that was initiated by the node with the
+// lower ID. Since the rule only depends on the two IDs, both ends agree on which
+// TCP connection to drop without any extra negotiation.
+pub struct ConnectionManager {
+    local_node_id: String,
+    sessions: Mutex<HashMap<String, SessionEntry>>,
+    next_id: AtomicU64,
+}
+
+impl ConnectionManager {
+    pub fn new(local_node_id: String) -> Self {
+        ConnectionManager {
+            local_node_id,
+            sessions: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    // Node ID of whoever opened a connection in the given direction
+    fn initiator<'a>(&'a self, peer_node_id: &'a str, direction: Direction) -> &'a str {
+        match direction {
+            Direction::Outbound => &self.local_node_id,
+            Direction::Inbound => peer_node_id,
+        }
+    }
+
+    fn is_preferred(&self, peer_node_id: &str, direction: Direction) -> bool {
+        let lower = std::cmp::min(self.local_node_id.as_str(), peer_node_id);
+        self.initiator(peer_node_id, direction) == lower
+    }
+
+    // Called once the handshake has told us who is on the other end. Returns None if
+    // an existing session to the same node should be kept instead of this one.
+    pub async fn register(&self, peer_node_id: &str, direction: Direction, addr: SocketAddr, peer_address: &str, outbound: mpsc::Sender<Message>) -> Option<SessionHandle> {
+        let mut sessions = self.sessions.lock().await;
+
+        if let Some(existing) = sessions.get(peer_node_id) {
+            let keep_existing = existing.direction != direction
+                && self.is_preferred(peer_node_id, existing.direction);
+            if keep_existing {
+                info!(
+                    "Dropping duplicate {:?} connection to {} ({}), keeping {:?} connection via {}",
+                    direction, peer_node_id, addr, existing.direction, existing.addr
+                );
+                return None;
+            }
+
+            // Either the new connection is the preferred one, or both were opened the same
+            // way and the older one is most likely a dead socket that has not noticed yet
+            info!(
+                "Replacing {:?} connection to {} ({}) with {:?} connection via {}",
+                existing.direction, peer_node_id, existing.addr, direction, addr
+            );
+            existing.close.notify_one();
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let close = Arc::new(Notify::new());
+        sessions.insert(peer_node_id.to_string(), SessionEntry {
+            id,
+            direction,
+            addr,
+            peer_address: peer_address.to_string(),
+            close: close.clone(),
+            outbound,
+        });
+
+        Some(SessionHandle {
+            node_id: peer_node_id.to_string(),
+            id,
+            close,
+        })
+    }
+
+    // Only removes the entry if it still belongs to this session, so a superseded
+    // session shutting down does not evict the connection that replaced it. True when
+    // no session to the node is left.
+    pub async fn unregister(&self, handle: &SessionHandle) -> bool {
+        let mut sessions = self.sessions.lock().await;
+        match sessions.get(&handle.node_id) {
+            Some(entry) if entry.id == handle.id => {
+                sessions.remove(&handle.node_id);
+                true
+            }
+            Some(_) => false,
+            None => true,
+        }
+    }
+
+    // Queues a message on the live session to the given node, wherever it came from
+    pub async fn send_to(&self, peer_node_id: &str, message: Message) -> std::io::Result<()> {
+        let outbound = {
+            let sessions = self.sessions.lock().await;
+            sessions.get(peer_node_id).map(|entry| entry.outbound.clone())
+        };
+        let outbound = outbound.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotConnected, format!("No session with node {}", peer_node_id))
+        })?;
+        outbound.send(message).await.map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe, format!("Session with node {} has closed", peer_node_id))
+        })
+    }
+
+    // The queue of the live session to the given node, for callers that send repeatedly
+    pub async fn outbound(&self, peer_node_id: &str) -> Option<mpsc::Sender<Message>> {
+        self.sessions.lock().await.get(peer_node_id).map(|entry| entry.outbound.clone())
+    }
+
+    pub async fn node_ids(&self) -> Vec<String> {
+        self.sessions.lock().await.keys().cloned().collect()
+    }
+
+    pub async fn sessions(&self) -> Vec<SessionInfo> {
+        let sessions = self.sessions.lock().await;
+        sessions.iter().map(|(node_id, entry)| SessionInfo {
+            node_id: node_id.clone(),
+            direction: entry.direction,
+            addr: entry.addr,
+            peer_address: entry.peer_address.clone(),
+        }).collect()
+    }
+
+    // Messages waiting in every session's outbound queue
+    pub async fn queued_messages(&self) -> usize {
+        let sessions = self.sessions.lock().await;
+        sessions.values().map(|entry| entry.outbound.max_capacity() - entry.outbound.capacity()).sum()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.sessions.lock().await.is_empty()
+    }
+
+    // `peer_address` is the peer's listening address ("ip:port"), not the socket's remote end
+    pub async fn is_connected_to(&self, peer_address: &str) -> bool {
+        let sessions = self.sessions.lock().await;
+        sessions.values().any(|entry| entry.peer_address == peer_address)
+    }
+}
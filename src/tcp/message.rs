@@ -0,0 +1,398 @@
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::time::Duration;
+use tracing::debug;
+use crate::config::BackendKind;
+use crate::conversation::Conversation;
+use crate::files::FileManifest;
+use crate::llm::{InferenceReply, InferenceRequest};
+use crate::mesh::NodeAnnouncement;
+
+const CHUNK_SIZE: usize = 8192;
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024 * 50; // 50MB limit
+
+// Every frame on the wire is a 5-byte marker, a little-endian u64 payload length and the payload
+#[derive(Debug)]
+pub enum Message {
+    Hello {
+        node_id: String,
+        listen_port: u16,
+    },
+    ConversationFile {
+        name: String,
+        content: String,
+    },
+    SyncRequest,
+    SyncResponse(Vec<Conversation>),
+    LLMCapability {
+        has_llm: bool,
+    },
+    LLMAccessRequest {
+        peer_name: String,
+        reason: String,
+    },
+    LLMAccessResponse {
+        granted: bool,
+        message: String,
+        llm_host: Option<String>,
+        llm_port: Option<i32>,
+        // None when the peer runs a kind of server we do not know
+        llm_backend: Option<BackendKind>,
+    },
+    // Timestamps are milliseconds since the Unix epoch
+    Ping {
+        sent_at: i64,
+    },
+    Pong {
+        sent_at: i64,
+        received_at: i64,
+    },
+    // Last message before a node closes the session on purpose
+    Goodbye {
+        reason: String,
+    },
+    // Files the sender shares, sent on connect and whenever it adds some
+    FileOffer {
+        files: Vec<FileManifest>,
+    },
+    // The sender no longer shares this file
+    FileWithdraw {
+        hash: String,
+    },
+    ChunkRequest {
+        hash: String,
+        index: u64,
+    },
+    ChunkData {
+        hash: String,
+        index: u64,
+        data: Vec<u8>,
+    },
+    ChunkUnavailable {
+        hash: String,
+        index: u64,
+        reason: String,
+    },
+    // The sender's status and sessions, sent on connect and at every share interval
+    NodeStatus(NodeAnnouncement),
+    // An LLM request for the receiver's server, and its answers
+    InferenceRequest(InferenceRequest),
+    InferenceReply(InferenceReply),
+    // The sender no longer wants the answer to its request `id`
+    InferenceCancel {
+        id: u64,
+    },
+}
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Splits "hash|index|" off the front of a chunk payload
+fn chunk_header(data: &[u8]) -> Option<(String, u64, &[u8])> {
+    let mut parts = data.splitn(3, |&byte| byte == b'|');
+    let hash = std::str::from_utf8(parts.next()?).ok()?;
+    let index = std::str::from_utf8(parts.next()?).ok()?.parse().ok()?;
+    Some((hash.to_string(), index, parts.next()?))
+}
+
+impl Message {
+    // Label for metrics and logs
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::Hello { .. } => "hello",
+            Message::ConversationFile { .. } => "conversation_file",
+            Message::SyncRequest => "sync_request",
+            Message::SyncResponse(_) => "sync_response",
+            Message::LLMCapability { .. } => "llm_capability",
+            Message::LLMAccessRequest { .. } => "llm_access_request",
+            Message::LLMAccessResponse { .. } => "llm_access_response",
+            Message::Ping { .. } => "ping",
+            Message::Pong { .. } => "pong",
+            Message::Goodbye { .. } => "goodbye",
+            Message::FileOffer { .. } => "file_offer",
+            Message::FileWithdraw { .. } => "file_withdraw",
+            Message::ChunkRequest { .. } => "chunk_request",
+            Message::ChunkData { .. } => "chunk_data",
+            Message::ChunkUnavailable { .. } => "chunk_unavailable",
+            Message::NodeStatus(_) => "node_status",
+            Message::InferenceRequest(_) => "inference_request",
+            Message::InferenceReply(_) => "inference_reply",
+            Message::InferenceCancel { .. } => "inference_cancel",
+        }
+    }
+
+    fn encode(&self) -> std::io::Result<(&'static [u8; 5], Vec<u8>)> {
+        let frame = match self {
+            Message::Hello { node_id, listen_port } => {
+                (b"HELO:", format!("{}|{}", node_id, listen_port).into_bytes())
+            }
+            Message::ConversationFile { name, content } => {
+                (b"FILE:", format!("{}|{}", name, content).into_bytes())
+            }
+            Message::SyncRequest => (b"SYNC:", Vec::new()),
+            Message::SyncResponse(conversations) => (b"RESP:", serde_json::to_vec(conversations)?),
+            Message::LLMCapability { has_llm } => (b"LLMC:", has_llm.to_string().into_bytes()),
+            Message::LLMAccessRequest { peer_name, reason } => {
+                (b"LREQ:", format!("{}|{}", peer_name, reason).into_bytes())
+            }
+            Message::LLMAccessResponse { granted, message, llm_host, llm_port, llm_backend } => {
+                let host_str = llm_host.as_deref().unwrap_or("");
+                let port_str = llm_port.map(|p| p.to_string()).unwrap_or_default();
+                let backend_str = llm_backend.map(|b| b.as_str()).unwrap_or("");
+                (b"LRES:", format!("{}|{}|{}|{}|{}", granted, message, host_str, port_str, backend_str).into_bytes())
+            }
+            Message::Ping { sent_at } => (b"PING:", sent_at.to_string().into_bytes()),
+            Message::Pong { sent_at, received_at } => {
+                (b"PONG:", format!("{}|{}", sent_at, received_at).into_bytes())
+            }
+            Message::Goodbye { reason } => (b"GBYE:", reason.clone().into_bytes()),
+            Message::FileOffer { files } => (b"OFFR:", serde_json::to_vec(files)?),
+            Message::FileWithdraw { hash } => (b"WDRW:", hash.clone().into_bytes()),
+            Message::ChunkRequest { hash, index } => (b"CREQ:", format!("{}|{}", hash, index).into_bytes()),
+            // The chunk follows the header as raw bytes
+            Message::ChunkData { hash, index, data } => {
+                let mut payload = format!("{}|{}|", hash, index).into_bytes();
+                payload.extend_from_slice(data);
+                (b"CDAT:", payload)
+            }
+            Message::ChunkUnavailable { hash, index, reason } => {
+                (b"CNAK:", format!("{}|{}|{}", hash, index, reason).into_bytes())
+            }
+            Message::NodeStatus(announcement) => (b"NODE:", serde_json::to_vec(announcement)?),
+            Message::InferenceRequest(request) => (b"INFQ:", serde_json::to_vec(request)?),
+            Message::InferenceReply(reply) => (b"INFR:", serde_json::to_vec(reply)?),
+            Message::InferenceCancel { id } => (b"INFC:", id.to_string().into_bytes()),
+        };
+        Ok(frame)
+    }
+
+    fn decode(marker: &[u8; 5], data: &[u8]) -> std::io::Result<Message> {
+        match marker {
+            b"HELO:" => {
+                let content = String::from_utf8_lossy(data);
+                match content.split_once('|') {
+                    Some((node_id, listen_port)) => match listen_port.parse::<u16>() {
+                        Ok(listen_port) => Ok(Message::Hello {
+                            node_id: node_id.trim().to_string(),
+                            listen_port,
+                        }),
+                        Err(_) => Err(invalid_data("Invalid hello port")),
+                    },
+                    None => Err(invalid_data("Invalid hello format")),
+                }
+            }
+            b"FILE:" => {
+                let content = String::from_utf8_lossy(data);
+                if let Some((name, content)) = content.split_once('|') {
+                    debug!(name, bytes = content.len(), "Received file");
+                    Ok(Message::ConversationFile {
+                        name: name.to_string(),
+                        content: content.to_string(),
+                    })
+                } else {
+                    Err(invalid_data("Invalid file format"))
+                }
+            }
+            b"SYNC:" => Ok(Message::SyncRequest),
+            b"RESP:" => {
+                let conversations = serde_json::from_slice(data)?;
+                Ok(Message::SyncResponse(conversations))
+            }
+            b"LLMC:" => {
+                let has_llm = String::from_utf8_lossy(data).parse::<bool>().unwrap_or(false);
+                Ok(Message::LLMCapability { has_llm })
+            }
+            b"LREQ:" => {
+                let content = String::from_utf8_lossy(data);
+                if let Some((peer_name, reason)) = content.split_once('|') {
+                    Ok(Message::LLMAccessRequest {
+                        peer_name: peer_name.to_string(),
+                        reason: reason.to_string(),
+                    })
+                } else {
+                    Err(invalid_data("Invalid LLM request format"))
+                }
+            }
+            b"LRES:" => {
+                let content = String::from_utf8_lossy(data);
+                let parts: Vec<&str> = content.split('|').collect();
+                // Peers from before backends were advertised send four fields and run Ollama
+                if parts.len() == 4 || parts.len() == 5 {
+                    let granted = parts[0].parse().unwrap_or(false);
+                    let message = parts[1].to_string();
+                    let llm_host = if !parts[2].is_empty() { Some(parts[2].to_string()) } else { None };
+                    let llm_port = if !parts[3].is_empty() { parts[3].parse().ok() } else { None };
+                    let llm_backend = match parts.get(4) {
+                        Some(backend) => BackendKind::parse(backend),
+                        None => Some(BackendKind::Ollama),
+                    };
+                    Ok(Message::LLMAccessResponse {
+                        granted,
+                        message,
+                        llm_host,
+                        llm_port,
+                        llm_backend,
+                    })
+                } else {
+                    Err(invalid_data("Invalid LLM response format"))
+                }
+            }
+            b"PING:" => {
+                match String::from_utf8_lossy(data).parse::<i64>() {
+                    Ok(sent_at) => Ok(Message::Ping { sent_at }),
+                    Err(_) => Err(invalid_data("Invalid ping format")),
+                }
+            }
+            b"PONG:" => {
+                let content = String::from_utf8_lossy(data);
+                let parsed = content.split_once('|').and_then(|(sent_at, received_at)| {
+                    Some((sent_at.parse::<i64>().ok()?, received_at.parse::<i64>().ok()?))
+                });
+                match parsed {
+                    Some((sent_at, received_at)) => Ok(Message::Pong { sent_at, received_at }),
+                    None => Err(invalid_data("Invalid pong format")),
+                }
+            }
+            b"GBYE:" => Ok(Message::Goodbye {
+                reason: String::from_utf8_lossy(data).to_string(),
+            }),
+            b"OFFR:" => Ok(Message::FileOffer {
+                files: serde_json::from_slice(data)?,
+            }),
+            b"WDRW:" => Ok(Message::FileWithdraw {
+                hash: String::from_utf8_lossy(data).to_string(),
+            }),
+            b"CREQ:" => {
+                let content = String::from_utf8_lossy(data);
+                match content.split_once('|').and_then(|(hash, index)| Some((hash, index.parse::<u64>().ok()?))) {
+                    Some((hash, index)) => Ok(Message::ChunkRequest { hash: hash.to_string(), index }),
+                    None => Err(invalid_data("Invalid chunk request format")),
+                }
+            }
+            b"CDAT:" => {
+                let (hash, index, rest) = chunk_header(data).ok_or_else(|| invalid_data("Invalid chunk format"))?;
+                Ok(Message::ChunkData { hash, index, data: rest.to_vec() })
+            }
+            b"CNAK:" => {
+                let (hash, index, rest) = chunk_header(data).ok_or_else(|| invalid_data("Invalid chunk refusal format"))?;
+                Ok(Message::ChunkUnavailable {
+                    hash,
+                    index,
+                    reason: String::from_utf8_lossy(rest).to_string(),
+                })
+            }
+            b"NODE:" => Ok(Message::NodeStatus(serde_json::from_slice(data)?)),
+            b"INFQ:" => Ok(Message::InferenceRequest(serde_json::from_slice(data)?)),
+            b"INFR:" => Ok(Message::InferenceReply(serde_json::from_slice(data)?)),
+            b"INFC:" => match String::from_utf8_lossy(data).parse::<u64>() {
+                Ok(id) => Ok(Message::InferenceCancel { id }),
+                Err(_) => Err(invalid_data("Invalid inference cancel format")),
+            },
+            _ => Err(invalid_data("Unknown message type")),
+        }
+    }
+
+    // Returns the size of the frame on the wire
+    pub async fn send<W: AsyncWrite + Unpin>(&self, stream: &mut W) -> std::io::Result<usize> {
+        let (marker, data) = self.encode()?;
+
+        stream.write_all(marker).await?;
+        let len = data.len() as u64;
+        stream.write_all(&len.to_le_bytes()).await?;
+
+        // Send data in chunks
+        for chunk in data.chunks(CHUNK_SIZE) {
+            match tokio::time::timeout(Duration::from_secs(30), stream.write_all(chunk)).await {
+                Ok(Ok(_)) => (),
+                Ok(Err(e)) => {
+                    debug!("Error sending chunk: {}", e);
+                    return Err(e);
+                },
+                Err(_) => {
+                    let err = std::io::Error::new(std::io::ErrorKind::TimedOut, "Timeout sending chunk");
+                    debug!("{}", err);
+                    return Err(err);
+                }
+            }
+        }
+
+        stream.flush().await?;
+        Ok(marker.len() + std::mem::size_of::<u64>() + data.len())
+    }
+
+    // The next message and the size of its frame on the wire
+    pub async fn receive<R: AsyncRead + Unpin>(stream: &mut R) -> std::io::Result<Option<(Message, usize)>> {
+        let mut marker = [0u8; 5];
+
+        // Read marker with longer timeout and better error handling
+        match tokio::time::timeout(Duration::from_secs(15), stream.read_exact(&mut marker)).await {
+            Ok(Ok(_)) => (),
+            Ok(Err(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                debug!("Connection closed gracefully");
+                return Ok(None);
+            },
+            Ok(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionReset => {
+                debug!("Connection reset by peer");
+                return Ok(None);
+            },
+            Ok(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionAborted => {
+                debug!("Connection aborted");
+                return Ok(None);
+            },
+            Ok(Err(e)) => {
+                debug!("Error reading marker: {} - treating as connection close", e);
+                return Ok(None);
+            },
+            Err(_) => {
+                debug!("Timeout reading marker - connection may be slow");
+                return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Timeout reading marker"));
+            },
+        }
+
+        // Read length with longer timeout
+        let mut len_bytes = [0u8; 8];
+        match tokio::time::timeout(Duration::from_secs(10), stream.read_exact(&mut len_bytes)).await {
+            Ok(Ok(_)) => (),
+            Ok(Err(e)) => {
+                debug!("Failed to read message length: {}", e);
+                return Err(e);
+            }
+            Err(_) => {
+                debug!("Timeout reading length - network may be slow");
+                return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Timeout reading length"));
+            },
+        }
+
+        let len = u64::from_le_bytes(len_bytes) as usize;
+        if len > MAX_MESSAGE_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Message too large: {} bytes", len)
+            ));
+        }
+
+        // Read data in chunks with timeout
+        let mut data = Vec::with_capacity(len);
+        let mut remaining = len;
+
+        while remaining > 0 {
+            let chunk_size = remaining.min(CHUNK_SIZE);
+            let mut chunk = vec![0u8; chunk_size];
+
+            match tokio::time::timeout(Duration::from_secs(30), stream.read_exact(&mut chunk)).await {
+                Ok(Ok(_)) => {
+                    data.extend_from_slice(&chunk);
+                    remaining -= chunk_size;
+                }
+                Ok(Err(e)) => {
+                    debug!("Failed to read chunk: {}", e);
+                    return Err(e);
+                }
+                Err(_) => return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Timeout reading chunk")),
+            }
+        }
+
+        let message = Self::decode(&marker, &data)?;
+        Ok(Some((message, marker.len() + len_bytes.len() + len)))
+    }
+}
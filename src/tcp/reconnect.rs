@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use tracing::info;
+
+const INITIAL_RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_INTERVAL: Duration = Duration::from_secs(60);
+// Failed attempts in a row after which a peer that is not a seed is forgotten, about
+// half an hour at the maximum interval. Discovery adds it again if it comes back.
+const MAX_ATTEMPTS: u32 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReconnectState {
+    Connecting,
+    Connected,
+    Backoff,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerConnectionStatus {
+    pub address: String,
+    pub state: ReconnectState,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub last_connected: Option<DateTime<Utc>>,
+    pub next_retry_at: Option<DateTime<Utc>>,
+    // Smoothed heartbeat round-trip time while connected; filled in by the HTTP API
+    #[serde(default)]
+    pub rtt_ms: Option<f64>,
+}
+
+struct PeerEntry {
+    status: PeerConnectionStatus,
+    next_retry: Option<Instant>,
+    // Configured seeds are retried for as long as the node runs
+    seed: bool,
+}
+
+// Delay before the given retry: doubles per failed attempt up to the maximum, then
+// keeps a random half of it so peers that dropped together do not redial in lockstep
+fn backoff_delay(attempts: u32) -> Duration {
+    let exponent = attempts.saturating_sub(1).min(16);
+    let capped = INITIAL_RECONNECT_INTERVAL
+        .saturating_mul(1 << exponent)
+        .min(MAX_RECONNECT_INTERVAL);
+    let half = capped / 2;
+    half + half.mul_f64(rand::thread_rng().gen::<f64>())
+}
+
+// Tracks every peer we know about, by listening address, and decides when to dial it again.
+//
+// Peers enter the schedule as configured seeds, when UDP discovery reports them or when
+// they connect to us. A lost session is retried with exponential backoff instead of
+// waiting for the peer's next discovery beacon, until the peer has failed MAX_ATTEMPTS
+// times in a row; only seeds are kept past that.
+pub struct ReconnectScheduler {
+    peers: Mutex<HashMap<String, PeerEntry>>,
+}
+
+impl ReconnectScheduler {
+    pub fn new() -> Self {
+        ReconnectScheduler {
+            peers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Newly discovered peers are due immediately; known peers keep their schedule
+    pub async fn add_peer(&self, address: &str) {
+        let mut peers = self.peers.lock().await;
+        peers.entry(address.to_string()).or_insert_with(|| PeerEntry {
+            status: PeerConnectionStatus {
+                address: address.to_string(),
+                state: ReconnectState::Backoff,
+                attempts: 0,
+                last_error: None,
+                last_connected: None,
+                next_retry_at: Some(Utc::now()),
+                rtt_ms: None,
+            },
+            next_retry: Some(Instant::now()),
+            seed: false,
+        });
+    }
+
+    pub async fn add_seed(&self, address: &str) {
+        self.add_peer(address).await;
+        if let Some(entry) = self.peers.lock().await.get_mut(address) {
+            entry.seed = true;
+        }
+    }
+
+    // Peers whose retry time has passed; they are marked as connecting so the next
+    // poll does not hand them out again while the dial is in flight
+    pub async fn take_due_peers(&self) -> Vec<String> {
+        let mut peers = self.peers.lock().await;
+        let now = Instant::now();
+        let mut due = Vec::new();
+        for (address, entry) in peers.iter_mut() {
+            let is_due = entry.status.state == ReconnectState::Backoff
+                && entry.next_retry.is_some_and(|at| at <= now);
+            if is_due {
+                entry.status.state = ReconnectState::Connecting;
+                entry.status.next_retry_at = None;
+                entry.next_retry = None;
+                due.push(address.clone());
+            }
+        }
+        due
+    }
+
+    pub async fn mark_connected(&self, address: &str) {
+        self.add_peer(address).await;
+        let mut peers = self.peers.lock().await;
+        if let Some(entry) = peers.get_mut(address) {
+            entry.status.state = ReconnectState::Connected;
+            entry.status.attempts = 0;
+            entry.status.last_error = None;
+            entry.status.last_connected = Some(Utc::now());
+            entry.status.next_retry_at = None;
+            entry.next_retry = None;
+        }
+    }
+
+    // Schedules the next attempt, or forgets a peer that has failed too often
+    fn schedule_retry(peers: &mut HashMap<String, PeerEntry>, address: &str, error: Option<String>) {
+        let Some(entry) = peers.get_mut(address) else { return };
+        if !entry.seed && entry.status.attempts + 1 >= MAX_ATTEMPTS {
+            info!(%address, "Giving up on peer after {} failed attempts", MAX_ATTEMPTS);
+            peers.remove(address);
+            return;
+        }
+        entry.status.attempts += 1;
+        let delay = backoff_delay(entry.status.attempts);
+        entry.status.state = ReconnectState::Backoff;
+        entry.status.last_error = error;
+        entry.status.next_retry_at = chrono::Duration::from_std(delay)
+            .ok()
+            .map(|delay| Utc::now() + delay);
+        entry.next_retry = Some(Instant::now() + delay);
+    }
+
+    // A session to the peer ended and no other session replaced it
+    pub async fn mark_disconnected(&self, address: &str, error: Option<String>) {
+        let mut peers = self.peers.lock().await;
+        Self::schedule_retry(&mut peers, address, error);
+    }
+
+    // A dial finished without ever establishing a session (connect or handshake
+    // failed, or the peer kept its own connection to us instead)
+    pub async fn finish_attempt(&self, address: &str, error: Option<String>) {
+        let mut peers = self.peers.lock().await;
+        if peers.get(address).is_some_and(|entry| entry.status.state == ReconnectState::Connecting) {
+            Self::schedule_retry(&mut peers, address, error);
+        }
+    }
+
+    pub async fn get_statuses(&self) -> Vec<PeerConnectionStatus> {
+        let peers = self.peers.lock().await;
+        let mut statuses: Vec<PeerConnectionStatus> = peers.values()
+            .map(|entry| entry.status.clone())
+            .collect();
+        statuses.sort_by(|a, b| a.address.cmp(&b.address));
+        statuses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A dial to `address` that failed
+    async fn fail_once(scheduler: &ReconnectScheduler, address: &str) {
+        if let Some(entry) = scheduler.peers.lock().await.get_mut(address) {
+            entry.status.state = ReconnectState::Connecting;
+        }
+        scheduler.finish_attempt(address, Some("refused".to_string())).await;
+    }
+
+    async fn status(scheduler: &ReconnectScheduler, address: &str) -> Option<PeerConnectionStatus> {
+        scheduler.get_statuses().await.into_iter().find(|status| status.address == address)
+    }
+
+    #[test]
+    fn delays_double_up_to_the_ceiling_with_jitter() {
+        for attempts in 1..=40 {
+            let ceiling = (INITIAL_RECONNECT_INTERVAL * 2u32.pow(attempts.min(16) - 1)).min(MAX_RECONNECT_INTERVAL);
+            for _ in 0..20 {
+                let delay = backoff_delay(attempts);
+                assert!(delay >= ceiling / 2 && delay <= ceiling, "attempt {}: {:?} outside {:?}", attempts, delay, ceiling);
+            }
+        }
+        assert!(backoff_delay(u32::MAX) <= MAX_RECONNECT_INTERVAL);
+    }
+
+    #[tokio::test]
+    async fn a_connection_starts_the_backoff_over() {
+        let scheduler = ReconnectScheduler::new();
+        scheduler.add_peer("10.0.0.2:7878").await;
+        assert_eq!(scheduler.take_due_peers().await, vec!["10.0.0.2:7878".to_string()]);
+        assert!(scheduler.take_due_peers().await.is_empty());
+        for _ in 0..8 {
+            fail_once(&scheduler, "10.0.0.2:7878").await;
+        }
+        let failing = status(&scheduler, "10.0.0.2:7878").await.unwrap();
+        assert_eq!(failing.attempts, 8);
+        assert_eq!(failing.last_error.as_deref(), Some("refused"));
+        assert!(failing.next_retry_at.unwrap() - Utc::now() > chrono::Duration::seconds(29));
+
+        scheduler.mark_connected("10.0.0.2:7878").await;
+        let connected = status(&scheduler, "10.0.0.2:7878").await.unwrap();
+        assert_eq!((connected.state, connected.attempts, connected.next_retry_at), (ReconnectState::Connected, 0, None));
+
+        scheduler.mark_disconnected("10.0.0.2:7878", None).await;
+        let lost = status(&scheduler, "10.0.0.2:7878").await.unwrap();
+        assert_eq!((lost.state, lost.attempts), (ReconnectState::Backoff, 1));
+        assert!(lost.next_retry_at.unwrap() - Utc::now() <= chrono::Duration::seconds(1));
+    }
+
+    #[tokio::test]
+    async fn only_seeds_are_retried_past_the_limit() {
+        let scheduler = ReconnectScheduler::new();
+        scheduler.add_peer("10.0.0.2:7878").await;
+        scheduler.add_seed("10.0.0.3:7878").await;
+        for _ in 0..MAX_ATTEMPTS - 1 {
+            fail_once(&scheduler, "10.0.0.2:7878").await;
+            fail_once(&scheduler, "10.0.0.3:7878").await;
+        }
+        assert!(status(&scheduler, "10.0.0.2:7878").await.is_some());
+
+        fail_once(&scheduler, "10.0.0.2:7878").await;
+        fail_once(&scheduler, "10.0.0.3:7878").await;
+        assert!(status(&scheduler, "10.0.0.2:7878").await.is_none());
+        assert_eq!(status(&scheduler, "10.0.0.3:7878").await.unwrap().attempts, MAX_ATTEMPTS);
+    }
+}
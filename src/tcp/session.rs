@@ -0,0 +1,414 @@
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::Utc;
+use tracing::{debug, debug_span, error, info, warn, Instrument};
+use crate::config::BackendKind;
+use crate::conversation::{Conversation, PEER_CONVERSATION_FILE};
+use crate::events::Event;
+use crate::files;
+use crate::llm::{self, LlmEndpoint};
+use crate::logging;
+use crate::mesh;
+use crate::metrics::Metrics;
+use crate::node::Node;
+use super::access::AccessRequest;
+use super::manager::SessionHandle;
+use super::message::Message;
+use super::{access_granted, HEARTBEAT_INTERVAL};
+
+const OUTBOUND_QUEUE: usize = 64;
+const INBOUND_QUEUE: usize = 64;
+// How long a closing session waits for its Goodbye to reach the socket
+const GOODBYE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// A live, identified connection to one peer.
+//
+// The socket is split between a writer task, which drains the outbound queue onto
+// the wire, and a reader task, which decodes frames into the inbound queue. The
+// session itself is the dispatcher: every message from the peer and every timer
+// (heartbeat, conversation share) is handled in `run`, so there is exactly one
+// place that decides how to react to the peer and exactly one writer on the socket.
+pub struct PeerSession {
+    node: Arc<Node>,
+    node_id: String,
+    addr: SocketAddr,
+    // The peer's listening address ("ip:port")
+    peer_address: String,
+    ip: String,
+    has_llm: bool,
+    access_requested: bool,
+    outbound: mpsc::Sender<Message>,
+    inbound: mpsc::Receiver<std::io::Result<Message>>,
+    writer: JoinHandle<()>,
+    reader: JoinHandle<()>,
+}
+
+async fn write_loop(mut stream: OwnedWriteHalf, mut outbound: mpsc::Receiver<Message>, metrics: Arc<Metrics>) {
+    while let Some(message) = outbound.recv().await {
+        match message.send(&mut stream).await {
+            Ok(bytes) => metrics.message_sent(message.kind(), bytes),
+            Err(e) => {
+                warn!("Failed to write to peer: {}", e);
+                break;
+            }
+        }
+        // Nothing may follow a Goodbye; closing our half tells the peer we are done
+        if matches!(message, Message::Goodbye { .. }) {
+            let _ = stream.shutdown().await;
+            break;
+        }
+    }
+}
+
+async fn read_loop(mut stream: OwnedReadHalf, inbound: mpsc::Sender<std::io::Result<Message>>, metrics: Arc<Metrics>) {
+    loop {
+        match Message::receive(&mut stream).await {
+            Ok(Some((message, bytes))) => {
+                metrics.message_received(message.kind(), bytes);
+                if inbound.send(Ok(message)).await.is_err() {
+                    break;
+                }
+            }
+            Ok(None) => {
+                info!("Connection closed by peer");
+                break;
+            }
+            Err(e) => {
+                let _ = inbound.send(Err(e)).await;
+                break;
+            }
+        }
+    }
+}
+
+impl PeerSession {
+    pub fn start(node: Arc<Node>, stream: TcpStream, node_id: String, addr: SocketAddr, peer_address: String) -> std::io::Result<Self> {
+        let ip = addr.ip().to_string();
+
+        let (read_half, write_half) = stream.into_split();
+        let (outbound, outbound_rx) = mpsc::channel(OUTBOUND_QUEUE);
+        let (inbound_tx, inbound) = mpsc::channel(INBOUND_QUEUE);
+
+        let writer = tokio::spawn(write_loop(write_half, outbound_rx, node.metrics.clone()).in_current_span());
+        let reader = tokio::spawn(read_loop(read_half, inbound_tx, node.metrics.clone()).in_current_span());
+
+        Ok(PeerSession {
+            node,
+            node_id,
+            addr,
+            peer_address,
+            ip,
+            has_llm: false,
+            access_requested: false,
+            outbound,
+            inbound,
+            writer,
+            reader,
+        })
+    }
+
+    // Lets other parts of the node (e.g. an operator approving access) write to this peer
+    pub fn outbound(&self) -> mpsc::Sender<Message> {
+        self.outbound.clone()
+    }
+
+    async fn send(&self, message: Message) -> std::io::Result<()> {
+        self.outbound.send(message).await.map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe, format!("Connection to {} is no longer writable", self.addr))
+        })
+    }
+
+    pub async fn run(mut self, handle: &SessionHandle) -> std::io::Result<()> {
+        // Peers use our LLM server through this session, so it only has to be up
+        self.has_llm = self.node.backend.health().await;
+        self.send(Message::LLMCapability { has_llm: self.has_llm }).await?;
+
+        if self.has_llm {
+            info!("Announced LLM capability");
+        } else {
+            info!("Announced no LLM capability (LLM server not available)");
+        }
+
+        let shared = self.node.shared.manifests().await;
+        if !shared.is_empty() {
+            self.send(Message::FileOffer { files: shared }).await?;
+        }
+
+        // Both timers fire immediately, so the peer gets our status, our conversation
+        // and a first RTT sample right after the capability announcement
+        let mut heartbeat_interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+        let mut share_interval = tokio::time::interval(self.node.config.sync_interval());
+
+        loop {
+            tokio::select! {
+                _ = handle.superseded() => {
+                    info!("Closing connection in favour of another session");
+                    return Ok(());
+                }
+                _ = self.node.shutdown.cancelled() => {
+                    self.say_goodbye("Node is shutting down").await;
+                    return Ok(());
+                }
+                _ = heartbeat_interval.tick() => {
+                    self.send(Message::Ping { sent_at: Utc::now().timestamp_millis() }).await?;
+                }
+                _ = share_interval.tick() => {
+                    self.send(Message::NodeStatus(mesh::announcement(&self.node).await)).await?;
+                    self.share_conversation().await?;
+                    // Request sync from peer to ensure we have their latest conversation
+                    self.send(Message::SyncRequest).await?;
+                }
+                received = self.inbound.recv() => match received {
+                    Some(Ok(Message::Goodbye { reason })) => {
+                        self.handle_goodbye(reason).await;
+                        return Ok(());
+                    }
+                    Some(Ok(message)) => self.dispatch(message).await?,
+                    Some(Err(e)) => {
+                        warn!("Error reading from peer: {}", e);
+                        return Err(e);
+                    }
+                    None => return Ok(()),
+                },
+            }
+        }
+    }
+
+    async fn share_conversation(&self) -> std::io::Result<()> {
+        if let Some(conversation) = self.node.conversations.get_local_conversation().await {
+            match serde_json::to_string(&conversation) {
+                Ok(content) => {
+                    self.send(Message::ConversationFile {
+                        name: PEER_CONVERSATION_FILE.to_string(),
+                        content,
+                    }).await?;
+                    self.node.conversation_synced(&self.node_id, &conversation.id, conversation.messages.len(), "sent");
+                    debug!(conversation = %conversation.id, "Sent local conversation");
+                }
+                Err(e) => error!(conversation = %conversation.id, "Failed to serialize conversation: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    async fn dispatch(&mut self, message: Message) -> std::io::Result<()> {
+        match message {
+            Message::ConversationFile { name, content } => self.handle_conversation_file(name, content).await,
+            Message::SyncRequest => {
+                self.share_conversation().await?;
+                debug!("Answered sync request");
+            }
+            Message::LLMAccessRequest { peer_name, reason } => {
+                self.handle_access_request(peer_name, reason).await?;
+            }
+            Message::LLMCapability { has_llm } => self.handle_capability(has_llm).await?,
+            Message::LLMAccessResponse { granted, message, llm_backend, .. } => {
+                // Older hosts still send their server's address; it goes unused
+                self.handle_access_response(granted, message, llm_backend).await;
+            }
+            Message::Ping { sent_at } => {
+                // Echo the sender's timestamp so it can compute the RTT against its own clock
+                self.send(Message::Pong {
+                    sent_at,
+                    received_at: Utc::now().timestamp_millis(),
+                }).await?;
+            }
+            Message::Pong { sent_at, .. } => self.handle_pong(sent_at).await,
+            Message::FileOffer { files } => {
+                let max_size = self.node.config.max_shared_file_bytes();
+                self.node.shared.record_offers(&self.node_id, files, max_size).await;
+            }
+            Message::FileWithdraw { hash } => self.node.shared.withdraw(&self.node_id, &hash).await,
+            Message::ChunkRequest { hash, index } => {
+                let reply = files::chunk_response(&self.node, hash, index).await;
+                self.send(reply).await?;
+            }
+            Message::ChunkData { hash, index, data } => {
+                self.node.shared.deliver(&self.node_id, hash, index, Ok(data)).await;
+            }
+            Message::ChunkUnavailable { hash, index, reason } => {
+                self.node.shared.deliver(&self.node_id, hash, index, Err(reason)).await;
+            }
+            Message::InferenceRequest(request) => {
+                llm::serve_peer_request(self.node.clone(), self.node_id.clone(), self.ip.clone(), request, self.outbound.clone());
+            }
+            Message::InferenceReply(reply) => self.node.remote_calls.deliver(&self.node_id, reply),
+            Message::InferenceCancel { id } => llm::cancel_peer_request(&self.node, &self.node_id, id),
+            Message::NodeStatus(announcement) => {
+                self.node.mesh.record(&self.node_id, &self.peer_address, announcement).await;
+            }
+            message @ (Message::Hello { .. } | Message::SyncResponse(_) | Message::Goodbye { .. }) => {
+                warn!("Received unexpected {} message", message.kind());
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_conversation_file(&self, name: String, content: String) {
+        if name != PEER_CONVERSATION_FILE {
+            warn!("Ignoring file {}", name);
+            return;
+        }
+
+        // Parse before touching disk so a malformed payload never replaces a good copy
+        let conversation = match serde_json::from_str::<Conversation>(&content) {
+            Ok(conversation) => conversation,
+            Err(e) => {
+                warn!(
+                    content = %logging::content(&content, self.node.config.logging.log_content),
+                    "Failed to parse conversation: {}", e
+                );
+                return;
+            }
+        };
+
+        // The store writes it to the peer's directory, within the peer's quota
+        let span = debug_span!("conversation", id = %conversation.id);
+        async {
+            let (id, messages) = (conversation.id.clone(), conversation.messages.len());
+            match self.node.conversations.add_peer_conversation(self.ip.clone(), conversation).await {
+                Ok(()) => {
+                    self.node.conversation_synced(&self.node_id, &id, messages, "received");
+                    debug!(messages, "Received conversation");
+                }
+                Err(e) => warn!("Not keeping conversation: {}", e),
+            }
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn handle_access_request(&self, peer_name: String, reason: String) -> std::io::Result<()> {
+        info!("Received LLM access request from {}: {}", peer_name, reason);
+
+        if !self.has_llm {
+            self.node.access_decided(&self.node_id, "sent", false);
+            return self.send(Message::LLMAccessResponse {
+                granted: false,
+                message: "This peer does not have LLM capability".to_string(),
+                llm_host: None,
+                llm_port: None,
+                llm_backend: None,
+            }).await;
+        }
+
+        let already_authorized = self.node.authorized_peers.lock().await.contains(&self.ip);
+        let pending = !already_authorized && !self.node.config.access.auto_approve;
+        self.node.events.publish(Event::AccessRequested { node_id: self.node_id.clone(), direction: "received", pending });
+        if pending {
+            self.node.access_queue.add(AccessRequest {
+                node_id: self.node_id.clone(),
+                ip: self.ip.clone(),
+                peer_name,
+                reason,
+                requested_at: Utc::now(),
+            }).await;
+            info!("LLM access request is waiting for approval");
+            return Ok(());
+        }
+
+        self.send(access_granted(&self.node, "Access granted automatically")).await?;
+        self.node.access_decided(&self.node_id, "sent", true);
+
+        let mut authorized = self.node.authorized_peers.lock().await;
+        authorized.insert(self.ip.clone());
+        info!("Granted LLM access to {}", peer_name);
+        Ok(())
+    }
+
+    async fn handle_capability(&mut self, has_llm: bool) -> std::io::Result<()> {
+        let mut llm_peers = self.node.llm_peers.lock().await;
+        let changed = if has_llm { llm_peers.insert(self.node_id.clone()) } else { llm_peers.remove(&self.node_id) };
+        drop(llm_peers);
+        if changed {
+            self.node.events.publish(Event::CapabilityChanged { node_id: self.node_id.clone(), has_llm });
+        }
+        if !has_llm {
+            info!("Peer does not have LLM capability");
+            return Ok(());
+        }
+        info!("Peer has LLM capability");
+
+        // The answer comes back through `dispatch` like any other message
+        let authorized = self.node.llm_connections.lock().await.contains_key(&self.node_id);
+        if !authorized && !self.access_requested {
+            info!("Sending LLM access request");
+            self.send(llm_access_request()).await?;
+            self.node.events.publish(Event::AccessRequested { node_id: self.node_id.clone(), direction: "sent", pending: true });
+            self.access_requested = true;
+        }
+        Ok(())
+    }
+
+    async fn handle_access_response(&mut self, granted: bool, message: String, llm_backend: Option<BackendKind>) {
+        self.access_requested = false;
+        self.node.access_decided(&self.node_id, "received", granted);
+
+        if !granted {
+            info!("LLM access denied: {}", message);
+            return;
+        }
+
+        info!("LLM access granted: {}", message);
+
+        let Some(backend) = llm_backend else {
+            warn!("Peer runs an LLM server of a kind we cannot talk to");
+            return;
+        };
+        let mut connections = self.node.llm_connections.lock().await;
+        info!("LLM access through the session stored ({})", backend.as_str());
+        connections.insert(self.node_id.clone(), LlmEndpoint { host: self.ip.clone(), backend });
+    }
+
+    async fn say_goodbye(&mut self, reason: &str) {
+        if self.send(Message::Goodbye { reason: reason.to_string() }).await.is_err() {
+            return;
+        }
+        if tokio::time::timeout(GOODBYE_TIMEOUT, &mut self.writer).await.is_err() {
+            warn!("Timed out saying goodbye");
+        }
+    }
+
+    // The peer is going away on purpose, so stop routing chat requests to it right
+    // away instead of waiting for requests to fail
+    async fn handle_goodbye(&self, reason: String) {
+        info!("Peer is closing the session: {}", reason);
+        self.node.llm_peers.lock().await.remove(&self.node_id);
+        self.node.llm_connections.lock().await.remove(&self.node_id);
+        self.node.shared.forget_peer(&self.node_id).await;
+    }
+
+    async fn handle_pong(&self, sent_at: i64) {
+        let elapsed_ms = Utc::now().timestamp_millis() - sent_at;
+        if elapsed_ms < 0 {
+            debug!("Ignoring pong with a timestamp in the future");
+            return;
+        }
+        self.node.peers.record_rtt(&self.ip, Duration::from_millis(elapsed_ms as u64)).await;
+    }
+}
+
+impl Drop for PeerSession {
+    fn drop(&mut self) {
+        info!("Session ended");
+        self.reader.abort();
+        self.writer.abort();
+    }
+}
+
+fn llm_access_request() -> Message {
+    let hostname = hostname::get()
+        .map(|h| h.to_string_lossy().to_string())
+        .unwrap_or_else(|_| "Unknown".to_string());
+
+    Message::LLMAccessRequest {
+        peer_name: hostname,
+        reason: "Requesting access to LLM services".to_string(),
+    }
+}
@@ -7,68 +7,77 @@ use std::sync::Arc;
 use ipconfig::get_adapters;
 use std::net::{IpAddr, Ipv4Addr};
 use serde::{Serialize, Deserialize};
-use reqwest::Client;
 use chrono::{DateTime, Utc};
+use tracing::{debug, info, warn};
+use crate::events::Event;
 use crate::ip::is_my_ip;
-use once_cell::sync::Lazy;
+use crate::node::Node;
 
-const BROADCAST_PORT: u16 = 5000;
-const BROADCAST_INTERVAL: Duration = Duration::from_secs(30);
-const LISTEN_ADDR: &str = "0.0.0.0:5000";
-const OLLAMA_CHECK_URL: &str = "http://127.0.0.1:11434/api/tags";
 const PEER_TIMEOUT: Duration = Duration::from_secs(60);
+// Older nodes do not announce their TCP port; they all listen on the default one
+const DEFAULT_TCP_PORT: u16 = 7878;
 
-// Replace lazy_static with once_cell for async Mutex
-static LAST_SEEN: Lazy<Arc<Mutex<HashMap<String, DateTime<Utc>>>>> = 
-    Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+// Per-node discovery state: when each peer was last announced, when we last logged
+// our own broadcast, and peers found since the connector last looked
+pub struct Discovery {
+    last_seen: Mutex<HashMap<String, DateTime<Utc>>>,
+    last_broadcast: Mutex<Option<DateTime<Utc>>>,
+    discovered: Mutex<HashSet<String>>,
+}
 
-// Track last broadcast time
-static LAST_BROADCAST: Lazy<Arc<Mutex<Option<DateTime<Utc>>>>> = 
-    Lazy::new(|| Arc::new(Mutex::new(None)));
+impl Discovery {
+    pub fn new() -> Self {
+        Discovery {
+            last_seen: Mutex::new(HashMap::new()),
+            last_broadcast: Mutex::new(None),
+            discovered: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub async fn take_discovered(&self) -> Vec<String> {
+        let mut discovered = self.discovered.lock().await;
+        discovered.drain().collect()
+    }
+}
+
+fn default_tcp_port() -> u16 {
+    DEFAULT_TCP_PORT
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 struct BroadcastMessage {
     message_type: String,
     has_llm: bool,
     timestamp: DateTime<Utc>,
+    #[serde(default)]
+    node_id: Option<String>,
+    #[serde(default = "default_tcp_port")]
+    tcp_port: u16,
 }
 
-// Check if Ollama is running
-async fn is_ollama_available() -> bool {
-    if let Ok(client) = Client::builder()
-        .timeout(Duration::from_secs(2))
-        .build() 
-    {
-        match client.get(OLLAMA_CHECK_URL).send().await {
-            Ok(response) => response.status().is_success(),
-            Err(_) => false,
-        }
-    } else {
-        false
-    }
-}
-
-async fn send_broadcast(broadcast_addr: String) -> Result<(), std::io::Error> {
+async fn send_broadcast(node: &Node, broadcast_addr: String) -> Result<(), std::io::Error> {
     let socket = UdpSocket::bind("0.0.0.0:0").await?;
     socket.set_broadcast(true)?;
     
-    let has_llm = is_ollama_available().await;
+    let has_llm = node.backend.health().await;
     let message = BroadcastMessage {
         message_type: "ONLINE".to_string(),
         has_llm,
         timestamp: Utc::now(),
+        node_id: Some(node.node_id.clone()),
+        tcp_port: node.config.tcp.port,
     };
     
     let message_bytes = serde_json::to_string(&message)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .map_err(std::io::Error::other)?
         .into_bytes();
     
-    // Only print broadcast message once per interval using async Mutex
-    let mut last_broadcast = LAST_BROADCAST.lock().await;
+    // Only log the broadcast once per interval, however many interfaces it goes out on
+    let mut last_broadcast = node.discovery.last_broadcast.lock().await;
     let now = Utc::now();
     if last_broadcast.is_none() || 
-       now.signed_duration_since(last_broadcast.unwrap()).num_seconds() >= BROADCAST_INTERVAL.as_secs() as i64 {
-        println!("UDP: Broadcasting to {} (LLM available: {})", broadcast_addr, has_llm);
+       now.signed_duration_since(last_broadcast.unwrap()).num_seconds() >= node.config.broadcast_interval().as_secs() as i64 {
+        debug!("Broadcasting to {} (LLM available: {})", broadcast_addr, has_llm);
         *last_broadcast = Some(now);
     }
     
@@ -76,8 +85,9 @@ async fn send_broadcast(broadcast_addr: String) -> Result<(), std::io::Error> {
     Ok(())
 }
 
-pub async fn periodic_broadcast() {
-    let mut interval = interval(BROADCAST_INTERVAL);
+pub async fn periodic_broadcast(node: Arc<Node>) {
+    let broadcast_port = node.config.udp.broadcast_port;
+    let mut interval = interval(node.config.broadcast_interval());
     loop {
         interval.tick().await;
         if let Ok(adapters) = get_adapters() {
@@ -95,9 +105,9 @@ pub async fn periodic_broadcast() {
                                 None => None,
                             };
                             if let Some(broadcast_addr) = subnet_mask {
-                                let broadcast_addr = format!("{}:{}", broadcast_addr, BROADCAST_PORT);
-                                if let Err(e) = send_broadcast(broadcast_addr).await {
-                                    eprintln!("UDP: Broadcast error: {}", e);
+                                let broadcast_addr = format!("{}:{}", broadcast_addr, broadcast_port);
+                                if let Err(e) = send_broadcast(&node, broadcast_addr).await {
+                                    warn!("Broadcast error: {}", e);
                                 }
                             }
                         }
@@ -108,9 +118,11 @@ pub async fn periodic_broadcast() {
     }
 }
 
-pub async fn receive_broadcast(received_ips: Arc<Mutex<HashSet<String>>>) -> Result<(), std::io::Error> {
-    println!("UDP: Listening on {}", LISTEN_ADDR);
-    let socket = UdpSocket::bind(LISTEN_ADDR).await?;
+// Discovered peers are reported as the address they accept TCP sessions on ("ip:port")
+pub async fn receive_broadcast(node: Arc<Node>) -> Result<(), std::io::Error> {
+    let listen_addr = format!("0.0.0.0:{}", node.config.udp.broadcast_port);
+    info!("Listening on {}", listen_addr);
+    let socket = UdpSocket::bind(&listen_addr).await?;
     let mut buf = [0; 1024];
 
     loop {
@@ -118,18 +130,27 @@ pub async fn receive_broadcast(received_ips: Arc<Mutex<HashSet<String>>>) -> Res
         if let Ok(message_str) = String::from_utf8(buf[..size].to_vec()) {
             if let Ok(broadcast_msg) = serde_json::from_str::<BroadcastMessage>(&message_str) {
                 let ip = src.ip().to_string();
-                if !is_my_ip(&ip) {
-                    let mut last_seen = LAST_SEEN.lock().await;
+                // Nodes that announce an ID can share a host with us; older ones are
+                // recognised by IP only
+                let is_self = match &broadcast_msg.node_id {
+                    Some(node_id) => *node_id == node.node_id,
+                    None => is_my_ip(&ip),
+                };
+                if !is_self {
+                    let address = format!("{}:{}", ip, broadcast_msg.tcp_port);
+                    let mut last_seen = node.discovery.last_seen.lock().await;
                     let now = Utc::now();
                     
                     // Only process if we haven't seen this peer recently
-                    if !last_seen.contains_key(&ip) || 
-                       now.signed_duration_since(*last_seen.get(&ip).unwrap()).num_seconds() >= PEER_TIMEOUT.as_secs() as i64 {
-                        println!("UDP: Discovered peer {} (LLM available: {})", ip, broadcast_msg.has_llm);
-                        last_seen.insert(ip.clone(), now);
+                    if !last_seen.contains_key(&address) || 
+                       now.signed_duration_since(*last_seen.get(&address).unwrap()).num_seconds() >= PEER_TIMEOUT.as_secs() as i64 {
+                        info!("Discovered peer {} (LLM available: {})", address, broadcast_msg.has_llm);
+                        last_seen.insert(address.clone(), now);
                         
-                        let mut ips = received_ips.lock().await;
-                        ips.insert(ip);
+                        let mut discovered = node.discovery.discovered.lock().await;
+                        if discovered.insert(address.clone()) {
+                            node.events.publish(Event::PeerDiscovered { address });
+                        }
                     }
                 }
             }
@@ -0,0 +1,141 @@
+mod common;
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use common::{FakeLlm, NodeOptions, TestNode, FAKE_REPLY};
+use serde_json::{json, Value};
+
+// Known only once the client is up, and needed in the host's configuration
+static CLIENT_ID: OnceLock<String> = OnceLock::new();
+
+async fn ask(node: &TestNode, body: Value) -> String {
+    let response = node.post_json("/api/chat", body).await;
+    assert!(response.status().is_success());
+    let message: Value = response.json().await.unwrap();
+    message["content"].as_str().unwrap().to_string()
+}
+
+fn deterministic(message: &str) -> Value {
+    json!({ "message": message, "sender": "tester", "options": { "temperature": 0.0 } })
+}
+
+#[actix_web::test]
+async fn identical_deterministic_requests_are_answered_once() {
+    let llm = FakeLlm::start().await;
+    let node = TestNode::start(NodeOptions {
+        llm_url: Some(llm.url()),
+        configure: |config| config.cache.enabled = true,
+        ..Default::default()
+    })
+    .await;
+
+    assert_eq!(ask(&node, deterministic("Say hello")).await, FAKE_REPLY.concat());
+    assert_eq!(llm.last_chat().unwrap()["options"], json!({ "temperature": 0.0 }));
+    assert_eq!(ask(&node, deterministic("Say hello")).await, FAKE_REPLY.concat());
+    assert_eq!(llm.chat_requests(), 1);
+
+    // Keeping the model loaded for longer makes no difference to the answer
+    let mut kept_loaded = deterministic("Say hello");
+    kept_loaded["options"]["keep_alive"] = json!("1h");
+    assert_eq!(ask(&node, kept_loaded).await, FAKE_REPLY.concat());
+    assert_eq!(llm.chat_requests(), 1);
+
+    // The streamed and OpenAI endpoints share the same answers
+    let streamed = node.post_json("/api/chat/stream", deterministic("Say hello")).await.text().await.unwrap();
+    let chunks: Vec<Value> = streamed.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(chunks[0]["content"], FAKE_REPLY.concat());
+    assert_eq!(chunks.last().unwrap()["done"], true);
+    let model = node.node.config().llm.model.clone();
+    let completion = node.post_json("/v1/chat/completions", json!({
+        "model": model,
+        "messages": [{ "role": "user", "content": "Say hello" }],
+        "temperature": 0,
+    }))
+    .await;
+    let completion: Value = completion.json().await.unwrap();
+    assert_eq!(completion["choices"][0]["message"]["content"], FAKE_REPLY.concat());
+    assert_eq!(llm.chat_requests(), 1);
+
+    // Bypassing the cache, or leaving the temperature to the server, generates afresh
+    let mut bypass = deterministic("Say hello");
+    bypass["no_cache"] = json!(true);
+    ask(&node, bypass).await;
+    ask(&node, json!({ "message": "Say hello", "sender": "tester" })).await;
+    assert_eq!(llm.chat_requests(), 3);
+
+    let stats = node.get_json("/api/cache").await.unwrap();
+    assert_eq!(stats["entries"], 1);
+    assert_eq!(stats["hits"], 4);
+    assert_eq!(stats["misses"], 1);
+    let metrics = node.client.get(node.url("/metrics")).send().await.unwrap().text().await.unwrap();
+    assert!(metrics.contains("neuromesh_response_cache_lookups_total{result=\"hit\"} 4"));
+
+    let cleared = node.client.delete(node.url("/api/cache")).send().await.unwrap().json::<Value>().await.unwrap();
+    assert_eq!(cleared["cleared"], 1);
+    ask(&node, deterministic("Say hello")).await;
+    assert_eq!(llm.chat_requests(), 4);
+
+    node.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn answers_expire_and_make_room_for_newer_ones() {
+    let llm = FakeLlm::start().await;
+    let node = TestNode::start(NodeOptions {
+        llm_url: Some(llm.url()),
+        configure: |config| {
+            config.cache.enabled = true;
+            config.cache.ttl_secs = 1;
+            config.cache.max_entries = 1;
+        },
+        ..Default::default()
+    })
+    .await;
+
+    ask(&node, deterministic("First question")).await;
+    ask(&node, deterministic("Second question")).await;
+    ask(&node, deterministic("Second question")).await;
+    assert_eq!(llm.chat_requests(), 2);
+    ask(&node, deterministic("First question")).await;
+    assert_eq!(llm.chat_requests(), 3);
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    ask(&node, deterministic("First question")).await;
+    assert_eq!(llm.chat_requests(), 4);
+
+    node.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn a_host_answers_a_trusted_peer_from_its_cache() {
+    let llm = FakeLlm::start().await;
+    let client = TestNode::start(NodeOptions::default()).await;
+    CLIENT_ID.set(client.node.node_id().to_string()).unwrap();
+    let host = TestNode::start(NodeOptions {
+        llm_url: Some(llm.url()),
+        seeds: vec![client.tcp_port],
+        configure: |config| {
+            config.cache.enabled = true;
+            config.cache.share_with = vec![CLIENT_ID.get().unwrap().clone()];
+        },
+        ..Default::default()
+    })
+    .await;
+    client.wait_for_llm_access().await;
+
+    assert_eq!(ask(&client, deterministic("Say hello")).await, FAKE_REPLY.concat());
+    assert_eq!(ask(&client, deterministic("Say hello")).await, FAKE_REPLY.concat());
+    assert_eq!(llm.chat_requests(), 1);
+    assert_eq!(host.get_json("/api/cache").await.unwrap()["hits"], 1);
+
+    // Only the request that reached the server is charged to the peer
+    let served = host.get_json("/api/usage?direction=served").await.unwrap();
+    assert_eq!(served[0]["requests"], 1);
+
+    client.stop().await.unwrap();
+    host.stop().await.unwrap();
+    llm.stop().await;
+}
@@ -0,0 +1,201 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{wait_for, FakeLlm, NodeOptions, TestNode, FAKE_REPLY, WAIT_TIMEOUT};
+use futures::StreamExt;
+use serde_json::{json, Value};
+
+// The fake answer takes five of these, well past the point where each test interrupts it
+const PIECE_DELAY: Duration = Duration::from_secs(1);
+
+async fn running_requests(node: &TestNode) -> Vec<Value> {
+    node.get_json("/api/requests").await.unwrap().as_array().unwrap().clone()
+}
+
+#[actix_web::test]
+async fn cancelling_a_streaming_chat_stops_the_llm() {
+    let llm = FakeLlm::start().await;
+    llm.slow_down(PIECE_DELAY);
+    let node = TestNode::start(NodeOptions { llm_url: Some(llm.url()), ..Default::default() }).await;
+
+    let response = node.post_json("/api/chat/stream", json!({ "message": "Say hello", "sender": "tester" })).await;
+    assert!(response.status().is_success());
+    let id = response.headers()["x-request-id"].to_str().unwrap().to_string();
+    let running = running_requests(&node).await;
+    assert_eq!(running.len(), 1);
+    assert_eq!(running[0]["id"], id);
+    assert_eq!(running[0]["requester"], "local");
+
+    let cancel = node.post_json(&format!("/api/requests/{}/cancel", id), json!({})).await;
+    assert!(cancel.status().is_success());
+
+    let body = response.text().await.unwrap();
+    let last: Value = serde_json::from_str(body.lines().last().unwrap()).unwrap();
+    assert_eq!(last["done"], true);
+    assert_eq!(last["error"], "Request cancelled");
+    wait_for("the LLM server to see the answer abandoned", || async { (llm.aborted_chats() == 1).then_some(()) }).await;
+    assert!(running_requests(&node).await.is_empty());
+
+    let again = node.post_json(&format!("/api/requests/{}/cancel", id), json!({})).await;
+    assert_eq!(again.status(), 404);
+
+    node.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn a_client_hanging_up_cancels_the_request_on_the_host() {
+    let llm = FakeLlm::start().await;
+    llm.slow_down(PIECE_DELAY);
+    let host = TestNode::start(NodeOptions { llm_url: Some(llm.url()), ..Default::default() }).await;
+    let client = TestNode::start(NodeOptions { seeds: vec![host.tcp_port], ..Default::default() }).await;
+    client.wait_for_llm_access().await;
+
+    let response = client.post_json("/api/chat/stream", json!({ "message": "Say hello", "sender": "tester" })).await;
+    assert!(response.status().is_success());
+    let hosted = running_requests(&host).await;
+    assert_eq!(hosted.len(), 1);
+    assert_eq!(hosted[0]["requester"], client.node.node_id());
+
+    // Dropping the response closes its connection
+    drop(response);
+    wait_for("the host's LLM server to see the answer abandoned", || async { (llm.aborted_chats() == 1).then_some(()) }).await;
+    wait_for("both nodes to forget the request", || async {
+        (running_requests(&host).await.is_empty() && running_requests(&client).await.is_empty()).then_some(())
+    })
+    .await;
+
+    client.stop().await.unwrap();
+    host.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn a_peer_is_charged_for_what_it_got_before_cancelling() {
+    let llm = FakeLlm::start().await;
+    llm.slow_down(PIECE_DELAY);
+    let host = TestNode::start(NodeOptions { llm_url: Some(llm.url()), ..Default::default() }).await;
+    let client = TestNode::start(NodeOptions { seeds: vec![host.tcp_port], ..Default::default() }).await;
+    client.wait_for_llm_access().await;
+
+    let response = client.post_json("/api/chat/stream", json!({ "message": "Say hello", "sender": "tester" })).await;
+    let id = response.headers()["x-request-id"].to_str().unwrap().to_string();
+    let mut body = response.bytes_stream();
+    let mut received = String::new();
+    while !received.contains(FAKE_REPLY[0]) {
+        let bytes = tokio::time::timeout(WAIT_TIMEOUT, body.next()).await.unwrap().unwrap().unwrap();
+        received.push_str(&String::from_utf8_lossy(&bytes));
+    }
+    let cancel = client.post_json(&format!("/api/requests/{}/cancel", id), json!({})).await;
+    assert!(cancel.status().is_success());
+    wait_for("the host to forget the request", || async { running_requests(&host).await.is_empty().then_some(()) }).await;
+
+    let limits = host.get_json("/api/limits").await.unwrap();
+    assert_eq!(limits[0]["ip"], "127.0.0.1");
+    let charged = limits[0]["tokens_today"].as_u64().unwrap();
+    assert!(charged >= 1, "nothing charged: {}", limits);
+
+    // The ledger records the same charge
+    let entries = wait_for("the host to record the answer", || async {
+        let entries = host.get_json("/api/usage/ledger?limit=1").await?;
+        (entries[0]["outcome"] == "incomplete").then_some(entries)
+    })
+    .await;
+    assert_eq!(entries[0]["completion_tokens"], charged);
+
+    client.stop().await.unwrap();
+    host.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn a_request_named_by_the_client_can_be_cancelled_before_it_is_answered() {
+    let llm = FakeLlm::start().await;
+    llm.slow_down(PIECE_DELAY);
+    let host = TestNode::start(NodeOptions { llm_url: Some(llm.url()), ..Default::default() }).await;
+    let client = TestNode::start(NodeOptions { seeds: vec![host.tcp_port], ..Default::default() }).await;
+    client.wait_for_llm_access().await;
+
+    let asking = tokio::spawn(
+        client.client.post(client.url("/api/chat"))
+            .header("X-Request-Id", "tab-42")
+            .json(&json!({ "message": "Say hello", "sender": "tester" }))
+            .send(),
+    );
+    wait_for("the host to start on the request", || async {
+        (!running_requests(&host).await.is_empty()).then_some(())
+    })
+    .await;
+    assert_eq!(running_requests(&client).await[0]["id"], "tab-42");
+
+    let cancel = client.post_json("/api/requests/tab-42/cancel", json!({})).await;
+    assert!(cancel.status().is_success());
+    let answer = asking.await.unwrap().unwrap();
+    assert_eq!(answer.status(), 409);
+    assert_eq!(answer.headers()["x-request-id"], "tab-42");
+    wait_for("the host's LLM server to see the answer abandoned", || async { (llm.aborted_chats() == 1).then_some(()) }).await;
+    wait_for("the host to forget the request", || async { running_requests(&host).await.is_empty().then_some(()) }).await;
+
+    // An ID already in use is not handed out twice
+    let ask = || client.client.post(client.url("/api/chat/stream"))
+        .header("X-Request-Id", "tab-7")
+        .json(&json!({ "message": "Say hello", "sender": "tester" }))
+        .send();
+    let (first, second) = tokio::join!(ask(), ask());
+    let ids = [first.unwrap(), second.unwrap()].map(|response| response.headers()["x-request-id"].to_str().unwrap().to_string());
+    assert!(ids.contains(&"tab-7".to_string()) && ids[0] != ids[1], "IDs handed out: {:?}", ids);
+
+    client.stop().await.unwrap();
+    host.stop().await.unwrap();
+    llm.stop().await;
+}
+
+// An address of this machine other than loopback, as a client elsewhere on the network
+// would appear; None where there is no network
+fn lan_ip() -> Option<std::net::IpAddr> {
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+    // Connecting a UDP socket sends nothing; it only picks the outgoing address
+    socket.connect("192.0.2.1:9").ok()?;
+    Some(socket.local_addr().ok()?.ip()).filter(|ip| !ip.is_loopback() && !ip.is_unspecified())
+}
+
+#[actix_web::test]
+async fn a_remote_client_may_cancel_only_its_own_request() {
+    let Some(ip) = lan_ip() else {
+        eprintln!("no non-loopback address, skipping");
+        return;
+    };
+    let llm = FakeLlm::start().await;
+    llm.slow_down(PIECE_DELAY);
+    let node = TestNode::start(NodeOptions {
+        llm_url: Some(llm.url()),
+        configure: |config| config.http.bind = "0.0.0.0".to_string(),
+        ..Default::default()
+    })
+    .await;
+    let remote = |path: &str| format!("http://{}:{}{}", ip, node.http_port, path);
+
+    let response = node.client
+        .post(remote("/api/chat/stream"))
+        .header("X-Request-Id", "remote-1")
+        .json(&json!({ "message": "Say hello", "sender": "tester" }))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    // Someone else who is not the operator may not stop it
+    let stranger = node.client.post(node.url("/api/requests/remote-1/cancel")).header("Host", "example.com").send().await.unwrap();
+    assert_eq!(stranger.status(), 403);
+    assert_eq!(running_requests(&node).await.len(), 1);
+
+    let cancel = node.client.post(remote("/api/requests/remote-1/cancel")).send().await.unwrap();
+    assert!(cancel.status().is_success());
+    let body = response.text().await.unwrap();
+    let last: Value = serde_json::from_str(body.lines().last().unwrap()).unwrap();
+    assert_eq!(last["error"], "Request cancelled");
+
+    node.stop().await.unwrap();
+    llm.stop().await;
+}
@@ -0,0 +1,587 @@
+// Harness for end-to-end tests: several nodes in one process on loopback, each with
+// its own ports and data directory, plus a fake LLM server they can share. Nodes find
+// each other through seed peers; UDP discovery is off because broadcasts do not
+// reach other sockets bound to the same port on one host.
+#![allow(dead_code)]
+
+use std::future::Future;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::dev::ServerHandle;
+use actix_web::{get, post, web, App, HttpResponse, HttpServer};
+use neuromesh::config::BackendKind;
+use neuromesh::{Config, Node};
+use serde_json::{json, Value};
+use tempfile::TempDir;
+use tokio::task::JoinHandle;
+
+pub const WAIT_TIMEOUT: Duration = Duration::from_secs(20);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub const FAKE_MODEL: &str = "phi3-fast";
+pub const FAKE_REPLY: [&str; 4] = ["Hello", " from", " the", " fake model"];
+// Server-side timings the fake model reports for every chat
+pub const FAKE_TOTAL_DURATION_MS: u64 = 40;
+pub const FAKE_EVAL_DURATION_MS: u64 = 30;
+
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .expect("no free port")
+}
+
+// Polls `check` until it returns Some or the timeout runs out
+pub async fn wait_for<T, F, Fut>(what: &str, mut check: F) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Option<T>>,
+{
+    let deadline = tokio::time::Instant::now() + WAIT_TIMEOUT;
+    loop {
+        if let Some(value) = check().await {
+            return value;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            panic!("timed out after {:?} waiting for {}", WAIT_TIMEOUT, what);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[derive(Clone, Default)]
+struct FakeLlmState {
+    chat_requests: Arc<AtomicUsize>,
+    embed_requests: Arc<AtomicUsize>,
+    last_chat: Arc<Mutex<Option<Value>>>,
+    // Pause before each line of an Ollama chat answer
+    piece_delay: Arc<Mutex<Duration>>,
+    // Lines of an Ollama chat answer sent before it goes silent, if it does
+    stall_after: Arc<Mutex<Option<usize>>>,
+    // Whether the root path reports the server down
+    unhealthy: Arc<std::sync::atomic::AtomicBool>,
+    // Ollama chat answers whose client hung up before the last line
+    aborted_chats: Arc<AtomicUsize>,
+}
+
+// Counts the answer as aborted unless the stream ran to its end
+struct ChatBody {
+    aborted_chats: Arc<AtomicUsize>,
+    finished: bool,
+}
+
+impl Drop for ChatBody {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.aborted_chats.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+// Ollama answers its root path with a plain liveness message
+#[get("/")]
+async fn fake_root(state: web::Data<FakeLlmState>) -> HttpResponse {
+    if state.unhealthy.load(Ordering::SeqCst) {
+        return HttpResponse::ServiceUnavailable().finish();
+    }
+    HttpResponse::Ok().body("Ollama is running")
+}
+
+#[get("/api/tags")]
+async fn fake_tags() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "models": [{ "name": FAKE_MODEL }] }))
+}
+
+#[post("/api/chat")]
+async fn fake_chat(state: web::Data<FakeLlmState>, body: web::Json<Value>) -> HttpResponse {
+    state.chat_requests.fetch_add(1, Ordering::SeqCst);
+    *state.last_chat.lock().unwrap() = Some(body.clone());
+    // Like Ollama, which reads a string keep_alive with Go's time.ParseDuration
+    if let Some(keep_alive) = body["keep_alive"].as_str() {
+        if keep_alive != "0" && keep_alive.trim_start_matches('-').chars().all(|c| c.is_ascii_digit()) {
+            let error = format!("time: missing unit in duration \"{}\"", keep_alive);
+            return HttpResponse::BadRequest().json(json!({ "error": error }));
+        }
+    }
+    let model = body["model"].as_str().unwrap_or(FAKE_MODEL).to_string();
+
+    let mut lines = String::new();
+    for (i, piece) in FAKE_REPLY.iter().enumerate() {
+        let line = json!({
+            "model": model,
+            "created_at": "2024-01-01T00:00:00Z",
+            "message": { "role": "assistant", "content": piece },
+            "done": false,
+        });
+        lines.push_str(&line.to_string());
+        lines.push('\n');
+        if i == FAKE_REPLY.len() - 1 {
+            let done = json!({
+                "model": model,
+                "created_at": "2024-01-01T00:00:00Z",
+                "message": { "role": "assistant", "content": "" },
+                "done": true,
+                "done_reason": "stop",
+                "prompt_eval_count": 5,
+                "eval_count": FAKE_REPLY.len(),
+                "total_duration": FAKE_TOTAL_DURATION_MS * 1_000_000,
+                "eval_duration": FAKE_EVAL_DURATION_MS * 1_000_000,
+            });
+            lines.push_str(&done.to_string());
+            lines.push('\n');
+        }
+    }
+
+    // One chunk per line so clients see a real stream
+    let chunks: Vec<web::Bytes> = lines
+        .split_inclusive('\n')
+        .map(|line| web::Bytes::from(line.to_string()))
+        .collect();
+    let delay = *state.piece_delay.lock().unwrap();
+    let stall_after = *state.stall_after.lock().unwrap();
+    let body = ChatBody { aborted_chats: state.aborted_chats.clone(), finished: false };
+    let stream = futures::stream::unfold((chunks.into_iter().enumerate(), body), move |(mut chunks, mut body)| async move {
+        let Some((i, chunk)) = chunks.next() else {
+            body.finished = true;
+            drop(body);
+            return None;
+        };
+        if stall_after == Some(i) {
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+        }
+        tokio::time::sleep(delay).await;
+        Some((Ok::<_, std::io::Error>(chunk), (chunks, body)))
+    });
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(stream)
+}
+
+// Deterministic stand-in for an embedding: letter counts, so texts sharing words
+// come out close to each other
+pub fn fake_embedding(text: &str) -> Vec<f32> {
+    let mut counts = vec![0.0; 26];
+    for byte in text.to_ascii_lowercase().bytes().filter(u8::is_ascii_lowercase) {
+        counts[(byte - b'a') as usize] += 1.0;
+    }
+    counts
+}
+
+fn embedding_inputs(input: &Value) -> Vec<String> {
+    match input {
+        Value::String(text) => vec![text.clone()],
+        Value::Array(texts) => texts.iter().filter_map(|text| text.as_str().map(str::to_string)).collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[post("/api/embed")]
+async fn fake_embed(state: web::Data<FakeLlmState>, body: web::Json<Value>) -> HttpResponse {
+    state.embed_requests.fetch_add(1, Ordering::SeqCst);
+    let embeddings: Vec<Vec<f32>> = embedding_inputs(&body["input"]).iter().map(|text| fake_embedding(text)).collect();
+    HttpResponse::Ok().json(json!({ "model": body["model"], "embeddings": embeddings }))
+}
+
+#[post("/v1/embeddings")]
+async fn fake_openai_embeddings(state: web::Data<FakeLlmState>, body: web::Json<Value>) -> HttpResponse {
+    state.embed_requests.fetch_add(1, Ordering::SeqCst);
+    let data: Vec<Value> = embedding_inputs(&body["input"])
+        .iter()
+        .enumerate()
+        .map(|(index, text)| json!({ "object": "embedding", "index": index, "embedding": fake_embedding(text) }))
+        .collect();
+    HttpResponse::Ok().json(json!({ "object": "list", "data": data, "model": body["model"] }))
+}
+
+#[get("/v1/models")]
+async fn fake_openai_models() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "object": "list", "data": [{ "id": FAKE_MODEL, "object": "model" }] }))
+}
+
+#[post("/v1/chat/completions")]
+async fn fake_openai_chat(state: web::Data<FakeLlmState>, body: web::Json<Value>) -> HttpResponse {
+    state.chat_requests.fetch_add(1, Ordering::SeqCst);
+    *state.last_chat.lock().unwrap() = Some(body.clone());
+    let model = body["model"].as_str().unwrap_or(FAKE_MODEL).to_string();
+
+    if body["stream"] != true {
+        return HttpResponse::Ok().json(json!({
+            "id": "chatcmpl-fake",
+            "object": "chat.completion",
+            "model": model,
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": FAKE_REPLY.concat() },
+                "finish_reason": "stop",
+            }],
+        }));
+    }
+
+    let chunk = |delta: Value, finish_reason: Value| {
+        let chunk = json!({
+            "id": "chatcmpl-fake",
+            "object": "chat.completion.chunk",
+            "model": model,
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+        });
+        format!("data: {}\n\n", chunk)
+    };
+    let mut events = vec![chunk(json!({ "role": "assistant" }), Value::Null)];
+    events.extend(FAKE_REPLY.iter().map(|piece| chunk(json!({ "content": piece }), Value::Null)));
+    events.push(chunk(json!({}), json!("stop")));
+    if body["stream_options"]["include_usage"] == true {
+        let usage = json!({
+            "id": "chatcmpl-fake",
+            "object": "chat.completion.chunk",
+            "model": model,
+            "choices": [],
+            "usage": { "prompt_tokens": 5, "completion_tokens": FAKE_REPLY.len(), "total_tokens": 5 + FAKE_REPLY.len() },
+        });
+        events.push(format!("data: {}\n\n", usage));
+    }
+    events.push("data: [DONE]\n\n".to_string());
+
+    let chunks: Vec<Result<web::Bytes, std::io::Error>> =
+        events.into_iter().map(|event| Ok(web::Bytes::from(event))).collect();
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .streaming(futures::stream::iter(chunks))
+}
+
+// Speaks just enough of the Ollama API (/, /api/tags, streaming /api/chat, /api/embed)
+// and of the OpenAI API (/v1/models, /v1/chat/completions, /v1/embeddings) for the node
+pub struct FakeLlm {
+    pub port: u16,
+    state: FakeLlmState,
+    handle: ServerHandle,
+}
+
+impl FakeLlm {
+    pub async fn start() -> FakeLlm {
+        let port = free_port();
+        let state = FakeLlmState::default();
+        let data = web::Data::new(state.clone());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .service(fake_root)
+                .service(fake_tags)
+                .service(fake_chat)
+                .service(fake_openai_models)
+                .service(fake_openai_chat)
+                .service(fake_embed)
+                .service(fake_openai_embeddings)
+        })
+        .workers(1)
+        .disable_signals()
+        .bind(("127.0.0.1", port))
+        .expect("fake LLM server could not bind")
+        .run();
+        let handle = server.handle();
+        tokio::spawn(server);
+        FakeLlm { port, state, handle }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+
+    pub fn chat_requests(&self) -> usize {
+        self.state.chat_requests.load(Ordering::SeqCst)
+    }
+
+    // Body of the most recent chat request, as the node sent it
+    pub fn last_chat(&self) -> Option<Value> {
+        self.state.last_chat.lock().unwrap().clone()
+    }
+
+    pub fn embed_requests(&self) -> usize {
+        self.state.embed_requests.load(Ordering::SeqCst)
+    }
+
+    // Makes Ollama chat answers take `delay` per line, long enough to interrupt
+    pub fn slow_down(&self, delay: Duration) {
+        *self.state.piece_delay.lock().unwrap() = delay;
+    }
+
+    // Makes Ollama chat answers stop after `lines` lines while keeping the connection open
+    pub fn stall_after(&self, lines: usize) {
+        *self.state.stall_after.lock().unwrap() = Some(lines);
+    }
+
+    // Makes health checks fail while the server goes on answering everything else
+    pub fn fail_health_checks(&self) {
+        self.state.unhealthy.store(true, Ordering::SeqCst);
+    }
+
+    pub fn aborted_chats(&self) -> usize {
+        self.state.aborted_chats.load(Ordering::SeqCst)
+    }
+
+    pub async fn stop(&self) {
+        self.handle.stop(false).await;
+    }
+}
+
+pub struct TestNode {
+    pub node: Arc<Node>,
+    pub http_port: u16,
+    pub tcp_port: u16,
+    pub client: reqwest::Client,
+    task: JoinHandle<std::io::Result<()>>,
+    // Removed when the node is dropped
+    _dir: TempDir,
+}
+
+pub struct NodeOptions {
+    // LLM server the node treats as its own; None points it at a closed port
+    pub llm_url: Option<String>,
+    pub backend: BackendKind,
+    pub seeds: Vec<u16>,
+    pub auto_approve: bool,
+    // Port for peer connections, for tests that must know it before the node starts
+    pub tcp_port: Option<u16>,
+    // Last word on the configuration, for settings the other options do not cover
+    pub configure: fn(&mut Config),
+}
+
+impl Default for NodeOptions {
+    fn default() -> Self {
+        NodeOptions {
+            llm_url: None,
+            backend: BackendKind::Ollama,
+            seeds: Vec::new(),
+            auto_approve: true,
+            tcp_port: None,
+            configure: |_| {},
+        }
+    }
+}
+
+impl TestNode {
+    pub async fn start(options: NodeOptions) -> TestNode {
+        let dir = tempfile::tempdir().expect("no temp dir");
+        let http_port = free_port();
+        let tcp_port = options.tcp_port.unwrap_or_else(free_port);
+
+        let mut config = Config::default();
+        config.http.bind = "127.0.0.1".to_string();
+        config.http.port = http_port;
+        config.tcp.port = tcp_port;
+        config.tcp.sync_interval_secs = 1;
+        config.udp.enabled = false;
+        let llm_url = options
+            .llm_url
+            .unwrap_or_else(|| format!("http://127.0.0.1:{}", free_port()));
+        config.llm.backend = options.backend;
+        match options.backend {
+            BackendKind::Ollama => config.ollama.url = llm_url,
+            BackendKind::OpenAi => config.openai.url = llm_url,
+        }
+        config.llm.remote_timeout_secs = 10;
+        config.storage.conversations_dir = dir.path().join("conversations");
+        config.storage.received_dir = dir.path().join("received");
+        config.storage.documents_dir = dir.path().join("documents");
+        config.storage.shared_dir = dir.path().join("shared");
+        config.documents.embedding_model = FAKE_MODEL.to_string();
+        config.access.auto_approve = options.auto_approve;
+        config.shutdown.timeout_secs = 5;
+        config.peers.seeds = options
+            .seeds
+            .iter()
+            .map(|port| format!("127.0.0.1:{}", port))
+            .collect();
+        (options.configure)(&mut config);
+        TestNode::launch(config, dir).await
+    }
+
+    async fn launch(config: Config, dir: TempDir) -> TestNode {
+        let (http_port, tcp_port) = (config.http.port, config.tcp.port);
+        let node = Node::new(config).await.expect("node failed to start");
+        let task = tokio::spawn(node.clone().run());
+        let test_node = TestNode {
+            node,
+            http_port,
+            tcp_port,
+            // No idle keep-alive connections, which would hold up the node's graceful HTTP stop
+            client: reqwest::Client::builder()
+                .pool_max_idle_per_host(0)
+                .build()
+                .expect("failed to build HTTP client"),
+            task,
+            _dir: dir,
+        };
+        test_node.wait_until_healthy().await;
+        test_node
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{}", self.http_port, path)
+    }
+
+    pub fn address(&self) -> String {
+        format!("127.0.0.1:{}", self.tcp_port)
+    }
+
+    pub async fn get_json(&self, path: &str) -> Option<Value> {
+        let response = self.client.get(self.url(path)).send().await.ok()?;
+        response.json().await.ok()
+    }
+
+    pub async fn post_json(&self, path: &str, body: Value) -> reqwest::Response {
+        self.client
+            .post(self.url(path))
+            .json(&body)
+            .send()
+            .await
+            .expect("request to node failed")
+    }
+
+    // Multipart upload of (file name, content) pairs
+    async fn upload(&self, path: &str, files: &[(&str, &[u8])]) -> reqwest::Response {
+        let mut form = reqwest::multipart::Form::new();
+        for (name, content) in files {
+            let part = reqwest::multipart::Part::bytes(content.to_vec()).file_name(name.to_string());
+            form = form.part("file", part);
+        }
+        self.client
+            .post(self.url(path))
+            .multipart(form)
+            .send()
+            .await
+            .expect("upload to node failed")
+    }
+
+    pub async fn upload_documents(&self, files: &[(&str, &[u8])]) -> reqwest::Response {
+        self.upload("/api/documents", files).await
+    }
+
+    pub async fn offer_files(&self, files: &[(&str, &[u8])]) -> reqwest::Response {
+        self.upload("/api/files", files).await
+    }
+
+    pub async fn wait_until_healthy(&self) {
+        wait_for("node to report healthy", || async {
+            let health = self.get_json("/api/health").await?;
+            (health["status"] == "ok").then_some(())
+        })
+        .await;
+    }
+
+    // Discovery: the node has `other` in its peer list, whatever the connection state
+    pub async fn wait_for_peer_known(&self, other: &TestNode) {
+        let address = other.address();
+        wait_for(&format!("{} to know about {}", self.address(), address), || async {
+            self.connection_state(&address).await.map(|_| ())
+        })
+        .await;
+    }
+
+    pub async fn wait_for_connection(&self, other: &TestNode) {
+        let address = other.address();
+        wait_for(&format!("{} to connect to {}", self.address(), address), || async {
+            (self.connection_state(&address).await? == "Connected").then_some(())
+        })
+        .await;
+    }
+
+    // Takes the address because the other node has usually been stopped by now
+    pub async fn wait_for_disconnection(&self, address: &str) {
+        wait_for(&format!("{} to lose {}", self.address(), address), || async {
+            (self.connection_state(address).await? != "Connected").then_some(())
+        })
+        .await;
+    }
+
+    pub async fn connection_state(&self, address: &str) -> Option<String> {
+        let connections = self.get_json("/api/connections").await?;
+        connections
+            .as_array()?
+            .iter()
+            .find(|status| status["address"] == address)
+            .and_then(|status| status["state"].as_str().map(str::to_string))
+    }
+
+    // The host granted this node access: its models show up in our model list
+    pub async fn wait_for_llm_access(&self) {
+        self.wait_for_remote_models(1).await;
+    }
+
+    // At least `count` models served by peers, e.g. one each from `count` fake hosts
+    pub async fn wait_for_remote_models(&self, count: usize) {
+        wait_for(&format!("{} to see {} remote models", self.address(), count), || async {
+            let models = self.get_json("/api/models").await?;
+            let remote = models
+                .as_array()?
+                .iter()
+                .filter(|model| model["host"] != "local")
+                .count();
+            (remote >= count).then_some(())
+        })
+        .await;
+    }
+
+    pub async fn wait_for_access_request(&self) -> Value {
+        wait_for(&format!("{} to receive an access request", self.address()), || async {
+            let requests = self.get_json("/api/access").await?;
+            requests.as_array()?.first().cloned()
+        })
+        .await
+    }
+
+    // Conversation sync: a peer's conversation with at least `min_messages` arrived
+    pub async fn wait_for_peer_conversation(&self, min_messages: usize) -> Value {
+        wait_for(&format!("{} to receive a peer conversation", self.address()), || async {
+            let conversations = self.get_json("/peers").await?;
+            conversations
+                .as_object()?
+                .values()
+                .find(|conversation| {
+                    conversation["messages"]
+                        .as_array()
+                        .is_some_and(|messages| messages.len() >= min_messages)
+                })
+                .cloned()
+        })
+        .await
+    }
+
+    pub async fn stop(self) -> std::io::Result<()> {
+        self.node.stop();
+        tokio::time::timeout(WAIT_TIMEOUT, self.task)
+            .await
+            .expect("node did not stop in time")
+            .expect("node task panicked")
+    }
+
+    // Stops the node and starts it again with the same configuration and storage
+    pub async fn restart(self) -> TestNode {
+        self.node.stop();
+        tokio::time::timeout(WAIT_TIMEOUT, self.task)
+            .await
+            .expect("node did not stop in time")
+            .expect("node task panicked")
+            .expect("node failed while stopping");
+        TestNode::launch(self.node.config().clone(), self._dir).await
+    }
+}
+
+// A fully connected mesh of `count` nodes, each seeded with every node started before it
+pub async fn start_mesh(count: usize) -> Vec<TestNode> {
+    let mut nodes: Vec<TestNode> = Vec::with_capacity(count);
+    for _ in 0..count {
+        let seeds = nodes.iter().map(|node| node.tcp_port).collect();
+        nodes.push(TestNode::start(NodeOptions { seeds, ..Default::default() }).await);
+    }
+    for node in &nodes {
+        for other in &nodes {
+            if node.tcp_port != other.tcp_port {
+                node.wait_for_connection(other).await;
+            }
+        }
+    }
+    nodes
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use common::{NodeOptions, TestNode};
+use neuromesh::cli::Cli;
+use neuromesh::config::{Config, ConfigArgs};
+
+fn write_config(dir: &Path, content: &str) -> PathBuf {
+    let path = dir.join("neuromesh.toml");
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+fn load_file(content: &str) -> Result<Config, String> {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Some(write_config(dir.path(), content));
+    Config::load(&ConfigArgs { config, ..Default::default() })
+}
+
+// The only test here that reads the environment, so no other sees these variables
+#[test]
+fn flags_override_the_environment_which_overrides_the_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = write_config(dir.path(), r#"
+        [http]
+        port = 9000
+        [tcp]
+        port = 9001
+        sync_interval_secs = 7
+        [llm]
+        model = "from-file"
+        remote_timeout_secs = 30
+        [peers]
+        seeds = ["10.0.0.1"]
+    "#);
+    std::env::set_var("NEUROMESH_CONFIG", &path);
+    std::env::set_var("NEUROMESH_MODEL", "from-env");
+    std::env::set_var("NEUROMESH_HTTP_PORT", "9100");
+    let cli = Cli::try_parse_from(["neuromesh", "--model", "from-flag", "--peer", "10.0.0.2:9999"]).unwrap();
+    for name in ["NEUROMESH_CONFIG", "NEUROMESH_MODEL", "NEUROMESH_HTTP_PORT"] {
+        std::env::remove_var(name);
+    }
+    let config = Config::load(&cli.serve.config).unwrap();
+
+    assert_eq!(config.llm.model, "from-flag");
+    assert_eq!(config.http.port, 9100);
+    assert_eq!(config.tcp.port, 9001);
+    assert_eq!(config.tcp.sync_interval_secs, 7);
+    assert_eq!(config.llm.remote_timeout_secs, 30);
+    // Untouched by the file, so still the default
+    let defaults = Config::default();
+    assert_eq!(config.udp.broadcast_port, defaults.udp.broadcast_port);
+    assert_eq!(config.documents.embedding_model, defaults.documents.embedding_model);
+    // Seeds given as flags are added to the file's
+    assert_eq!(config.peers.seeds, ["10.0.0.1", "10.0.0.2:9999"]);
+}
+
+#[test]
+fn invalid_settings_are_rejected() {
+    let cases = [
+        ("[llm]\nremote_timeout_secs = 0", "Remote LLM timeout must be at least 1 second"),
+        ("[tcp]\nsync_interval_secs = 0", "Sync and broadcast intervals must be at least 1 second"),
+        ("[http]\nport = 9000\n[tcp]\nport = 9000", "HTTP and TCP ports must differ"),
+        ("[ollama]\nurl = \"not a url\"", "Invalid Ollama URL"),
+        ("[documents]\nchunk_chars = 100\nchunk_overlap = 100", "Document chunks must be longer than their overlap"),
+        ("[peers]\nseeds = [\"10.0.0.1:0\"]", "Invalid peer address"),
+        ("[llm]\nmodle = \"typo\"", "Invalid config file"),
+    ];
+    for (content, expected) in cases {
+        let error = load_file(content).unwrap_err();
+        assert!(error.contains(expected), "{:?} gave {:?}", content, error);
+    }
+
+    // A flag is checked like the file it overrides
+    let dir = tempfile::tempdir().unwrap();
+    let config = Some(write_config(dir.path(), "[llm]\nremote_timeout_secs = 30"));
+    let error = Config::load(&ConfigArgs { config, remote_timeout: Some(0), ..Default::default() }).unwrap_err();
+    assert_eq!(error, "Remote LLM timeout must be at least 1 second");
+
+    let error = Config::load(&ConfigArgs { config: Some(dir.path().join("missing.toml")), ..Default::default() }).unwrap_err();
+    assert!(error.starts_with("Failed to read config file"), "{}", error);
+}
+
+#[actix_web::test]
+async fn the_operator_sees_the_merged_config_without_secrets() {
+    let node = TestNode::start(NodeOptions {
+        configure: |config| {
+            config.http.admin_token = Some("operator-secret".to_string());
+            config.openai.api_key = Some("server-key".to_string());
+            config.llm.model = "configured-model".to_string();
+        },
+        ..Default::default()
+    })
+    .await;
+
+    let config = node.get_json("/api/config").await.unwrap();
+    assert_eq!(config["llm"]["model"], "configured-model");
+    assert_eq!(config["http"]["port"], node.http_port);
+    assert_eq!(config["tcp"]["port"], node.tcp_port);
+    assert_eq!(config["documents"]["embedding_model"], common::FAKE_MODEL);
+    assert!(config["http"].get("admin_token").is_none(), "{}", config);
+    assert!(config["openai"].get("api_key").is_none(), "{}", config);
+
+    // From another machine it takes the operator's token
+    let rebound = node.client.get(node.url("/api/config")).header("Host", "example.com").send().await.unwrap();
+    assert_eq!(rebound.status(), 403);
+    let authorized = node.client
+        .get(node.url("/api/config"))
+        .header("Host", "example.com")
+        .bearer_auth("operator-secret")
+        .send()
+        .await
+        .unwrap();
+    assert!(authorized.status().is_success());
+    assert_eq!(authorized.json::<serde_json::Value>().await.unwrap(), config);
+
+    node.stop().await.unwrap();
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use common::{FakeLlm, NodeOptions, TestNode, FAKE_REPLY};
+use serde_json::{json, Value};
+
+const ZEBRAS: &str = "Zebras have black and white stripes. The stripes of every zebra are unique.";
+const APPLES: &str = "Apple orchards grow red and green apples, picked in the autumn.";
+
+#[actix_web::test]
+async fn documents_are_indexed_listed_and_deleted() {
+    let llm = FakeLlm::start().await;
+    let node = TestNode::start(NodeOptions { llm_url: Some(llm.url()), ..Default::default() }).await;
+
+    let response = node
+        .upload_documents(&[("zebras.txt", ZEBRAS.as_bytes()), ("notes/apples.md", APPLES.as_bytes())])
+        .await;
+    assert!(response.status().is_success());
+    let uploaded: Vec<Value> = response.json().await.unwrap();
+    assert_eq!(uploaded.len(), 2);
+    // Only the file name is kept, never the client's directories
+    assert_eq!(uploaded[1]["name"], "apples.md");
+    assert!(uploaded.iter().all(|document| document["chunks"].as_u64().unwrap() >= 1));
+
+    // The same content again is the same document
+    let response = node.upload_documents(&[("zebras-copy.txt", ZEBRAS.as_bytes())]).await;
+    assert!(response.status().is_success());
+    let documents = node.get_json("/api/documents").await.unwrap();
+    assert_eq!(documents.as_array().unwrap().len(), 2);
+
+    let id = uploaded[0]["id"].as_str().unwrap();
+    let response = node.client.delete(node.url(&format!("/api/documents/{}", id))).send().await.unwrap();
+    assert_eq!(response.status(), 204);
+    let response = node.client.delete(node.url(&format!("/api/documents/{}", id))).send().await.unwrap();
+    assert_eq!(response.status(), 404);
+    let documents = node.get_json("/api/documents").await.unwrap();
+    assert_eq!(documents.as_array().unwrap().len(), 1);
+
+    node.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn binary_uploads_are_rejected() {
+    let llm = FakeLlm::start().await;
+    let node = TestNode::start(NodeOptions { llm_url: Some(llm.url()), ..Default::default() }).await;
+
+    let response = node.upload_documents(&[("image.png", &[0x89, b'P', b'N', b'G', 0xff, 0xfe])]).await;
+    assert_eq!(response.status(), 415);
+    assert_eq!(llm.embed_requests(), 0);
+
+    node.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn only_the_operator_may_upload_documents() {
+    let llm = FakeLlm::start().await;
+    let node = TestNode::start(NodeOptions { llm_url: Some(llm.url()), ..Default::default() }).await;
+
+    let form = reqwest::multipart::Form::new()
+        .part("file", reqwest::multipart::Part::bytes(ZEBRAS.as_bytes().to_vec()).file_name("zebras.txt"));
+    let response = node.client.post(node.url("/api/documents")).header("Host", "example.com").multipart(form).send().await.unwrap();
+    assert_eq!(response.status(), 403);
+    let form = reqwest::multipart::Form::new()
+        .part("file", reqwest::multipart::Part::bytes(ZEBRAS.as_bytes().to_vec()).file_name("zebras.txt"));
+    let response = node.client.post(node.url("/api/documents")).header("Origin", "http://example.com").multipart(form).send().await.unwrap();
+    assert!(response.status().is_client_error());
+    assert_eq!(llm.embed_requests(), 0);
+    assert_eq!(node.get_json("/api/documents").await.unwrap(), json!([]));
+
+    node.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn chat_answers_from_documents_with_citations() {
+    let llm = FakeLlm::start().await;
+    let node = TestNode::start(NodeOptions { llm_url: Some(llm.url()), ..Default::default() }).await;
+    let response = node
+        .upload_documents(&[("zebras.txt", ZEBRAS.as_bytes()), ("apples.txt", APPLES.as_bytes())])
+        .await;
+    assert!(response.status().is_success());
+
+    let question = "What stripes do zebras have?";
+    let response = node
+        .post_json("/api/chat", json!({ "message": question, "sender": "tester", "use_documents": true }))
+        .await;
+    assert!(response.status().is_success());
+    let message: Value = response.json().await.unwrap();
+    assert_eq!(message["content"], FAKE_REPLY.concat());
+    assert_eq!(message["citations"][0]["document_name"], "zebras.txt");
+
+    // The excerpts went to the model ahead of the question
+    let prompt = llm.last_chat().unwrap();
+    let messages = prompt["messages"].as_array().unwrap();
+    assert_eq!(messages[0]["role"], "system");
+    assert!(messages[0]["content"].as_str().unwrap().contains("[1] zebras.txt"));
+    assert_eq!(messages.last().unwrap()["content"], question);
+
+    // Streaming answers carry the citations on their final chunk
+    let response = node
+        .post_json("/api/chat/stream", json!({ "message": question, "sender": "tester", "use_documents": true }))
+        .await;
+    let body = response.text().await.unwrap();
+    let last: Value = serde_json::from_str(body.lines().last().unwrap()).unwrap();
+    assert_eq!(last["done"], true);
+    assert_eq!(last["citations"][0]["document_name"], "zebras.txt");
+
+    // Without the flag the model sees the question alone
+    node.post_json("/api/chat", json!({ "message": question, "sender": "tester" })).await;
+    let prompt = llm.last_chat().unwrap();
+    assert_eq!(prompt["messages"].as_array().unwrap().len(), 1);
+
+    node.stop().await.unwrap();
+    llm.stop().await;
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::{fake_embedding, FakeLlm, NodeOptions, TestNode, FAKE_MODEL};
+use serde_json::{json, Value};
+
+#[actix_web::test]
+async fn large_batches_are_split_across_hosts_in_order() {
+    let first_llm = FakeLlm::start().await;
+    let second_llm = FakeLlm::start().await;
+    let first = TestNode::start(NodeOptions { llm_url: Some(first_llm.url()), ..Default::default() }).await;
+    let second = TestNode::start(NodeOptions { llm_url: Some(second_llm.url()), ..Default::default() }).await;
+    let client = TestNode::start(NodeOptions {
+        seeds: vec![first.tcp_port, second.tcp_port],
+        ..Default::default()
+    })
+    .await;
+    client.wait_for_remote_models(2).await;
+
+    // Distinct lengths and first letters, so any reordering changes the vectors
+    let inputs: Vec<String> = (0..100)
+        .map(|i| format!("{}{}", (b'a' + (i % 26) as u8) as char, "x".repeat(i)))
+        .collect();
+    let response = client
+        .post_json("/api/embed", json!({ "model": FAKE_MODEL, "input": inputs }))
+        .await;
+    assert!(response.status().is_success());
+    let body: Value = response.json().await.unwrap();
+    let embeddings = body["embeddings"].as_array().unwrap();
+    assert_eq!(embeddings.len(), inputs.len());
+    for (input, embedding) in inputs.iter().zip(embeddings) {
+        assert_eq!(embedding, &json!(fake_embedding(input)));
+    }
+
+    // Both hosts did part of the work
+    assert!(first_llm.embed_requests() > 0);
+    assert!(second_llm.embed_requests() > 0);
+
+    client.stop().await.unwrap();
+    first.stop().await.unwrap();
+    second.stop().await.unwrap();
+    first_llm.stop().await;
+    second_llm.stop().await;
+}
+
+#[actix_web::test]
+async fn host_that_stopped_answering_gets_no_batches() {
+    let dead_llm = FakeLlm::start().await;
+    let live_llm = FakeLlm::start().await;
+    let dead = TestNode::start(NodeOptions { llm_url: Some(dead_llm.url()), ..Default::default() }).await;
+    let live = TestNode::start(NodeOptions { llm_url: Some(live_llm.url()), ..Default::default() }).await;
+    let client = TestNode::start(NodeOptions {
+        seeds: vec![dead.tcp_port, live.tcp_port],
+        ..Default::default()
+    })
+    .await;
+    client.wait_for_remote_models(2).await;
+
+    // Gone after the client was granted access; the live host takes every batch
+    dead_llm.stop().await;
+
+    let inputs: Vec<String> = (0..70).map(|i| format!("text {}", i)).collect();
+    let response = client
+        .post_json("/api/embed", json!({ "model": FAKE_MODEL, "input": inputs }))
+        .await;
+    assert!(response.status().is_success());
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["embeddings"].as_array().unwrap().len(), inputs.len());
+
+    client.stop().await.unwrap();
+    dead.stop().await.unwrap();
+    live.stop().await.unwrap();
+    live_llm.stop().await;
+}
+
+#[actix_web::test]
+async fn unknown_embedding_model_is_not_found() {
+    let client = TestNode::start(NodeOptions::default()).await;
+
+    let response = client
+        .post_json("/api/embed", json!({ "model": "no-such-model", "input": ["text"] }))
+        .await;
+    assert_eq!(response.status(), 404);
+
+    client.stop().await.unwrap();
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use std::time::Duration;
+use common::{FakeLlm, NodeOptions, TestNode, FAKE_REPLY, WAIT_TIMEOUT};
+use futures::StreamExt;
+use serde_json::{json, Value};
+
+// Reads Server-Sent Events from /api/events
+struct EventStream {
+    body: futures::stream::BoxStream<'static, reqwest::Result<actix_web::web::Bytes>>,
+    buffer: String,
+}
+
+impl EventStream {
+    async fn open(node: &TestNode, query: &str) -> EventStream {
+        let response = node.client.get(node.url(&format!("/api/events{}", query))).send().await.unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        EventStream { body: response.bytes_stream().boxed(), buffer: String::new() }
+    }
+
+    // The next event, skipping keep-alive comments
+    async fn next(&mut self) -> (String, Value) {
+        loop {
+            if let Some(end) = self.buffer.find("\n\n") {
+                let frame: String = self.buffer.drain(..end + 2).collect();
+                let mut kind = None;
+                let mut data = None;
+                for line in frame.lines() {
+                    if let Some(value) = line.strip_prefix("event: ") {
+                        kind = Some(value.to_string());
+                    } else if let Some(value) = line.strip_prefix("data: ") {
+                        data = Some(serde_json::from_str(value).unwrap());
+                    }
+                }
+                if let (Some(kind), Some(data)) = (kind, data) {
+                    return (kind, data);
+                }
+                continue;
+            }
+            let chunk = tokio::time::timeout(WAIT_TIMEOUT, self.body.next())
+                .await
+                .expect("timed out waiting for an event")
+                .expect("event stream ended")
+                .unwrap();
+            self.buffer.push_str(&String::from_utf8_lossy(&chunk));
+        }
+    }
+
+    async fn next_of(&mut self, kind: &str) -> Value {
+        loop {
+            let (received, data) = self.next().await;
+            if received == kind {
+                return data;
+            }
+        }
+    }
+}
+
+#[actix_web::test]
+async fn peer_and_chat_events_are_pushed() {
+    let llm = FakeLlm::start().await;
+    let host = TestNode::start(NodeOptions { llm_url: Some(llm.url()), ..Default::default() }).await;
+    let mut host_events = EventStream::open(&host, "").await;
+
+    let client = TestNode::start(NodeOptions { seeds: vec![host.tcp_port], ..Default::default() }).await;
+    let client_id = client.node.node_id().to_string();
+    let connected = host_events.next_of("peer_connected").await;
+    assert_eq!(connected["node_id"], client_id);
+    let requested = host_events.next_of("access_requested").await;
+    assert_eq!(requested["direction"], "received");
+    assert_eq!(requested["pending"], false);
+    let decided = host_events.next_of("access_decided").await;
+    assert_eq!(decided["granted"], true);
+
+    client.wait_for_llm_access().await;
+    let mut client_events = EventStream::open(&client, "?types=message_added,inference_started,inference_finished").await;
+    let response = client
+        .post_json("/api/chat", json!({ "message": "Say hello", "sender": "tester" }))
+        .await;
+    assert!(response.status().is_success());
+
+    let (kind, question) = client_events.next().await;
+    assert_eq!(kind, "message_added");
+    assert_eq!(question["conversation"], "local");
+    assert_eq!(question["message"]["content"], "Say hello");
+    let (kind, started) = client_events.next().await;
+    assert_eq!(kind, "inference_started");
+    assert_eq!(started["host"], "remote");
+    let (kind, finished) = client_events.next().await;
+    assert_eq!(kind, "inference_finished");
+    assert_eq!(finished["id"], started["id"]);
+    assert_eq!(finished["outcome"], "ok");
+    assert_eq!(finished["tokens"], FAKE_REPLY.len());
+    let (kind, answer) = client_events.next().await;
+    assert_eq!(kind, "message_added");
+    assert_eq!(answer["message"]["content"], FAKE_REPLY.concat());
+    assert!(answer["seq"].as_u64() > question["seq"].as_u64());
+
+    // The next share carries the new messages to the host
+    loop {
+        let synced = host_events.next_of("sync_completed").await;
+        if synced["direction"] == "received" && synced["messages"] == 2 {
+            break;
+        }
+    }
+
+    client.stop().await.unwrap();
+    let lost = host_events.next_of("peer_lost").await;
+    assert_eq!(lost["node_id"], client_id);
+
+    // Subscribers are let go when the node stops, so shutdown is not held up
+    let stopped = tokio::time::timeout(Duration::from_secs(5), host.stop()).await;
+    assert!(stopped.expect("node did not stop with a subscriber attached").is_ok());
+    llm.stop().await;
+}
@@ -0,0 +1,180 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{wait_for, FakeLlm, NodeOptions, TestNode, FAKE_MODEL, FAKE_REPLY, WAIT_TIMEOUT};
+use futures::StreamExt;
+use neuromesh::config::BackendKind;
+use serde_json::{json, Value};
+
+// Both nodes offer the fake model, so either can answer
+fn options(llm: &FakeLlm, seeds: Vec<u16>) -> NodeOptions {
+    NodeOptions {
+        llm_url: Some(llm.url()),
+        seeds,
+        configure: |config| config.llm.model = FAKE_MODEL.to_string(),
+        ..Default::default()
+    }
+}
+
+#[actix_web::test]
+async fn an_answer_carries_on_on_another_host_when_its_host_fails() {
+    let local_llm = FakeLlm::start().await;
+    local_llm.slow_down(Duration::from_secs(1));
+    let peer_llm = FakeLlm::start().await;
+    let peer = TestNode::start(options(&peer_llm, Vec::new())).await;
+    let node = TestNode::start(options(&local_llm, vec![peer.tcp_port])).await;
+    node.wait_for_llm_access().await;
+
+    let events = node.client.get(node.url("/api/events?types=inference_failover")).send().await.unwrap();
+    let mut events = events.bytes_stream();
+
+    let response = node.post_json("/api/chat/stream", json!({ "message": "Say hello", "sender": "tester" })).await;
+    assert!(response.status().is_success());
+    let mut body = response.bytes_stream();
+    let mut buffer = String::new();
+    let mut chunks: Vec<Value> = Vec::new();
+    let mut llm_stopped = false;
+    while chunks.last().is_none_or(|chunk| chunk["done"] != true) {
+        let bytes = tokio::time::timeout(WAIT_TIMEOUT, body.next())
+            .await
+            .expect("timed out waiting for the answer")
+            .expect("answer ended early")
+            .unwrap();
+        buffer.push_str(&String::from_utf8_lossy(&bytes));
+        while let Some(end) = buffer.find('\n') {
+            let line: String = buffer.drain(..=end).collect();
+            chunks.push(serde_json::from_str(&line).unwrap());
+        }
+        // The local LLM server goes away after the first piece of its answer
+        if !llm_stopped && chunks.iter().any(|chunk| chunk["content"] != "") {
+            local_llm.stop().await;
+            llm_stopped = true;
+        }
+    }
+
+    let last = chunks.last().unwrap();
+    assert!(last.get("error").is_none_or(Value::is_null), "answer failed: {}", last);
+    let notice = chunks.iter().find_map(|chunk| chunk.get("failover")).expect("no failover notice");
+    assert_eq!(notice["from"], "local");
+    assert_eq!(notice["to"], peer.node.node_id());
+
+    // The peer was handed the first piece as the start of its reply
+    let resumed = peer_llm.last_chat().unwrap();
+    let messages = resumed["messages"].as_array().unwrap();
+    assert_eq!(messages.last().unwrap(), &json!({ "role": "assistant", "content": FAKE_REPLY[0] }));
+    let answer: String = chunks.iter().map(|chunk| chunk["content"].as_str().unwrap_or_default()).collect();
+    assert_eq!(answer, format!("{}{}", FAKE_REPLY[0], FAKE_REPLY.concat()));
+
+    // Past any keep-alive comments
+    let mut seen = String::new();
+    while !seen.contains("event: ") || !seen.ends_with("\n\n") {
+        let bytes = tokio::time::timeout(WAIT_TIMEOUT, events.next())
+            .await
+            .expect("timed out waiting for the failover event")
+            .expect("event stream ended")
+            .unwrap();
+        seen.push_str(&String::from_utf8_lossy(&bytes));
+    }
+    assert!(seen.contains("event: inference_failover\n"), "unexpected event: {}", seen);
+    assert!(seen.contains("\"resumed_after_chars\":5"), "unexpected event: {}", seen);
+
+    node.stop().await.unwrap();
+    peer.stop().await.unwrap();
+    peer_llm.stop().await;
+}
+
+#[actix_web::test]
+async fn an_answer_moves_on_when_its_host_goes_quiet() {
+    // Skipped as the first choice, but still there to take the answer over
+    let local_llm = FakeLlm::start().await;
+    local_llm.fail_health_checks();
+    let peer_llm = FakeLlm::start().await;
+    peer_llm.stall_after(1);
+    let peer = TestNode::start(options(&peer_llm, Vec::new())).await;
+    let node = TestNode::start(NodeOptions {
+        configure: |config| {
+            config.llm.model = FAKE_MODEL.to_string();
+            config.llm.remote_timeout_secs = 2;
+        },
+        ..options(&local_llm, vec![peer.tcp_port])
+    })
+    .await;
+    node.wait_for_llm_access().await;
+
+    let response = node.post_json("/api/chat/stream", json!({ "message": "Say hello", "sender": "tester" })).await;
+    assert!(response.status().is_success());
+    let body = tokio::time::timeout(WAIT_TIMEOUT, response.text()).await.expect("the answer hung").unwrap();
+    let chunks: Vec<Value> = body.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let last = chunks.last().unwrap();
+    assert!(last.get("error").is_none_or(Value::is_null), "answer failed: {}", last);
+    let notice = chunks.iter().find_map(|chunk| chunk.get("failover")).expect("no failover notice");
+    assert_eq!(notice["from"], peer.node.node_id());
+    assert_eq!(notice["to"], "local");
+    let answer: String = chunks.iter().map(|chunk| chunk["content"].as_str().unwrap_or_default()).collect();
+    assert_eq!(answer, format!("{}{}", FAKE_REPLY[0], FAKE_REPLY.concat()));
+    // The quiet host was told to stop
+    wait_for("the peer to drop the request", || async {
+        let running = peer.get_json("/api/requests").await?;
+        running.as_array()?.is_empty().then_some(())
+    })
+    .await;
+
+    node.stop().await.unwrap();
+    peer.stop().await.unwrap();
+    local_llm.stop().await;
+    peer_llm.stop().await;
+}
+
+#[actix_web::test]
+async fn an_answer_is_not_handed_to_a_server_that_cannot_continue_it() {
+    let local_llm = FakeLlm::start().await;
+    local_llm.slow_down(Duration::from_secs(1));
+    let peer_llm = FakeLlm::start().await;
+    let peer = TestNode::start(NodeOptions { backend: BackendKind::OpenAi, ..options(&peer_llm, Vec::new()) }).await;
+    let node = TestNode::start(options(&local_llm, vec![peer.tcp_port])).await;
+    node.wait_for_llm_access().await;
+
+    let response = node.post_json("/api/chat/stream", json!({ "message": "Say hello", "sender": "tester" })).await;
+    assert!(response.status().is_success());
+    let mut body = response.bytes_stream();
+    let first = tokio::time::timeout(WAIT_TIMEOUT, body.next()).await.unwrap().unwrap().unwrap();
+    assert!(String::from_utf8_lossy(&first).contains(FAKE_REPLY[0]));
+    local_llm.stop().await;
+
+    let mut rest = String::new();
+    while let Some(bytes) = tokio::time::timeout(WAIT_TIMEOUT, body.next()).await.unwrap() {
+        rest.push_str(&String::from_utf8_lossy(&bytes.unwrap()));
+    }
+    let last: Value = serde_json::from_str(rest.lines().last().unwrap()).unwrap();
+    assert!(last["error"].as_str().unwrap().contains("can continue the answer"), "unexpected error: {}", last);
+    assert_eq!(peer_llm.chat_requests(), 0);
+
+    node.stop().await.unwrap();
+    peer.stop().await.unwrap();
+    peer_llm.stop().await;
+}
+
+#[actix_web::test]
+async fn an_answer_with_nowhere_else_to_go_ends_with_an_error() {
+    let llm = FakeLlm::start().await;
+    llm.slow_down(Duration::from_secs(1));
+    let node = TestNode::start(options(&llm, Vec::new())).await;
+
+    let response = node.post_json("/api/chat/stream", json!({ "message": "Say hello", "sender": "tester" })).await;
+    assert!(response.status().is_success());
+    let mut body = response.bytes_stream();
+    let first = tokio::time::timeout(WAIT_TIMEOUT, body.next()).await.unwrap().unwrap().unwrap();
+    assert!(String::from_utf8_lossy(&first).contains(FAKE_REPLY[0]));
+    llm.stop().await;
+
+    let mut rest = String::new();
+    while let Some(bytes) = tokio::time::timeout(WAIT_TIMEOUT, body.next()).await.unwrap() {
+        rest.push_str(&String::from_utf8_lossy(&bytes.unwrap()));
+    }
+    let last: Value = serde_json::from_str(rest.lines().last().unwrap()).unwrap();
+    assert_eq!(last["done"], true);
+    assert!(last["error"].as_str().unwrap().contains("no other host has"), "unexpected error: {}", last);
+
+    node.stop().await.unwrap();
+}
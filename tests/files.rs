@@ -0,0 +1,150 @@
+mod common;
+
+use common::{wait_for, NodeOptions, TestNode};
+use serde_json::Value;
+
+// A bit over two chunks, so transfers cover a short last chunk
+const FILE_BYTES: usize = 600 * 1024;
+const CHUNK_BYTES: usize = 256 * 1024;
+
+fn content(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 % 251) as u8).collect()
+}
+
+async fn offer(node: &TestNode, name: &str, data: &[u8]) -> String {
+    let response = node.offer_files(&[(name, data)]).await;
+    assert!(response.status().is_success());
+    let offered: Vec<Value> = response.json().await.unwrap();
+    offered[0]["hash"].as_str().unwrap().to_string()
+}
+
+async fn wait_for_offer(node: &TestNode, hash: &str) {
+    wait_for(&format!("{} to see the offer of {}", node.address(), hash), || async {
+        let files = node.get_json("/api/files").await?;
+        files["offers"].as_array()?.iter().any(|offer| offer["hash"] == hash).then_some(())
+    })
+    .await;
+}
+
+async fn wait_for_shared(node: &TestNode, hash: &str) -> Value {
+    wait_for(&format!("{} to have {}", node.address(), hash), || async {
+        let files = node.get_json("/api/files").await?;
+        files["files"].as_array()?.iter().find(|file| file["hash"] == hash).cloned()
+    })
+    .await
+}
+
+async fn fetch(node: &TestNode, hash: &str) -> reqwest::Response {
+    node.client
+        .post(node.url(&format!("/api/files/{}/fetch", hash)))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn download(node: &TestNode, hash: &str) -> Vec<u8> {
+    let response = node.client.get(node.url(&format!("/api/files/{}", hash))).send().await.unwrap();
+    assert!(response.status().is_success());
+    response.bytes().await.unwrap().to_vec()
+}
+
+#[actix_web::test]
+async fn files_are_offered_and_fetched_by_hash() {
+    let owner = TestNode::start(NodeOptions::default()).await;
+    let data = content(FILE_BYTES);
+    let hash = offer(&owner, "dataset.bin", &data).await;
+
+    // Offers already made reach peers that connect later
+    let fetcher = TestNode::start(NodeOptions { seeds: vec![owner.tcp_port], ..Default::default() }).await;
+    fetcher.wait_for_connection(&owner).await;
+    wait_for_offer(&fetcher, &hash).await;
+
+    let response = fetch(&fetcher, &hash).await;
+    assert_eq!(response.status(), 202);
+    let file = wait_for_shared(&fetcher, &hash).await;
+    assert_eq!(file["name"], "dataset.bin");
+    assert_eq!(file["origin"], owner.node.node_id());
+    assert_eq!(download(&fetcher, &hash).await, data);
+
+    // Already here, so there is nothing left to fetch
+    let response = fetch(&fetcher, &hash).await;
+    assert_eq!(response.status(), 200);
+
+    fetcher.stop().await.unwrap();
+    owner.stop().await.unwrap();
+}
+
+#[actix_web::test]
+async fn interrupted_download_resumes_from_verified_chunks() {
+    let owner = TestNode::start(NodeOptions::default()).await;
+    let fetcher = TestNode::start(NodeOptions { seeds: vec![owner.tcp_port], ..Default::default() }).await;
+    fetcher.wait_for_connection(&owner).await;
+
+    let data = content(FILE_BYTES);
+    let hash = offer(&owner, "dataset.bin", &data).await;
+    wait_for_offer(&fetcher, &hash).await;
+
+    // One good chunk followed by a torn write, as left by a crash mid-download
+    let mut partial = data[..CHUNK_BYTES].to_vec();
+    partial.extend_from_slice(&[0xff; 1000]);
+    let partial_path = fetcher.node.config().storage.shared_dir.join("partial").join(format!("{}.part", hash));
+    std::fs::write(&partial_path, partial).unwrap();
+
+    let response = fetch(&fetcher, &hash).await;
+    assert_eq!(response.status(), 202);
+    wait_for_shared(&fetcher, &hash).await;
+    assert_eq!(download(&fetcher, &hash).await, data);
+    assert!(!partial_path.exists());
+
+    fetcher.stop().await.unwrap();
+    owner.stop().await.unwrap();
+}
+
+#[actix_web::test]
+async fn fetches_beyond_a_peer_quota_are_refused() {
+    let owner = TestNode::start(NodeOptions::default()).await;
+    let fetcher = TestNode::start(NodeOptions {
+        seeds: vec![owner.tcp_port],
+        configure: |config| config.sharing.peer_quota_mb = 1,
+        ..Default::default()
+    })
+    .await;
+    fetcher.wait_for_connection(&owner).await;
+
+    let hash = offer(&owner, "large.bin", &content(1536 * 1024)).await;
+    wait_for_offer(&fetcher, &hash).await;
+
+    let response = fetch(&fetcher, &hash).await;
+    assert_eq!(response.status(), 507);
+    let files = fetcher.get_json("/api/files").await.unwrap();
+    assert!(files["downloads"].as_array().unwrap().is_empty());
+
+    fetcher.stop().await.unwrap();
+    owner.stop().await.unwrap();
+}
+
+#[actix_web::test]
+async fn deleting_a_file_withdraws_the_offer() {
+    let owner = TestNode::start(NodeOptions::default()).await;
+    let fetcher = TestNode::start(NodeOptions { seeds: vec![owner.tcp_port], ..Default::default() }).await;
+    fetcher.wait_for_connection(&owner).await;
+
+    let hash = offer(&owner, "notes.txt", b"shared notes").await;
+    wait_for_offer(&fetcher, &hash).await;
+
+    let url = owner.url(&format!("/api/files/{}", hash));
+    assert_eq!(owner.client.delete(&url).send().await.unwrap().status(), 204);
+    assert_eq!(owner.client.delete(&url).send().await.unwrap().status(), 404);
+    wait_for("the offer to be withdrawn", || async {
+        let files = fetcher.get_json("/api/files").await?;
+        files["offers"].as_array()?.is_empty().then_some(())
+    })
+    .await;
+
+    // Paths never come from anything but a well-formed hash
+    let response = owner.client.get(owner.url("/api/files/..%2Findex.json")).send().await.unwrap();
+    assert_eq!(response.status(), 400);
+
+    fetcher.stop().await.unwrap();
+    owner.stop().await.unwrap();
+}
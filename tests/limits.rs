@@ -0,0 +1,110 @@
+mod common;
+
+use common::{wait_for, FakeLlm, NodeOptions, TestNode, FAKE_REPLY};
+use serde_json::{json, Value};
+
+// Answer tokens the fake server reports for every chat
+const TOKENS_PER_CHAT: usize = FAKE_REPLY.len();
+// Where every test node connects from
+const PEER_IP: &str = "127.0.0.1";
+
+async fn ask(node: &TestNode) -> reqwest::Response {
+    node.post_json("/api/chat", json!({ "message": "Say hello", "sender": "tester" })).await
+}
+
+async fn peer_usage(host: &TestNode, ip: &str) -> Value {
+    let usage = host.get_json("/api/limits").await.unwrap();
+    usage.as_array().unwrap().iter().find(|peer| peer["ip"] == ip).cloned().unwrap()
+}
+
+#[actix_web::test]
+async fn host_refuses_requests_over_the_per_minute_limit() {
+    let llm = FakeLlm::start().await;
+    let host = TestNode::start(NodeOptions {
+        llm_url: Some(llm.url()),
+        configure: |config| config.limits.requests_per_minute = 1,
+        ..Default::default()
+    })
+    .await;
+    let client = TestNode::start(NodeOptions { seeds: vec![host.tcp_port], ..Default::default() }).await;
+    client.wait_for_llm_access().await;
+
+    assert!(ask(&client).await.status().is_success());
+
+    let refused = ask(&client).await;
+    assert_eq!(refused.status(), 429);
+    let retry_after: u64 = refused.headers()["retry-after"].to_str().unwrap().parse().unwrap();
+    assert!((1..=60).contains(&retry_after));
+    let body: Value = refused.json().await.unwrap();
+    assert_eq!(body["error"], "Quota exceeded");
+    assert_eq!(body["retry_after_secs"], retry_after);
+    assert_eq!(llm.chat_requests(), 1);
+
+    let usage = peer_usage(&host, PEER_IP).await;
+    assert_eq!(usage["requests_last_minute"], 1);
+    assert_eq!(usage["in_flight"], 0);
+    assert_eq!(usage["tokens_today"], TOKENS_PER_CHAT);
+    assert_eq!(usage["limits"]["requests_per_minute"], 1);
+
+    client.stop().await.unwrap();
+    host.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn daily_token_usage_survives_a_restart() {
+    let llm = FakeLlm::start().await;
+    let host = TestNode::start(NodeOptions {
+        llm_url: Some(llm.url()),
+        configure: |config| config.limits.tokens_per_day = TOKENS_PER_CHAT as u64,
+        ..Default::default()
+    })
+    .await;
+    let client = TestNode::start(NodeOptions { seeds: vec![host.tcp_port], ..Default::default() }).await;
+    client.wait_for_llm_access().await;
+    assert!(ask(&client).await.status().is_success());
+
+    // The restarted host still counts today's tokens, so the client is out of budget
+    let host = host.restart().await;
+    let refused = wait_for("the restarted host to refuse the client", || async {
+        let response = ask(&client).await;
+        assert!(!response.status().is_success(), "the restarted host forgot the client's usage");
+        (response.status() == 429).then_some(response)
+    })
+    .await;
+    let body: Value = refused.json().await.unwrap();
+    assert_eq!(body["error"], "Quota exceeded");
+    assert_eq!(llm.chat_requests(), 1);
+    assert_eq!(peer_usage(&host, PEER_IP).await["tokens_today"], TOKENS_PER_CHAT);
+
+    client.stop().await.unwrap();
+    host.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn a_new_node_id_from_the_same_address_does_not_reset_usage() {
+    let llm = FakeLlm::start().await;
+    let host = TestNode::start(NodeOptions {
+        llm_url: Some(llm.url()),
+        configure: |config| config.limits.requests_per_minute = 1,
+        ..Default::default()
+    })
+    .await;
+    let client = TestNode::start(NodeOptions { seeds: vec![host.tcp_port], ..Default::default() }).await;
+    client.wait_for_llm_access().await;
+    assert!(ask(&client).await.status().is_success());
+    let first_id = client.node.node_id().to_string();
+    client.stop().await.unwrap();
+
+    let renamed = TestNode::start(NodeOptions { seeds: vec![host.tcp_port], ..Default::default() }).await;
+    assert_ne!(renamed.node.node_id(), first_id);
+    renamed.wait_for_llm_access().await;
+    assert_eq!(ask(&renamed).await.status(), 429);
+    assert_eq!(llm.chat_requests(), 1);
+    assert_eq!(peer_usage(&host, PEER_IP).await["requests_last_minute"], 1);
+
+    renamed.stop().await.unwrap();
+    host.stop().await.unwrap();
+    llm.stop().await;
+}
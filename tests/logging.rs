@@ -0,0 +1,18 @@
+use neuromesh::logging;
+use neuromesh::Config;
+
+#[test]
+fn content_is_redacted_unless_enabled() {
+    let prompt = "my bank PIN is 1234";
+    assert_eq!(logging::content(prompt, false).to_string(), "<19 chars redacted>");
+    assert_eq!(logging::content(prompt, true).to_string(), "\"my bank PIN is 1234\"");
+}
+
+#[test]
+fn log_levels_are_validated() {
+    let mut config = Config::default();
+    config.logging.level = "info,neuromesh::tcp=debug".to_string();
+    assert!(config.validate().is_ok());
+    config.logging.level = "info,neuromesh::tcp=loud".to_string();
+    assert!(config.validate().is_err());
+}
@@ -0,0 +1,205 @@
+mod common;
+
+use common::{start_mesh, FakeLlm, NodeOptions, TestNode, FAKE_MODEL, FAKE_REPLY};
+use futures::StreamExt;
+use neuromesh::config::BackendKind;
+use serde_json::{json, Value};
+
+#[actix_web::test]
+async fn nodes_connect_through_seed_peers() {
+    let nodes = start_mesh(3).await;
+
+    // Exactly one session per pair, so every peer shows up once
+    for node in &nodes {
+        let connections = node.get_json("/api/connections").await.unwrap();
+        assert_eq!(connections.as_array().unwrap().len(), 2);
+    }
+
+    for node in nodes {
+        node.stop().await.unwrap();
+    }
+}
+
+#[actix_web::test]
+async fn chat_is_served_by_peer_with_llm() {
+    let llm = FakeLlm::start().await;
+    let host = TestNode::start(NodeOptions { llm_url: Some(llm.url()), ..Default::default() }).await;
+    let client = TestNode::start(NodeOptions { seeds: vec![host.tcp_port], ..Default::default() }).await;
+
+    client.wait_for_peer_known(&host).await;
+    client.wait_for_connection(&host).await;
+    client.wait_for_llm_access().await;
+
+    let response = client
+        .post_json("/api/chat", json!({ "message": "Say hello", "sender": "tester", "options": { "keep_alive": "-1" } }))
+        .await;
+    assert!(response.status().is_success());
+    let message: Value = response.json().await.unwrap();
+    assert_eq!(message["content"], FAKE_REPLY.concat());
+    assert_eq!(llm.chat_requests(), 1);
+    // How long the host keeps its models loaded is up to the host
+    assert!(llm.last_chat().unwrap().get("keep_alive").is_none());
+
+    client.stop().await.unwrap();
+    host.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn chat_stream_delivers_chunks_in_order() {
+    let llm = FakeLlm::start().await;
+    let host = TestNode::start(NodeOptions { llm_url: Some(llm.url()), ..Default::default() }).await;
+    let client = TestNode::start(NodeOptions { seeds: vec![host.tcp_port], ..Default::default() }).await;
+    client.wait_for_llm_access().await;
+
+    let response = client
+        .post_json("/api/chat/stream", json!({ "message": "Say hello", "sender": "tester" }))
+        .await;
+    assert!(response.status().is_success());
+
+    let mut body = Vec::new();
+    let mut stream = response.bytes_stream();
+    while let Some(bytes) = stream.next().await {
+        body.extend_from_slice(&bytes.unwrap());
+    }
+    let chunks: Vec<Value> = body
+        .split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice(line).unwrap())
+        .collect();
+
+    let content: String = chunks.iter().filter_map(|chunk| chunk["content"].as_str()).collect();
+    assert_eq!(content, FAKE_REPLY.concat());
+    let last = chunks.last().unwrap();
+    assert_eq!(last["done"], true);
+    assert!(last.get("error").is_none());
+
+    client.stop().await.unwrap();
+    host.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn openai_compatible_backend_is_shared_with_peers() {
+    let llm = FakeLlm::start().await;
+    let host = TestNode::start(NodeOptions {
+        llm_url: Some(llm.url()),
+        backend: BackendKind::OpenAi,
+        ..Default::default()
+    })
+    .await;
+    let client = TestNode::start(NodeOptions { seeds: vec![host.tcp_port], ..Default::default() }).await;
+    client.wait_for_llm_access().await;
+
+    // The host told us which kind of server it runs, and listed its models over our session
+    let models = client.get_json("/api/models").await.unwrap();
+    let remote = models
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|model| model["host"] != "local")
+        .unwrap();
+    assert_eq!(remote["name"], FAKE_MODEL);
+    assert_eq!(remote["backend"], "openai");
+
+    let response = client
+        .post_json("/api/chat", json!({ "message": "Say hello", "sender": "tester" }))
+        .await;
+    assert!(response.status().is_success());
+    let message: Value = response.json().await.unwrap();
+    assert_eq!(message["content"], FAKE_REPLY.concat());
+    assert_eq!(llm.chat_requests(), 1);
+
+    // The server was asked for its token counts, and they are charged to the peer
+    assert_eq!(llm.last_chat().unwrap()["stream_options"], json!({ "include_usage": true }));
+    let limits = host.get_json("/api/limits").await.unwrap();
+    assert_eq!(limits[0]["tokens_today"], FAKE_REPLY.len());
+
+    client.stop().await.unwrap();
+    host.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn conversations_sync_between_peers() {
+    let llm = FakeLlm::start().await;
+    let host = TestNode::start(NodeOptions { llm_url: Some(llm.url()), ..Default::default() }).await;
+    let client = TestNode::start(NodeOptions { seeds: vec![host.tcp_port], ..Default::default() }).await;
+    client.wait_for_connection(&host).await;
+
+    let response = host
+        .post_json("/api/chat", json!({ "message": "Remember this", "sender": "host user" }))
+        .await;
+    assert!(response.status().is_success());
+
+    // Question and answer both arrive
+    let conversation = client.wait_for_peer_conversation(2).await;
+    assert_eq!(conversation["messages"][0]["content"], "Remember this");
+
+    client.stop().await.unwrap();
+    host.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn manual_approval_grants_access() {
+    let llm = FakeLlm::start().await;
+    let host = TestNode::start(NodeOptions {
+        llm_url: Some(llm.url()),
+        auto_approve: false,
+        configure: |config| config.http.admin_token = Some("operator-secret".to_string()),
+        ..Default::default()
+    })
+    .await;
+    let client = TestNode::start(NodeOptions { seeds: vec![host.tcp_port], ..Default::default() }).await;
+
+    let request = host.wait_for_access_request().await;
+    assert_eq!(request["node_id"], client.node.node_id());
+
+    // Neither other sites nor names rebound to this machine may approve it
+    let path = format!("/api/access/{}/approve", client.node.node_id());
+    let from_elsewhere = host.client.post(host.url(&path)).header("Origin", "http://example.com").send().await.unwrap();
+    assert!(from_elsewhere.status().is_client_error());
+    let rebound = host.client.post(host.url(&path)).header("Host", "example.com").send().await.unwrap();
+    assert_eq!(rebound.status(), 403);
+    let config = host.client.get(host.url("/api/config")).header("Origin", "http://example.com").send().await.unwrap();
+    assert_eq!(config.status(), 403);
+    // Other sites may list models but not read the node's own state
+    let models = host.client.get(host.url("/api/models")).header("Origin", "http://example.com").send().await.unwrap();
+    assert_eq!(models.headers()["access-control-allow-origin"], "http://example.com");
+    for path in ["/peers", "/api/usage", "/api/events"] {
+        let response = host.client.get(host.url(path)).header("Origin", "http://example.com").send().await.unwrap();
+        assert!(response.headers().get("access-control-allow-origin").is_none(), "{} is readable by other sites", path);
+    }
+    assert_eq!(host.get_json("/api/access").await.unwrap().as_array().unwrap().len(), 1);
+
+    // The operator's token works from anywhere
+    let response = host.client
+        .post(host.url(&path))
+        .header("Host", "example.com")
+        .bearer_auth("operator-secret")
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    client.wait_for_llm_access().await;
+    assert_eq!(host.get_json("/api/access").await.unwrap(), json!([]));
+
+    client.stop().await.unwrap();
+    host.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn stopping_a_node_says_goodbye() {
+    let mut nodes = start_mesh(2).await;
+    let leaving = nodes.pop().unwrap();
+    let staying = nodes.pop().unwrap();
+
+    let address = leaving.address();
+    leaving.stop().await.unwrap();
+    staying.wait_for_disconnection(&address).await;
+
+    staying.stop().await.unwrap();
+}
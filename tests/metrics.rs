@@ -0,0 +1,74 @@
+mod common;
+
+use common::{wait_for, FakeLlm, NodeOptions, TestNode, FAKE_REPLY};
+use serde_json::json;
+
+async fn scrape(node: &TestNode) -> String {
+    let response = node.client.get(node.url("/metrics")).send().await.unwrap();
+    assert!(response.status().is_success());
+    let content_type = response.headers()["content-type"].to_str().unwrap().to_string();
+    assert!(content_type.starts_with("text/plain"));
+    response.text().await.unwrap()
+}
+
+// Value of the sample called `name` whose labels include all of `labels`
+fn sample(metrics: &str, name: &str, labels: &[&str]) -> Option<f64> {
+    metrics
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter(|line| line.starts_with(&format!("{}{{", name)) || line.starts_with(&format!("{} ", name)))
+        .find(|line| labels.iter().all(|label| line.contains(label)))
+        .and_then(|line| line.rsplit(' ').next()?.parse().ok())
+}
+
+#[actix_web::test]
+async fn metrics_count_peers_messages_and_inference() {
+    let llm = FakeLlm::start().await;
+    let host = TestNode::start(NodeOptions { llm_url: Some(llm.url()), ..Default::default() }).await;
+    let client = TestNode::start(NodeOptions { seeds: vec![host.tcp_port], ..Default::default() }).await;
+    client.wait_for_connection(&host).await;
+    client.wait_for_llm_access().await;
+
+    let response = client
+        .post_json("/api/chat", json!({ "message": "Say hello", "sender": "tester" }))
+        .await;
+    assert!(response.status().is_success());
+
+    let metrics = scrape(&client).await;
+    assert_eq!(sample(&metrics, "neuromesh_peers", &[r#"state="connected""#]), Some(1.0));
+    let remote_chat = [r#"host="remote""#, r#"operation="chat""#];
+    assert_eq!(
+        sample(&metrics, "neuromesh_inference_requests_total", &[&remote_chat[..], &[r#"outcome="ok""#]].concat()),
+        Some(1.0),
+    );
+    assert_eq!(sample(&metrics, "neuromesh_inference_duration_seconds_count", &remote_chat), Some(1.0));
+    assert_eq!(
+        sample(&metrics, "neuromesh_tokens_generated_total", &[r#"host="remote""#]),
+        Some(FAKE_REPLY.len() as f64),
+    );
+    assert_eq!(
+        sample(&metrics, "neuromesh_access_decisions_total", &[r#"direction="received""#, r#"outcome="granted""#]),
+        Some(1.0),
+    );
+    assert!(sample(&metrics, "neuromesh_peer_bytes_total", &[r#"direction="sent""#, r#"type="hello""#]).unwrap() > 0.0);
+    assert!(sample(&metrics, "neuromesh_access_queue_depth", &[]).is_some());
+
+    // The host answered from its own server and handed out the access
+    let metrics = scrape(&host).await;
+    assert_eq!(
+        sample(&metrics, "neuromesh_access_decisions_total", &[r#"direction="sent""#, r#"outcome="granted""#]),
+        Some(1.0),
+    );
+    assert!(sample(&metrics, "neuromesh_peer_messages_total", &[r#"direction="received""#, r#"type="hello""#]).is_some());
+
+    // Conversations keep syncing after the chat
+    wait_for("a conversation to be synced", || async {
+        let metrics = scrape(&host).await;
+        sample(&metrics, "neuromesh_conversation_syncs_total", &[r#"direction="received""#]).filter(|n| *n > 0.0)
+    })
+    .await;
+
+    client.stop().await.unwrap();
+    host.stop().await.unwrap();
+    llm.stop().await;
+}
@@ -0,0 +1,131 @@
+mod common;
+
+use common::{fake_embedding, FakeLlm, NodeOptions, TestNode, FAKE_MODEL, FAKE_REPLY};
+use serde_json::{json, Value};
+
+// A node without an LLM of its own, connected to a host that runs the fake one
+async fn client_and_host(llm: &FakeLlm) -> (TestNode, TestNode) {
+    let host = TestNode::start(NodeOptions { llm_url: Some(llm.url()), ..Default::default() }).await;
+    let client = TestNode::start(NodeOptions { seeds: vec![host.tcp_port], ..Default::default() }).await;
+    client.wait_for_llm_access().await;
+    (client, host)
+}
+
+async fn stop_all(client: TestNode, host: TestNode, llm: FakeLlm) {
+    client.stop().await.unwrap();
+    host.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn chat_completion_is_served_by_peer_hosting_the_model() {
+    let llm = FakeLlm::start().await;
+    let (client, host) = client_and_host(&llm).await;
+
+    let response = client
+        .post_json("/v1/chat/completions", json!({
+            "model": FAKE_MODEL,
+            "messages": [
+                { "role": "system", "content": "Be brief." },
+                { "role": "user", "content": [{ "type": "text", "text": "Say hello" }] },
+            ],
+        }))
+        .await;
+    assert!(response.status().is_success());
+    let completion: Value = response.json().await.unwrap();
+    assert_eq!(completion["object"], "chat.completion");
+    assert_eq!(completion["model"], FAKE_MODEL);
+    assert_eq!(completion["choices"][0]["message"]["role"], "assistant");
+    assert_eq!(completion["choices"][0]["message"]["content"], FAKE_REPLY.concat());
+    assert_eq!(completion["choices"][0]["finish_reason"], "stop");
+    assert_eq!(llm.chat_requests(), 1);
+
+    stop_all(client, host, llm).await;
+}
+
+#[actix_web::test]
+async fn streamed_chat_completion_uses_server_sent_events() {
+    let llm = FakeLlm::start().await;
+    let (client, host) = client_and_host(&llm).await;
+
+    let response = client
+        .post_json("/v1/chat/completions", json!({
+            "model": FAKE_MODEL,
+            "messages": [{ "role": "user", "content": "Say hello" }],
+            "stream": true,
+        }))
+        .await;
+    assert!(response.status().is_success());
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+
+    let body = response.text().await.unwrap();
+    let events: Vec<&str> = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .collect();
+    assert_eq!(events.last(), Some(&"[DONE]"));
+
+    let chunks: Vec<Value> = events[..events.len() - 1]
+        .iter()
+        .map(|event| serde_json::from_str(event).unwrap())
+        .collect();
+    assert!(chunks.iter().all(|chunk| chunk["object"] == "chat.completion.chunk"));
+    let content: String = chunks
+        .iter()
+        .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str())
+        .collect();
+    assert_eq!(content, FAKE_REPLY.concat());
+    assert_eq!(chunks.last().unwrap()["choices"][0]["finish_reason"], "stop");
+
+    stop_all(client, host, llm).await;
+}
+
+#[actix_web::test]
+async fn models_and_embeddings_come_from_peers() {
+    let llm = FakeLlm::start().await;
+    let (client, host) = client_and_host(&llm).await;
+
+    let models = client.get_json("/v1/models").await.unwrap();
+    assert_eq!(models["object"], "list");
+    let ids: Vec<&str> = models["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|model| model["id"].as_str())
+        .collect();
+    assert_eq!(ids, vec![FAKE_MODEL]);
+
+    let inputs = ["first text", "second"];
+    let response = client
+        .post_json("/v1/embeddings", json!({ "model": FAKE_MODEL, "input": inputs }))
+        .await;
+    assert!(response.status().is_success());
+    let embeddings: Value = response.json().await.unwrap();
+    let data = embeddings["data"].as_array().unwrap();
+    assert_eq!(data.len(), inputs.len());
+    for (i, input) in inputs.iter().enumerate() {
+        assert_eq!(data[i]["index"], i);
+        assert_eq!(data[i]["embedding"], json!(fake_embedding(input)));
+    }
+
+    stop_all(client, host, llm).await;
+}
+
+#[actix_web::test]
+async fn unknown_model_is_reported_like_openai() {
+    let llm = FakeLlm::start().await;
+    let (client, host) = client_and_host(&llm).await;
+
+    let response = client
+        .post_json("/v1/chat/completions", json!({
+            "model": "no-such-model",
+            "messages": [{ "role": "user", "content": "Hello?" }],
+        }))
+        .await;
+    assert_eq!(response.status(), 404);
+    let error: Value = response.json().await.unwrap();
+    assert_eq!(error["error"]["code"], "model_not_found");
+    assert_eq!(llm.chat_requests(), 0);
+
+    stop_all(client, host, llm).await;
+}
@@ -0,0 +1,154 @@
+mod common;
+
+use common::{FakeLlm, NodeOptions, TestNode};
+use neuromesh::config::BackendKind;
+use serde_json::{json, Value};
+
+fn ask_with(options: Value) -> Value {
+    json!({ "message": "Say hello", "sender": "tester", "options": options })
+}
+
+#[actix_web::test]
+async fn options_reach_ollama_in_its_own_terms() {
+    let llm = FakeLlm::start().await;
+    let node = TestNode::start(NodeOptions { llm_url: Some(llm.url()), ..Default::default() }).await;
+
+    let response = node.post_json("/api/chat", ask_with(json!({
+        "temperature": 0.2,
+        "top_p": 0.9,
+        "num_ctx": 8192,
+        "seed": 7,
+        "stop": ["\n\n"],
+        "max_tokens": 100,
+        "keep_alive": "10m",
+        "format": "json",
+    })))
+    .await;
+    assert!(response.status().is_success());
+    let sent = llm.last_chat().unwrap();
+    assert_eq!(sent["options"], json!({
+        "temperature": 0.2,
+        "top_p": 0.9,
+        "num_ctx": 8192,
+        "seed": 7,
+        "stop": ["\n\n"],
+        "num_predict": 100,
+    }));
+    assert_eq!(sent["keep_alive"], "10m");
+    assert_eq!(sent["format"], "json");
+
+    // Without options the server's defaults apply
+    node.post_json("/api/chat", json!({ "message": "Say hello", "sender": "tester" })).await;
+    let sent = llm.last_chat().unwrap();
+    assert!(sent.get("options").is_none() && sent.get("keep_alive").is_none() && sent.get("format").is_none());
+
+    // Ollama wants a unit, so a bare number goes out as seconds
+    for (asked, sent) in [("-1", "-1s"), ("0", "0s"), ("600", "600s")] {
+        let mut ask = ask_with(json!({ "keep_alive": asked }));
+        ask["no_cache"] = json!(true);
+        let response = node.post_json("/api/chat", ask).await;
+        assert!(response.status().is_success(), "keep_alive {} failed", asked);
+        assert_eq!(llm.last_chat().unwrap()["keep_alive"], sent);
+    }
+
+    for invalid in [
+        json!({ "temperature": 3.0 }),
+        json!({ "top_p": -0.1 }),
+        json!({ "num_ctx": 0 }),
+        json!({ "stop": ["a", "b", "c", "d", "e"] }),
+        json!({ "keep_alive": "a while" }),
+    ] {
+        let response = node.post_json("/api/chat/stream", ask_with(invalid.clone())).await;
+        assert_eq!(response.status(), 400, "{} was accepted", invalid);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "Invalid generation options");
+    }
+    assert_eq!(llm.chat_requests(), 5);
+
+    node.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn openai_style_options_reach_an_openai_server() {
+    let llm = FakeLlm::start().await;
+    let node = TestNode::start(NodeOptions {
+        llm_url: Some(llm.url()),
+        backend: BackendKind::OpenAi,
+        ..Default::default()
+    })
+    .await;
+    let model = node.node.config().llm.model.clone();
+
+    let response = node.post_json("/v1/chat/completions", json!({
+        "model": model,
+        "messages": [{ "role": "user", "content": "Say hello" }],
+        "temperature": 0.5,
+        "seed": 3,
+        "stop": "END",
+        "max_completion_tokens": 50,
+        "response_format": { "type": "json_object" },
+        "num_ctx": 4096,
+    }))
+    .await;
+    assert!(response.status().is_success());
+    let sent = llm.last_chat().unwrap();
+    assert_eq!(sent["temperature"], 0.5);
+    assert_eq!(sent["seed"], 3);
+    assert_eq!(sent["stop"], json!(["END"]));
+    assert_eq!(sent["max_tokens"], 50);
+    assert_eq!(sent["response_format"], json!({ "type": "json_object" }));
+    // OpenAI servers have no context window setting
+    assert!(sent.get("num_ctx").is_none());
+
+    let refused = node.post_json("/v1/chat/completions", json!({
+        "model": model,
+        "messages": [{ "role": "user", "content": "Say hello" }],
+        "response_format": { "type": "json_schema" },
+    }))
+    .await;
+    assert_eq!(refused.status(), 400);
+    let body: Value = refused.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+    assert_eq!(llm.chat_requests(), 1);
+
+    node.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn a_host_holds_peer_options_to_its_limits() {
+    let llm = FakeLlm::start().await;
+    let host = TestNode::start(NodeOptions {
+        llm_url: Some(llm.url()),
+        configure: |config| {
+            config.limits.max_num_ctx = 2048;
+            config.limits.max_tokens = 64;
+            config.limits.max_keep_alive_secs = 600;
+        },
+        ..Default::default()
+    })
+    .await;
+    let client = TestNode::start(NodeOptions { seeds: vec![host.tcp_port], ..Default::default() }).await;
+    client.wait_for_llm_access().await;
+
+    let response = client.post_json("/api/chat", ask_with(json!({ "temperature": 0.7, "num_ctx": 8192, "keep_alive": "1h" }))).await;
+    assert!(response.status().is_success());
+    let sent = llm.last_chat().unwrap();
+    assert_eq!(sent["options"], json!({ "temperature": 0.7, "num_ctx": 2048, "num_predict": 64 }));
+    assert_eq!(sent["keep_alive"], "600s");
+
+    // Nor may a peer keep the model loaded for good
+    client.post_json("/api/chat", ask_with(json!({ "keep_alive": "-1" }))).await;
+    assert_eq!(llm.last_chat().unwrap()["keep_alive"], "600s");
+
+    // Within the limits, the peer's choice stands
+    client.post_json("/api/chat", ask_with(json!({ "num_ctx": 1024, "max_tokens": 10, "keep_alive": "5m" }))).await;
+    let sent = llm.last_chat().unwrap();
+    assert_eq!(sent["options"], json!({ "num_ctx": 1024, "num_predict": 10 }));
+    assert_eq!(sent["keep_alive"], "5m");
+
+    client.stop().await.unwrap();
+    host.stop().await.unwrap();
+    llm.stop().await;
+}
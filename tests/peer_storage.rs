@@ -0,0 +1,63 @@
+mod common;
+
+use common::{wait_for, NodeOptions, TestNode};
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+// One wire frame: marker, little-endian payload length, payload
+fn frame(marker: &[u8; 5], payload: &[u8]) -> Vec<u8> {
+    let mut bytes = marker.to_vec();
+    bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+fn conversation(hostname: &str) -> String {
+    json!({
+        "id": "local",
+        "messages": [],
+        "host_info": { "hostname": hostname, "ip_address": "127.0.0.1", "is_llm_host": false },
+    })
+    .to_string()
+}
+
+fn conversation_file(name: &str, content: &str) -> Vec<u8> {
+    frame(b"FILE:", format!("{}|{}", name, content).as_bytes())
+}
+
+// A peer that speaks the protocol by hand and sends whatever it likes
+#[actix_web::test]
+async fn hostile_peer_writes_stay_in_its_directory_and_quota() {
+    let node = TestNode::start(NodeOptions {
+        configure: |config| config.storage.peer_quota_mb = 1,
+        ..Default::default()
+    })
+    .await;
+    let received_dir = node.node.config().storage.received_dir.clone();
+
+    let mut peer = TcpStream::connect(node.address()).await.unwrap();
+    peer.write_all(&frame(b"HELO:", b"hostile-peer|1")).await.unwrap();
+    peer.write_all(&conversation_file("../../escape.json", &conversation("escaped"))).await.unwrap();
+    peer.write_all(&conversation_file("local.json", "{ not json")).await.unwrap();
+    let oversized = "x".repeat(2 * 1024 * 1024);
+    peer.write_all(&conversation_file("local.json", &conversation(&oversized))).await.unwrap();
+    peer.write_all(&conversation_file("local.json", &conversation("accepted"))).await.unwrap();
+
+    // Messages are handled in order, so once the last one is in, the others were refused
+    wait_for("the well-formed conversation to be kept", || async {
+        let peers = node.get_json("/peers").await?;
+        (peers["127.0.0.1"]["host_info"]["hostname"] == "accepted").then_some(())
+    })
+    .await;
+    assert!(!received_dir.parent().unwrap().join("escape.json").exists());
+    assert!(!received_dir.join("escape.json").exists());
+    let entries: Vec<_> = std::fs::read_dir(received_dir.join("127.0.0.1"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(entries, vec!["local.json"]);
+
+    drop(peer);
+    node.stop().await.unwrap();
+}
@@ -0,0 +1,146 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{free_port, wait_for, NodeOptions, TestNode, WAIT_TIMEOUT};
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+// One wire frame: marker, little-endian payload length, payload
+fn frame(marker: &[u8; 5], payload: &[u8]) -> Vec<u8> {
+    let mut bytes = marker.to_vec();
+    bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+async fn hellos_sent(node: &TestNode) -> u64 {
+    let metrics = node.client.get(node.url("/metrics")).send().await.unwrap().text().await.unwrap();
+    metrics
+        .lines()
+        .find(|line| line.starts_with("neuromesh_peer_messages_total") && line.contains(r#"direction="sent""#) && line.contains(r#"type="hello""#))
+        .and_then(|line| line.rsplit(' ').next()?.parse().ok())
+        .unwrap_or(0)
+}
+
+// Our own peers that have a session, as /api/nodes shows them
+async fn connected(node: &TestNode) -> Vec<Value> {
+    let nodes = node.get_json("/api/nodes").await.unwrap();
+    nodes.as_array().unwrap().iter().filter(|view| view["state"] == "connected").cloned().collect()
+}
+
+// Holds a dial to each of two ports until both have come in, then passes them on to
+// `targets` together. Later dials are refused, so each node gets exactly one through.
+async fn dial_gate(listeners: [TcpListener; 2], targets: [u16; 2]) {
+    let [first, second] = listeners;
+    let (first, second) = tokio::join!(first.accept(), second.accept());
+    let dials = [first.unwrap().0, second.unwrap().0];
+    for (mut dial, target) in dials.into_iter().zip(targets) {
+        let mut upstream = TcpStream::connect(("127.0.0.1", target)).await.unwrap();
+        tokio::spawn(async move { tokio::io::copy_bidirectional(&mut dial, &mut upstream).await });
+    }
+}
+
+#[actix_web::test]
+async fn dialing_each_other_at_once_leaves_one_session() {
+    let (port_a, port_b) = (free_port(), free_port());
+    let to_a = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let to_b = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (seed_a, seed_b) = (to_a.local_addr().unwrap().port(), to_b.local_addr().unwrap().port());
+    let gate = tokio::spawn(dial_gate([to_a, to_b], [port_a, port_b]));
+
+    let a = TestNode::start(NodeOptions { tcp_port: Some(port_a), seeds: vec![seed_b], ..Default::default() }).await;
+    let b = TestNode::start(NodeOptions { tcp_port: Some(port_b), seeds: vec![seed_a], ..Default::default() }).await;
+    tokio::time::timeout(WAIT_TIMEOUT, gate).await.expect("the nodes did not both dial").unwrap();
+    // Both connections get as far as the handshake before the tie-break closes one
+    wait_for("both handshakes", || async {
+        (hellos_sent(&a).await == 2 && hellos_sent(&b).await == 2).then_some(())
+    })
+    .await;
+    a.wait_for_connection(&b).await;
+    b.wait_for_connection(&a).await;
+
+    let (on_a, on_b) = (connected(&a).await, connected(&b).await);
+    assert_eq!(on_a.len(), 1, "{:?}", on_a);
+    assert_eq!(on_b.len(), 1, "{:?}", on_b);
+    assert_eq!(on_a[0]["node_id"], b.node.node_id());
+    assert_eq!(on_b[0]["node_id"], a.node.node_id());
+    // Both kept the connection the node with the lower ID opened
+    let (lower, higher) = if a.node.node_id() < b.node.node_id() { (&on_a, &on_b) } else { (&on_b, &on_a) };
+    assert_eq!(lower[0]["direction"], "outbound");
+    assert_eq!(higher[0]["direction"], "inbound");
+
+    a.stop().await.unwrap();
+    b.stop().await.unwrap();
+}
+
+#[actix_web::test]
+async fn connected_peers_show_their_heartbeat_rtt() {
+    let host = TestNode::start(NodeOptions::default()).await;
+    let client = TestNode::start(NodeOptions { seeds: vec![host.tcp_port], ..Default::default() }).await;
+
+    // The first ping goes out as soon as the session starts
+    let connection = wait_for("an RTT sample", || async {
+        let connections = client.get_json("/api/connections").await?;
+        connections.as_array()?.iter().find(|status| status["rtt_ms"].is_number()).cloned()
+    })
+    .await;
+    assert_eq!(connection["address"], host.address());
+    assert_eq!(connection["state"], "Connected");
+    let rtt = connection["rtt_ms"].as_f64().unwrap();
+    assert!((0.0..1000.0).contains(&rtt), "{}", connection);
+
+    client.stop().await.unwrap();
+    host.stop().await.unwrap();
+}
+
+#[actix_web::test]
+async fn a_peer_that_stops_answering_heartbeats_is_dropped() {
+    let node = TestNode::start(NodeOptions::default()).await;
+
+    let mut silent = TcpStream::connect(node.address()).await.unwrap();
+    silent.write_all(&frame(b"HELO:", b"silent-peer|1")).await.unwrap();
+    wait_for("the session", || async { (connected(&node).await.len() == 1).then_some(()) }).await;
+
+    // It reads the node's pings but never answers or sends anything else
+    let mut buffer = [0u8; 4096];
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while silent.read(&mut buffer).await.is_ok_and(|read| read > 0) {}
+    })
+    .await
+    .expect("the node kept talking to a silent peer");
+    wait_for("the session to close", || async { connected(&node).await.is_empty().then_some(()) }).await;
+
+    node.stop().await.unwrap();
+}
+
+#[actix_web::test]
+async fn a_reconnect_the_same_way_replaces_the_stale_session() {
+    let node = TestNode::start(NodeOptions::default()).await;
+
+    let mut stale = TcpStream::connect(node.address()).await.unwrap();
+    stale.write_all(&frame(b"HELO:", b"returning-peer|1")).await.unwrap();
+    wait_for("the first session", || async { (connected(&node).await.len() == 1).then_some(()) }).await;
+
+    let mut fresh = TcpStream::connect(node.address()).await.unwrap();
+    fresh.write_all(&frame(b"HELO:", b"returning-peer|1")).await.unwrap();
+
+    // The node hangs up on the old connection, past whatever it had already sent there
+    let mut buffer = [0u8; 4096];
+    tokio::time::timeout(WAIT_TIMEOUT, async {
+        while stale.read(&mut buffer).await.is_ok_and(|read| read > 0) {}
+    })
+    .await
+    .expect("the stale connection was kept open");
+
+    let sessions = connected(&node).await;
+    assert_eq!(sessions.len(), 1, "{:?}", sessions);
+    assert_eq!(sessions[0]["node_id"], "returning-peer");
+    assert_eq!(sessions[0]["direction"], "inbound");
+    // And keeps talking on the new one
+    assert!(tokio::time::timeout(Duration::from_secs(1), fresh.read(&mut buffer)).await.is_ok_and(|read| read.is_ok_and(|read| read > 0)));
+
+    drop(fresh);
+    node.stop().await.unwrap();
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use common::{wait_for, FakeLlm, NodeOptions, TestNode, FAKE_MODEL};
+use serde_json::Value;
+
+fn edge(a: &str, b: &str) -> Value {
+    let (source, target) = if a <= b { (a, b) } else { (b, a) };
+    serde_json::json!({ "source": source, "target": target })
+}
+
+#[actix_web::test]
+async fn nodes_and_topology_reach_past_direct_peers() {
+    // A chain: a - b - c, with the LLM on b
+    let llm = FakeLlm::start().await;
+    let b = TestNode::start(NodeOptions { llm_url: Some(llm.url()), ..Default::default() }).await;
+    let a = TestNode::start(NodeOptions { seeds: vec![b.tcp_port], ..Default::default() }).await;
+    let c = TestNode::start(NodeOptions { seeds: vec![b.tcp_port], ..Default::default() }).await;
+    let (a_id, b_id) = (a.node.node_id(), b.node.node_id());
+    let c_id = c.node.node_id().to_string();
+
+    // a only hears about c through b's announcements
+    let topology = wait_for("a to see the b - c session", || async {
+        let topology = a.get_json("/api/topology").await?;
+        topology["edges"].as_array()?.contains(&edge(b_id, &c_id)).then_some(topology)
+    })
+    .await;
+    assert!(topology["edges"].as_array().unwrap().contains(&edge(a_id, b_id)));
+    assert_eq!(topology["edges"].as_array().unwrap().len(), 2);
+    let local: Vec<&Value> = topology["nodes"].as_array().unwrap().iter().filter(|n| n["local"] == true).collect();
+    assert_eq!(local.len(), 1);
+    assert_eq!(local[0]["node_id"], a_id);
+
+    let nodes = wait_for("a to know b's models", || async {
+        let nodes = a.get_json("/api/nodes").await?;
+        let b_view = nodes.as_array()?.iter().find(|n| n["node_id"] == b_id)?;
+        (b_view["models"].as_array()?.contains(&Value::from(FAKE_MODEL)) && b_view["has_llm"] == true).then_some(nodes)
+    })
+    .await;
+    let nodes = nodes.as_array().unwrap();
+    assert_eq!(nodes.len(), 2);
+    let b_view = nodes.iter().find(|n| n["node_id"] == b_id).unwrap();
+    assert_eq!(b_view["state"], "connected");
+    assert_eq!(b_view["hops"], 1);
+    assert!(b_view["direction"] == "outbound" || b_view["direction"] == "inbound");
+    assert_eq!(b_view["addresses"][0], b.address());
+    assert!(b_view["last_seen"].is_string());
+    assert_eq!(b_view["load"], 0);
+    let c_view = nodes.iter().find(|n| n["node_id"] == c_id).unwrap();
+    assert_eq!(c_view["state"], "indirect");
+    assert_eq!(c_view["hops"], 2);
+    assert_eq!(c_view["has_llm"], false);
+
+    let status = b.get_json("/api/status").await.unwrap();
+    assert_eq!(status["node_id"], b_id);
+    assert_eq!(status["tcp_port"], b.tcp_port);
+    assert_eq!(status["backend"]["healthy"], true);
+    assert_eq!(status["models"][0], FAKE_MODEL);
+    assert_eq!(status["peers"]["connected"], 2);
+    let status = a.get_json("/api/status").await.unwrap();
+    assert_eq!(status["backend"]["healthy"], false);
+    assert_eq!(status["peers"]["connected"], 1);
+
+    // Once c leaves, b stops announcing it and a forgets the edge
+    c.stop().await.unwrap();
+    wait_for("a to drop the b - c session", || async {
+        let topology = a.get_json("/api/topology").await?;
+        (!topology["edges"].as_array()?.contains(&edge(b_id, &c_id))).then_some(())
+    })
+    .await;
+
+    a.stop().await.unwrap();
+    b.stop().await.unwrap();
+    llm.stop().await;
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use common::{FakeLlm, NodeOptions, TestNode, FAKE_EVAL_DURATION_MS, FAKE_REPLY};
+use serde_json::{json, Value};
+
+async fn ask(node: &TestNode) {
+    let response = node.post_json("/api/chat", json!({ "message": "Say hello", "sender": "tester" })).await;
+    assert!(response.status().is_success());
+}
+
+#[actix_web::test]
+async fn both_sides_record_a_remote_chat() {
+    let llm = FakeLlm::start().await;
+    let host = TestNode::start(NodeOptions { llm_url: Some(llm.url()), ..Default::default() }).await;
+    let client = TestNode::start(NodeOptions { seeds: vec![host.tcp_port], ..Default::default() }).await;
+    client.wait_for_llm_access().await;
+    ask(&client).await;
+    ask(&client).await;
+
+    let served = host.get_json("/api/usage?group_by=peer,direction").await.unwrap();
+    assert_eq!(served, json!([{
+        "peer": client.node.node_id(),
+        "direction": "served",
+        "requests": 2,
+        "failed": 0,
+        "prompt_tokens": 10,
+        "completion_tokens": 2 * FAKE_REPLY.len(),
+        "duration_ms": served[0]["duration_ms"],
+        "server_eval_ms": 2 * FAKE_EVAL_DURATION_MS,
+    }]));
+
+    let consumed = client.get_json("/api/usage?direction=consumed").await.unwrap();
+    assert_eq!(consumed.as_array().unwrap().len(), 1);
+    assert_eq!(consumed[0]["peer"], host.node.node_id());
+    assert_eq!(consumed[0]["requests"], 2);
+    assert_eq!(consumed[0]["completion_tokens"], 2 * FAKE_REPLY.len());
+    assert!(client.get_json("/api/usage?direction=served").await.unwrap().as_array().unwrap().is_empty());
+
+    // The ledger itself names both parties
+    let entries = host.get_json("/api/usage/ledger?limit=1").await.unwrap();
+    let entries = entries.as_array().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["requester"], client.node.node_id());
+    assert_eq!(entries[0]["host"], host.node.node_id());
+    assert_eq!(entries[0]["operation"], "chat");
+    assert_eq!(entries[0]["outcome"], "ok");
+
+    client.stop().await.unwrap();
+    host.stop().await.unwrap();
+    llm.stop().await;
+}
+
+#[actix_web::test]
+async fn usage_groups_by_model_and_day_and_survives_a_restart() {
+    let llm = FakeLlm::start().await;
+    let node = TestNode::start(NodeOptions { llm_url: Some(llm.url()), ..Default::default() }).await;
+    ask(&node).await;
+
+    let node = node.restart().await;
+    let rows: Value = node.get_json("/api/usage?group_by=model,day").await.unwrap();
+    let rows = rows.as_array().unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["model"], node.node.config().llm.model);
+    assert_eq!(rows[0]["day"], chrono::Utc::now().date_naive().to_string());
+    assert_eq!(rows[0]["requests"], 1);
+    assert!(rows[0].get("peer").is_none());
+
+    let by_peer = node.get_json("/api/usage").await.unwrap();
+    assert_eq!(by_peer[0]["peer"], "local");
+
+    let refused = node.client.get(node.url("/api/usage?group_by=colour")).send().await.unwrap();
+    assert_eq!(refused.status(), 400);
+
+    node.stop().await.unwrap();
+    llm.stop().await;
+}
@@ -1,6 +1,6 @@
 import React, { useState, useRef, useEffect } from 'react';
 import { Send, Bot, Sparkles, Copy, RotateCcw } from 'lucide-react';
-import { sendMessageToLLM, getPeerConversations } from './api/llm';
+import { sendMessageToLLM, getPeerConversations, subscribeToEvents } from './api/llm';
 import { PeersConversation } from './PeersConversation';
 import { ParticleBackground } from './components/ParticleBackground';
 import { Sidebar } from './components/Sidebar';
@@ -282,7 +282,7 @@ function App() {
   const [isConnected, setIsConnected] = useState(true);
   const [peerCount, setPeerCount] = useState(0);
 
-  // Check peer count periodically
+  // Check peer count whenever peers come and go
   useEffect(() => {
     const checkPeers = async () => {
       try {
@@ -296,8 +296,7 @@ function App() {
     };
 
     checkPeers();
-    const interval = setInterval(checkPeers, 10000);
-    return () => clearInterval(interval);
+    return subscribeToEvents(['peer_connected', 'peer_lost', 'sync_completed'], checkPeers);
   }, []);
 
   const clearConversation = () => {
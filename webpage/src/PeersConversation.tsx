@@ -1,5 +1,5 @@
 import React, { useEffect, useState } from 'react';
-import { getPeerConversations, Conversation } from './api/llm';
+import { getPeerConversations, subscribeToEvents, Conversation } from './api/llm';
 import { Bot, Users, Clock, Wifi, WifiOff, User, RefreshCw } from 'lucide-react';
 import { NetworkVisualization } from './components/NetworkVisualization';
 
@@ -49,15 +49,12 @@ export function PeersConversation() {
   useEffect(() => {
     fetchPeerConversations(true); // Initial load
 
-    // Refresh every 10 seconds (reduced frequency)
-    const interval = setInterval(() => {
-      // Only refresh if not currently viewing a conversation and tab is visible
-      if (!document.hidden) {
+    // Refresh when a peer's conversation arrives instead of polling
+    return subscribeToEvents(['sync_completed'], (event) => {
+      if (event.direction === 'received') {
         fetchPeerConversations(false); // Background refresh
       }
-    }, 10000);
-
-    return () => clearInterval(interval);
+    });
   }, []); // Empty dependency array - only run once on mount
 
   const peerIps = Object.keys(peerConversations);
@@ -11,6 +11,18 @@ export interface Message {
 export interface ChatRequest {
   message: string;
   sender: string;
+  options?: {
+    temperature?: number;
+    top_p?: number;
+    num_ctx?: number;
+    seed?: number;
+    stop?: string[];
+    max_tokens?: number;
+    keep_alive?: string;
+    format?: 'json';
+  };
+  // Skip a cached answer
+  no_cache?: boolean;
 }
 
 export interface Conversation {
@@ -33,16 +45,25 @@ export interface Conversation {
   };
 }
 
+// The node cannot tell when the page goes away, so the request is named up front and
+// cancelled on the way out; otherwise the model keeps generating for nobody
 export async function sendMessageToLLM(message: string): Promise<string> {
+  const requestId = `ui-${crypto.randomUUID()}`;
+  const cancel = () => navigator.sendBeacon(`${API_ENDPOINT}/requests/${requestId}/cancel`);
+  window.addEventListener('pagehide', cancel);
   try {
     const response = await axios.post<{ content: string }>(`${API_ENDPOINT}/chat`, {
       message,
       sender: 'user',
+    }, {
+      headers: { 'X-Request-Id': requestId },
     });
     return response.data.content;
   } catch (error) {
     console.error('Error sending message to LLM:', error);
     throw new Error('Failed to get response from LLM');
+  } finally {
+    window.removeEventListener('pagehide', cancel);
   }
 }
 
@@ -84,4 +105,34 @@ export async function getPeerConversations(): Promise<Record<string, Conversatio
     }
     throw new Error('Failed to get peer conversations');
   }
-}
\ No newline at end of file
+}
+
+export type MeshEventType =
+  | 'peer_discovered'
+  | 'peer_connected'
+  | 'peer_lost'
+  | 'capability_changed'
+  | 'access_requested'
+  | 'access_decided'
+  | 'message_added'
+  | 'sync_completed'
+  | 'inference_started'
+  | 'inference_finished'
+  | 'inference_failover';
+
+export interface MeshEvent {
+  type: MeshEventType;
+  seq: number;
+  at: string;
+  [field: string]: unknown;
+}
+
+// Pushes node events as they happen; returns a function that stops listening.
+// The browser reconnects on its own if the node restarts.
+export function subscribeToEvents(types: MeshEventType[], onEvent: (event: MeshEvent) => void): () => void {
+  const source = new EventSource(`${API_ENDPOINT}/events?types=${types.join(',')}`);
+  for (const type of types) {
+    source.addEventListener(type, (message) => onEvent(JSON.parse((message as MessageEvent).data)));
+  }
+  return () => source.close();
+}